@@ -0,0 +1,119 @@
+//! Synchronous wrappers around [`DaemonClient`], for callers without a tokio
+//! runtime. Each call connects, sends one command and waits for the answer
+//! on a runtime of its own, so they must not be used from async code (tokio
+//! panics when a runtime is started inside another).
+
+use crate::cli::CliOutputMode;
+use crate::daemon::DaemonClient;
+use crate::protocol::DaemonStatus;
+use anyhow::Result;
+use std::path::Path;
+use tokio::runtime::Runtime;
+
+/// Runs `request` against a fresh connection to the daemon at `socket_path`.
+fn with_client<T, F>(socket_path: &Path, request: F) -> Result<T>
+where
+    F: AsyncFnOnce(&mut DaemonClient) -> Result<T>,
+{
+    Runtime::new()?.block_on(async {
+        let mut client = DaemonClient::connect(socket_path).await?;
+        request(&mut client).await
+    })
+}
+
+/// See [`DaemonClient::status`].
+pub fn status(socket_path: &Path) -> Result<DaemonStatus> {
+    with_client(socket_path, async |client| client.status().await)
+}
+
+/// See [`DaemonClient::start`].
+pub fn start(socket_path: &Path, output_modes: Vec<CliOutputMode>) -> Result<()> {
+    with_client(socket_path, async |client| client.start(output_modes).await)
+}
+
+/// See [`DaemonClient::stop`].
+pub fn stop(socket_path: &Path) -> Result<()> {
+    with_client(socket_path, async |client| client.stop().await)
+}
+
+/// See [`DaemonClient::toggle`].
+pub fn toggle(socket_path: &Path, output_modes: Vec<CliOutputMode>) -> Result<()> {
+    with_client(socket_path, async |client| {
+        client.toggle(output_modes).await
+    })
+}
+
+/// See [`DaemonClient::shutdown`].
+pub fn shutdown(socket_path: &Path) -> Result<()> {
+    with_client(socket_path, async |client| client.shutdown().await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::DaemonReportedError;
+    use crate::protocol::DaemonState;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    /// Serves one connection per reply: reads a command, answers it with the
+    /// reply and hangs up. Returns the commands received.
+    fn serve(socket_path: &Path, replies: &[&'static str]) -> thread::JoinHandle<Vec<String>> {
+        let listener = UnixListener::bind(socket_path).unwrap();
+        let replies = replies.to_vec();
+        thread::spawn(move || {
+            let mut received = Vec::new();
+            for reply in replies {
+                let (socket, _) = listener.accept().unwrap();
+                let mut line = String::new();
+                BufReader::new(&socket).read_line(&mut line).unwrap();
+                received.push(line);
+                writeln!(&socket, "{}", reply).unwrap();
+            }
+            received
+        })
+    }
+
+    #[test]
+    fn test_blocking_client() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = serve(
+            &socket_path,
+            &[
+                r#"{"response_type":"status","status":{"state":"idle","last_error":null}}"#,
+                r#"{"response_type":"ack"}"#,
+                r#"{"response_type":"ack"}"#,
+                r#"{"response_type":"error","message":"Busy"}"#,
+            ],
+        );
+
+        assert_eq!(status(&socket_path).unwrap().state, DaemonState::Idle);
+        start(&socket_path, vec![CliOutputMode::Clipboard]).unwrap();
+        stop(&socket_path).unwrap();
+        let err = toggle(&socket_path, vec![]).unwrap_err();
+        assert!(err.is::<DaemonReportedError>());
+
+        let received = daemon.join().unwrap();
+        let commands: Vec<serde_json::Value> = received
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .map(|command: serde_json::Value| command["command"].clone())
+            .collect();
+        assert_eq!(commands, ["status", "start", "stop", "toggle"]);
+        assert!(received[1].contains(r#""output_modes":["clipboard"]"#));
+    }
+
+    #[test]
+    fn test_blocking_client_daemon_down() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = status(&dir.path().join("missing.sock")).unwrap_err();
+        assert_eq!(
+            err.root_cause()
+                .downcast_ref::<std::io::Error>()
+                .map(|io_err| io_err.kind()),
+            Some(std::io::ErrorKind::NotFound)
+        );
+    }
+}
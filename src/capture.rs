@@ -0,0 +1,188 @@
+//! Recording of raw wire traffic for `--capture`, in a JSON lines format
+//! that can be loaded back and replayed against the client, and dumping it
+//! to stderr for `--raw`.
+
+use anyhow::{Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What a captured entry records.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// A new connection to the daemon; `line` is the socket path
+    Connect,
+    /// A line written to the daemon
+    Sent,
+    /// A line read from the daemon
+    Received,
+}
+
+/// One line of a capture file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CaptureEntry {
+    /// Milliseconds since the Unix epoch
+    pub ts_ms: u64,
+    pub direction: Direction,
+    /// The raw line, without its trailing newline
+    pub line: String,
+}
+
+/// Writes capture entries to a file as they happen, so a session that
+/// crashes or hangs is still recorded up to that point.
+pub struct Capture {
+    writer: Box<dyn Write + Send>,
+}
+
+impl Capture {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create capture file {:?}", path))?;
+        Ok(Self::new(Box::new(file)))
+    }
+
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self { writer }
+    }
+
+    fn record(&mut self, direction: Direction, line: &str) {
+        let entry = CaptureEntry {
+            ts_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_millis() as u64)
+                .unwrap_or_default(),
+            direction,
+            line: line.to_string(),
+        };
+        let written = serde_json::to_string(&entry)
+            .map_err(Into::into)
+            .and_then(|json| writeln!(self.writer, "{}", json))
+            .and_then(|()| self.writer.flush());
+        if let Err(e) = written {
+            warn!("Failed to write capture entry: {}", e);
+        }
+    }
+}
+
+tokio::task_local! {
+    static CAPTURE: RefCell<Capture>;
+}
+
+/// Runs `f`, recording all wire traffic it causes to `capture`. Traffic from
+/// other tasks is not recorded.
+pub async fn with_capture<F: Future>(capture: Capture, f: F) -> F::Output {
+    CAPTURE.scope(RefCell::new(capture), f).await
+}
+
+/// Records a line if the current task is capturing, and with `raw` prints
+/// it to stderr verbatim, prefixed with `>>` if sent or `<<` if received.
+pub(crate) fn record(direction: Direction, line: &str, raw: bool) {
+    if raw {
+        match direction {
+            Direction::Sent => eprintln!(">> {}", line),
+            Direction::Received => eprintln!("<< {}", line),
+            Direction::Connect => {}
+        }
+    }
+    let _ = CAPTURE.try_with(|capture| capture.borrow_mut().record(direction, line));
+}
+
+/// Reads back a file written by [`Capture`].
+pub fn load_capture(path: &Path) -> Result<Vec<CaptureEntry>> {
+    let file =
+        File::open(path).with_context(|| format!("Failed to open capture file {:?}", path))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+        .map(|(i, line)| {
+            let line = line.context("Failed to read capture file")?;
+            serde_json::from_str(&line)
+                .with_context(|| format!("Invalid capture entry on line {}", i + 1))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::{
+        DEFAULT_READ_TIMEOUT, ResponseStream, connect_to_daemon, send_command, send_command_only,
+    };
+    use crate::protocol::DaemonCommand;
+    use crate::testutil::{MockDaemon, ReplayDaemon};
+    use futures::StreamExt;
+
+    // A short session touching both the request/response and streaming paths,
+    // rendered as the client saw it
+    async fn session(socket_path: &Path) -> Vec<String> {
+        let mut rendered = Vec::new();
+        let mut stream = connect_to_daemon(socket_path).await.unwrap();
+        for command in [DaemonCommand::Status, DaemonCommand::Stop] {
+            let response = send_command(&mut stream, &command, DEFAULT_READ_TIMEOUT).await;
+            rendered.push(format!("{:?}", response.unwrap()));
+        }
+
+        let mut stream = connect_to_daemon(socket_path).await.unwrap();
+        send_command_only(&mut stream, &DaemonCommand::Subscribe)
+            .await
+            .unwrap();
+        let mut events = ResponseStream::new(stream);
+        while let Some(event) = events.next().await {
+            rendered.push(format!("{:?}", event.unwrap()));
+        }
+        rendered
+    }
+
+    #[tokio::test]
+    async fn test_capture_replay_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let capture_path = dir.path().join("session.jsonl");
+
+        let daemon = MockDaemon::spawn(&dir.path().join("mock.sock")).unwrap();
+        let capture = Capture::create(&capture_path).unwrap();
+        let live = with_capture(capture, session(daemon.socket_path())).await;
+
+        let entries = load_capture(&capture_path).unwrap();
+        let directions: Vec<_> = entries.iter().map(|entry| entry.direction).collect();
+        assert_eq!(
+            directions,
+            [
+                Direction::Connect,
+                Direction::Sent,
+                Direction::Received,
+                Direction::Sent,
+                Direction::Received,
+                Direction::Connect,
+                Direction::Sent,
+                Direction::Received,
+            ]
+        );
+        assert!(entries[1].line.starts_with(r#"{"command":"status","id":"#));
+
+        let replay = ReplayDaemon::spawn(&dir.path().join("replay.sock"), entries).unwrap();
+        let replayed = session(replay.socket_path()).await;
+
+        assert_eq!(replayed, live);
+    }
+
+    #[test]
+    fn test_load_capture_reports_bad_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        std::fs::write(
+            &path,
+            "{\"ts_ms\":1,\"direction\":\"sent\",\"line\":\"{}\"}\n\nnot json\n",
+        )
+        .unwrap();
+
+        let err = load_capture(&path).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid capture entry on line 3");
+    }
+}
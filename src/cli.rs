@@ -1,11 +1,59 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use crate::daemon::{DEFAULT_CONNECT_RETRY_DELAY, DEFAULT_MAX_LINE_LEN, DEFAULT_READ_TIMEOUT};
+use clap::error::ErrorKind;
+use clap::{ArgAction, ArgGroup, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
 use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::time::Duration;
 
-#[derive(ValueEnum, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(ValueEnum, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum CliOutputMode {
+    #[default]
     Keyboard,
     Clipboard,
+    /// The X11 primary selection (middle-click paste). Not available on
+    /// Wayland compositors without primary selection support.
+    #[value(name = "primary")]
+    #[serde(rename = "primary")]
+    PrimarySelection,
+    /// Write to the file given with `--output-path`
+    File,
+}
+
+/// A `--state-alias` of the form `from=to`.
+fn parse_state_alias(alias: &str) -> Result<(String, String), String> {
+    match alias.split_once('=') {
+        Some((from, to)) if !from.is_empty() && !to.is_empty() => {
+            Ok((from.to_string(), to.to_string()))
+        }
+        _ => Err("expected FROM=TO, e.g. running=listening".to_string()),
+    }
+}
+
+/// Session ids are short tokens of ASCII letters, digits, `-` and `_`.
+fn parse_session_id(id: &str) -> Result<String, String> {
+    if id.is_empty() || id.len() > 64 {
+        return Err("session id must be between 1 and 64 characters".to_string());
+    }
+    if !id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err("session id may only contain letters, digits, '-' and '_'".to_string());
+    }
+    Ok(id.to_string())
+}
+
+/// Profile names end up in a variable name, so they are limited to ASCII
+/// letters, digits and `_`.
+fn parse_profile(name: &str) -> Result<String, String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err("profile may only contain letters, digits and '_'".to_string());
+    }
+    Ok(name.to_string())
 }
 
 #[derive(Parser, Debug)]
@@ -14,113 +62,1524 @@ pub enum CliOutputMode {
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+
+    /// Print each command sent to the daemon (as JSON) to stderr
+    #[arg(long, global = true)]
+    pub echo_command: bool,
+
+    /// Validate the arguments and print the parsed command without contacting the daemon
+    #[arg(long, global = true)]
+    pub parse_only: bool,
+
+    /// Log the first N bytes of each raw response at debug level, before parsing
+    #[arg(long, global = true, value_name = "N")]
+    pub response_preview: Option<usize>,
+
+    /// Reject response lines longer than this many bytes
+    #[arg(long, global = true, value_name = "BYTES", default_value_t = DEFAULT_MAX_LINE_LEN)]
+    pub max_line_length: NonZeroUsize,
+
+    /// How messages are delimited on the socket: newlines, or a 4-byte big-endian length prefix
+    #[arg(long, global = true, value_enum, default_value = "line")]
+    pub framing: Framing,
+
+    /// Don't create the runtime directory or fall back to /tmp when it is unusable
+    #[arg(long, global = true)]
+    pub no_create_runtime_dir: bool,
+
+    /// Seconds to wait for each response from the daemon (0 waits indefinitely) [default: 5]
+    #[arg(long, global = true, value_name = "SECONDS")]
+    pub timeout: Option<u64>,
+
+    /// Resend idempotent commands (e.g. status) up to N times if the daemon doesn't answer in time
+    #[arg(long, global = true, value_name = "N", default_value_t = 0)]
+    pub timeout_retries: u32,
+
+    /// Retry connecting up to N times while the daemon socket is missing or refusing connections
+    #[arg(long, global = true, value_name = "N", default_value_t = 0)]
+    pub connect_retries: u32,
+
+    /// Milliseconds to wait before the first connection retry; doubles after each retry
+    #[arg(long, global = true, value_name = "MS", default_value_t = DEFAULT_CONNECT_RETRY_DELAY.as_millis() as u64)]
+    pub connect_retry_delay: u64,
+
+    /// Give up connecting to the daemon after MS milliseconds, retries included [default: no limit]
+    #[arg(long, global = true, value_name = "MS")]
+    pub connect_timeout: Option<u64>,
+
+    /// Give up on the whole command (connecting, sending and every read) after MS
+    /// milliseconds [default: no limit]
+    #[arg(long, global = true, value_name = "MS")]
+    pub deadline: Option<u64>,
+
+    /// After the command is acknowledged, block until the daemon reaches STATE
+    #[arg(long, global = true, value_name = "STATE")]
+    pub then_wait: Option<String>,
+
+    /// Remove ANSI escape sequences and control characters from text sent by the daemon
+    #[arg(long, global = true)]
+    pub strip_ansi: bool,
+
+    /// Bare text only: no colors in logs, and text from the daemon as with --strip-ansi
+    #[arg(long, global = true, visible_alias = "no-color")]
+    pub plain: bool,
+
+    /// Take the default output for start and toggle from HANDSFREE_OUTPUT_<NAME>, ahead of HANDSFREE_OUTPUT
+    #[arg(long, global = true, value_name = "NAME", value_parser = parse_profile)]
+    pub profile: Option<String>,
+
+    /// Send ID with every command so daemon logs can be correlated with the caller's traces
+    #[arg(long, global = true, value_name = "ID")]
+    pub trace_id: Option<String>,
+
+    /// Print results as JSON: one object per command, or one line per event for watch
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    /// Indent --json output for reading (not for watch or batch, which print one object per line)
+    #[arg(long, global = true)]
+    pub pretty: bool,
+
+    /// Log more: -v for info, -vv for debug, -vvv for trace. RUST_LOG takes precedence
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// How to write log records to stderr
+    #[arg(long, global = true, value_enum, default_value = "text")]
+    pub log_format: LogFormat,
+
+    /// Print nothing on success; errors are still reported. --json output is kept
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Report failures on stderr as a JSON object instead of an error chain
+    #[arg(long, global = true)]
+    pub json_errors: bool,
+
+    /// Maximum time to wait for a state, in seconds [default: 30]
+    #[arg(long, global = true, value_name = "SECS")]
+    pub wait_timeout: Option<u64>,
+
+    /// Check the daemon's status first and refuse to send the command unless it is running
+    #[arg(long, global = true)]
+    pub require_running: bool,
+
+    /// Report the daemon state FROM as TO (repeatable), e.g. `running=listening`
+    #[arg(long, global = true, value_name = "FROM=TO", value_parser = parse_state_alias)]
+    pub state_alias: Vec<(String, String)>,
+
+    /// Record every line sent to and received from the daemon to FILE, for bug reports
+    #[arg(long, global = true, value_name = "FILE")]
+    pub capture: Option<PathBuf>,
+
+    /// Print every line sent to (`>>`) and received from (`<<`) the daemon to stderr
+    #[arg(long, global = true)]
+    pub raw: bool,
+
+    /// Connect to the daemon socket at PATH instead of the default location, or to the
+    /// abstract socket NAME given as `@NAME` (Linux only)
+    #[arg(long, global = true, value_name = "PATH")]
+    pub socket: Option<PathBuf>,
+
+    /// Read client defaults from PATH instead of ~/.config/handsfree/handsfreectl.toml
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config_file: Option<PathBuf>,
+
+    /// Set environment variables from KEY=VALUE lines in PATH (e.g. XDG_RUNTIME_DIR) before anything reads them
+    #[arg(long, global = true, value_name = "PATH")]
+    pub env_file: Option<PathBuf>,
+}
+
+/// How `status` prints the daemon's status
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum StatusFormat {
+    /// The state, then the last error on its own line if there is one
+    Plain,
+    /// The status object as JSON
+    Json,
+    /// Just the state, e.g. for shell prompts
+    Short,
+}
+
+/// How log records are written
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per record, with level, target, message and timestamp
+    Json,
+}
+
+/// How messages to and from the daemon are delimited
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Framing {
+    /// Newline-terminated JSON
+    Line,
+    /// JSON preceded by its length as a big-endian u32
+    Length,
+}
+
+/// Actions of the `config` command
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum ConfigAction {
+    /// Prints the socket path that would be used and why, without connecting
+    Path,
 }
 
 #[derive(Subcommand, Debug, PartialEq)]
 pub enum Commands {
     /// Starts the transcription
     Start {
-        #[arg(long, value_enum, default_value_t = CliOutputMode::Keyboard)]
-        output: CliOutputMode,
+        /// Where to send the transcribed text; repeat to send it to several [env: HANDSFREE_OUTPUT] [default: keyboard]
+        #[arg(long, value_enum)]
+        output: Vec<CliOutputMode>,
+        /// File to write the text to with `--output file`
+        #[arg(long, value_name = "PATH")]
+        output_path: Option<PathBuf>,
+        /// Truncate the --output-path file instead of appending to it
+        #[arg(long, requires = "output_path")]
+        overwrite: bool,
+        /// Input device to record from, by id
+        #[arg(long, conflicts_with = "device_index")]
+        device: Option<String>,
+        /// Input device to record from, by numeric (ALSA/PulseAudio) index
+        #[arg(long)]
+        device_index: Option<u32>,
+        /// Continue a previous session, reusing its context
+        #[arg(long, value_name = "ID", value_parser = parse_session_id)]
+        resume_session: Option<String>,
+        /// Output mode for the daemon to fall back to if the primary one fails
+        #[arg(long, value_enum, value_name = "MODE")]
+        output_fallback: Option<CliOutputMode>,
     },
+    /// Stops the current transcription, if any, and starts a new one
+    Restart {
+        /// Where to send the transcribed text [default: keyboard]
+        #[arg(long, value_enum)]
+        output: Option<CliOutputMode>,
+        /// File to write the text to with `--output file`
+        #[arg(long, value_name = "PATH")]
+        output_path: Option<PathBuf>,
+        /// Truncate the --output-path file instead of appending to it
+        #[arg(long, requires = "output_path")]
+        overwrite: bool,
+    },
+    /// Pauses transcription, e.g. during a call, keeping the session and model loaded
+    Pause,
+    /// Resumes a paused transcription
+    Resume,
     /// Stops the transcription
-    Stop,
+    Stop {
+        /// Wait until the daemon reports it is idle (bounded by --wait-timeout)
+        #[arg(long)]
+        wait: bool,
+    },
     /// Toggles the transcription state (starts if idle, stops if running)
     Toggle {
+        /// Where to send the transcribed text when starting; repeat to send it to several [env: HANDSFREE_OUTPUT]
         #[arg(long, value_enum)]
-        output: Option<CliOutputMode>,
+        output: Vec<CliOutputMode>,
+        /// File to write the text to with `--output file`
+        #[arg(long, value_name = "PATH")]
+        output_path: Option<PathBuf>,
+        /// Truncate the --output-path file instead of appending to it
+        #[arg(long, requires = "output_path")]
+        overwrite: bool,
+        /// Output mode for the daemon to fall back to if the primary one fails
+        #[arg(long, value_enum, value_name = "MODE")]
+        output_fallback: Option<CliOutputMode>,
+        /// Only start transcription; do nothing if it is already running
+        #[arg(long, conflicts_with = "stop_if_running")]
+        start_if_stopped: bool,
+        /// Only stop transcription; do nothing if the daemon is idle
+        #[arg(long)]
+        stop_if_running: bool,
+        /// Ask for the status and send start or stop, for daemons without a toggle command
+        #[arg(long)]
+        query: bool,
+        /// Ignore this toggle if the previous one was less than MS milliseconds ago,
+        /// e.g. from key repeat
+        #[arg(long, value_name = "MS")]
+        debounce: Option<u64>,
     },
     /// Gets the current status of the daemon
-    Status,
+    Status {
+        /// Print the status as a flat JSON object (e.g. for `jq .state`)
+        #[arg(long, conflicts_with = "format")]
+        flat: bool,
+        /// How to print the status [default: json with --json, else plain]
+        #[arg(long, value_enum, visible_alias = "output-format")]
+        format: Option<StatusFormat>,
+        /// Don't end the output with a newline, e.g. for `$(handsfreectl status)` in a
+        /// prompt. Implies `--format short` unless a format is given
+        #[arg(long)]
+        no_newline: bool,
+        /// Print nothing and report the state in the exit code: 0 running, 10 idle,
+        /// 20 error (6 if the daemon is down)
+        #[arg(long, conflicts_with_all = ["flat", "format", "no_newline"])]
+        exit_code_only: bool,
+    },
     /// Watch for status changes
-    Watch,
+    #[command(group(ArgGroup::new("reconnecting").args(["reconnect", "reconnect_on_error"]).multiple(true)))]
+    Watch {
+        /// Annotate each state change with how long the previous state lasted
+        #[arg(long)]
+        annotate_transitions: bool,
+        /// Don't flush stdout after every event (`--compact` output then only
+        /// shows up once the buffer fills)
+        #[arg(long)]
+        no_flush: bool,
+        /// Exit with an error on the first malformed line instead of skipping it
+        #[arg(long)]
+        ndjson_strict: bool,
+        /// Reconnect and resubscribe, with backoff, when the stream yields an unreadable
+        /// line or IO error
+        #[arg(long, conflicts_with = "ndjson_strict")]
+        reconnect_on_error: bool,
+        /// Reconnect and resubscribe, with backoff, when the daemon closes the stream or it fails
+        #[arg(long)]
+        reconnect: bool,
+        /// Give up reconnecting after this many seconds without a connection
+        #[arg(
+            long,
+            value_name = "SECONDS",
+            default_value_t = 60,
+            requires = "reconnecting"
+        )]
+        reconnect_window: u64,
+        /// Print a `connected` line each time the subscription is established
+        #[arg(long)]
+        emit_connected: bool,
+        /// Print a `daemon restarted` line when a reconnect reaches a different
+        /// daemon instance than before, as its state starts over
+        #[arg(long, requires = "reconnecting")]
+        detect_daemon_restart: bool,
+        /// Don't ask for and print the current status before the first change
+        #[arg(long = "no-initial", action = ArgAction::SetFalse)]
+        initial: bool,
+        /// Print event counts per state, session length and reconnects when the stream ends
+        #[arg(long)]
+        summary_on_exit: bool,
+        /// Print one character per state event (e.g. `ILPI`) with no newlines
+        #[arg(long, conflicts_with_all = ["format", "annotate_transitions"])]
+        compact: bool,
+        /// Print state events as `plain`, `short` or `json` like `status` does, or each event
+        /// using a template with {state}, {error}, {event} and {ts} placeholders
+        #[arg(long, value_name = "FORMAT|TEMPLATE")]
+        format: Option<String>,
+        /// Only print state events in one of these states (case-insensitive)
+        #[arg(long, value_name = "STATE", value_delimiter = ',')]
+        filter_state: Vec<String>,
+        /// Exit after N state events; fails if the stream ends first
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+        count: Option<u64>,
+    },
+    /// Blocks until the daemon reaches STATE, e.g. `listening` after a start
+    WaitFor {
+        /// State to wait for (case-insensitive)
+        state: String,
+        /// Give up after this many seconds [default: --wait-timeout]
+        #[arg(id = "within", long, value_name = "SECONDS")]
+        timeout: Option<u64>,
+    },
     /// Tells the daemon to shut down gracefully
     Shutdown,
+    /// Checks that the daemon is up and answering. Exits 6 if it can't be
+    /// reached and 3 if it answers badly
+    Ping {
+        /// Print the round-trip time in milliseconds instead of `pong`
+        #[arg(long)]
+        latency: bool,
+    },
+    /// Types or copies TEXT through the active output mode
+    #[command(visible_alias = "inject-text")]
+    Inject {
+        /// Text to output, or `-` to read it from stdin
+        text: String,
+    },
+    /// Types or copies TEXT through the given output mode, whether or not
+    /// transcription is running
+    SendText {
+        /// Text to output, or `-` to read it from stdin. May span several lines
+        text: String,
+        /// Where to send the text
+        #[arg(long, value_enum, default_value = "keyboard")]
+        output: CliOutputMode,
+    },
+    /// Retracts the last transcribed or injected text (best-effort, daemon-dependent)
+    Undo,
+    /// Sends the JSON commands read from stdin, one per line, over a single
+    /// connection and prints each response as a JSON line
+    Batch {
+        /// Keep going after the daemon answers a command with an error
+        #[arg(long)]
+        continue_on_error: bool,
+    },
+    /// Prints the daemon's usage counters, such as words transcribed
+    Metrics {
+        /// Also print rates derived from the counters: words per hour of
+        /// uptime and words per session
+        #[arg(long)]
+        summary: bool,
+    },
+    /// Lists the active dictation sessions, for daemons that run several at once
+    #[command(visible_alias = "list-sessions")]
+    Sessions,
+    /// Prints the client version and the version reported by the daemon
+    Version,
+    /// Inspects the client's configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Prints a completion script for SHELL to stdout
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Runs the client against a built-in mock daemon and reports the results
+    #[cfg(feature = "test-support")]
+    #[command(name = "selftest")]
+    SelfTest,
 }
 
+const DEFAULT_WAIT_TIMEOUT_SECS: u64 = 30;
+
+/// Environment variable with the default output mode for `start` and `toggle`.
+pub const OUTPUT_ENV: &str = "HANDSFREE_OUTPUT";
+
 impl Cli {
+    /// The `--timeout` for each response.
+    pub fn read_timeout(&self) -> Duration {
+        self.timeout
+            .map_or(DEFAULT_READ_TIMEOUT, Duration::from_secs)
+    }
+
+    /// Log filter to use when `RUST_LOG` is not set.
+    pub fn log_filter(&self) -> &'static str {
+        match self.verbose {
+            0 => "handsfreectl=warn",
+            1 => "handsfreectl=info",
+            2 => "handsfreectl=debug",
+            _ => "handsfreectl=trace",
+        }
+    }
+
+    /// The delay before the first connection retry, from `--connect-retry-delay`.
+    pub fn connect_retry_delay(&self) -> Duration {
+        Duration::from_millis(self.connect_retry_delay)
+    }
+
+    /// The `--connect-timeout`, if any.
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout.map(Duration::from_millis)
+    }
+
+    /// The `--deadline`, if any.
+    pub fn deadline(&self) -> Option<Duration> {
+        self.deadline.map(Duration::from_millis)
+    }
+
+    /// The `--then-wait` limit, from `--wait-timeout` or the built-in default.
+    pub fn wait_limit(&self) -> Duration {
+        Duration::from_secs(self.wait_timeout.unwrap_or(DEFAULT_WAIT_TIMEOUT_SECS))
+    }
+
     pub fn parse() -> Self {
         <Self as Parser>::parse()
     }
+
+    /// Like [`Cli::parse`], but returns the error instead of exiting.
+    pub fn try_parse() -> Result<Self, clap::Error> {
+        <Self as Parser>::try_parse()
+    }
+
+    /// Uses the `HANDSFREE_OUTPUT_<profile>` variable with `--profile`, or
+    /// else `HANDSFREE_OUTPUT`, as the output for `start` and `toggle` when
+    /// no `--output` was given. `var` looks variables up by name; an empty
+    /// one counts as unset.
+    pub fn apply_output_env(
+        &mut self,
+        var: impl Fn(&str) -> Option<OsString>,
+    ) -> Result<(), clap::Error> {
+        let names = self
+            .profile
+            .iter()
+            .map(|profile| format!("{}_{}", OUTPUT_ENV, profile))
+            .chain([OUTPUT_ENV.to_string()]);
+        let Some((name, value)) = names
+            .filter_map(|name| var(&name).map(|value| (name, value)))
+            .find(|(_, value)| !value.is_empty())
+        else {
+            return Ok(());
+        };
+        let (Commands::Start { output, .. } | Commands::Toggle { output, .. }) = &mut self.command
+        else {
+            return Ok(());
+        };
+        if output.is_empty() {
+            let value = value.to_string_lossy();
+            let mode = CliOutputMode::from_str(&value, true).map_err(|_| {
+                let possible: Vec<_> = CliOutputMode::value_variants()
+                    .iter()
+                    .filter_map(|mode| mode.to_possible_value())
+                    .map(|mode| mode.get_name().to_string())
+                    .collect();
+                Self::command().error(
+                    ErrorKind::InvalidValue,
+                    format!(
+                        "invalid value '{}' for {} [possible values: {}]",
+                        value,
+                        name,
+                        possible.join(", ")
+                    ),
+                )
+            })?;
+            output.push(mode);
+        }
+        Ok(())
+    }
+
+    /// Checks combinations of arguments that clap can't express on its own,
+    /// e.g. global options that only make sense for some subcommands. Call
+    /// it once the config file's defaults are applied, as they may set the
+    /// output.
+    pub fn validate(&self) -> Result<(), clap::Error> {
+        if self.then_wait.is_some()
+            && !matches!(
+                self.command,
+                Commands::Start { .. }
+                    | Commands::Restart { .. }
+                    | Commands::Stop { .. }
+                    | Commands::Toggle { .. }
+            )
+        {
+            return Err(Self::command().error(
+                ErrorKind::ArgumentConflict,
+                "--then-wait can only be used with start, restart, stop or toggle",
+            ));
+        }
+        // `wait-for STATE --timeout N` reads naturally but would only set how
+        // long each read may take, leaving the wait itself unbounded
+        if self.timeout.is_some() && matches!(self.command, Commands::WaitFor { .. }) {
+            return Err(Self::command().error(
+                ErrorKind::ArgumentConflict,
+                "--timeout can't be used with wait-for; use --within to bound the wait",
+            ));
+        }
+        let same_fallback = match &self.command {
+            Commands::Start {
+                output,
+                output_fallback: Some(fallback),
+                ..
+            } if output.is_empty() => *fallback == CliOutputMode::default(),
+            Commands::Start {
+                output,
+                output_fallback: Some(fallback),
+                ..
+            }
+            | Commands::Toggle {
+                output,
+                output_fallback: Some(fallback),
+                ..
+            } => output.contains(fallback),
+            _ => false,
+        };
+        if same_fallback {
+            return Err(Self::command().error(
+                ErrorKind::ArgumentConflict,
+                "--output-fallback must differ from the output modes",
+            ));
+        }
+        let (to_file, output_path) = match &self.command {
+            Commands::Start {
+                output,
+                output_path,
+                output_fallback,
+                ..
+            }
+            | Commands::Toggle {
+                output,
+                output_path,
+                output_fallback,
+                ..
+            } => (
+                output.contains(&CliOutputMode::File)
+                    || *output_fallback == Some(CliOutputMode::File),
+                output_path,
+            ),
+            Commands::Restart {
+                output,
+                output_path,
+                ..
+            } => (*output == Some(CliOutputMode::File), output_path),
+            _ => (false, &None),
+        };
+        if to_file && output_path.is_none() {
+            return Err(Self::command().error(
+                ErrorKind::MissingRequiredArgument,
+                "--output file requires --output-path <PATH>",
+            ));
+        }
+        if !to_file && output_path.is_some() {
+            return Err(Self::command().error(
+                ErrorKind::ArgumentConflict,
+                "--output-path can only be used with --output file",
+            ));
+        }
+        if let Commands::SendText {
+            output: CliOutputMode::File,
+            ..
+        } = self.command
+        {
+            return Err(Self::command().error(
+                ErrorKind::InvalidValue,
+                "send-text can't write to a file; use --output keyboard, clipboard or primary",
+            ));
+        }
+        if self.require_running
+            && matches!(
+                self.command,
+                Commands::Status { .. } | Commands::Watch { .. }
+            )
+        {
+            return Err(Self::command().error(
+                ErrorKind::ArgumentConflict,
+                "--require-running can't be used with status or watch",
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use clap::error::ErrorKind;
 
     #[test]
     fn test_parse_start_default() {
-        let args = Cli::parse_from(&["handsfreectl", "start"]);
+        let args = Cli::parse_from(["handsfreectl", "start"]);
         match args.command {
-            Commands::Start { output } => assert_eq!(output, CliOutputMode::Keyboard),
+            Commands::Start { output, .. } => assert!(output.is_empty()),
             _ => panic!("Expected Start command"),
         }
     }
 
     #[test]
     fn test_parse_start_clipboard() {
-        let args = Cli::parse_from(&["handsfreectl", "start", "--output", "clipboard"]);
+        let args = Cli::parse_from(["handsfreectl", "start", "--output", "clipboard"]);
+        match args.command {
+            Commands::Start { output, .. } => assert_eq!(output, [CliOutputMode::Clipboard]),
+            _ => panic!("Expected Start command"),
+        }
+    }
+
+    #[test]
+    fn test_parse_start_primary() {
+        let args = Cli::parse_from(["handsfreectl", "start", "--output", "primary"]);
         match args.command {
-            Commands::Start { output } => assert_eq!(output, CliOutputMode::Clipboard),
+            Commands::Start { output, .. } => {
+                assert_eq!(output, [CliOutputMode::PrimarySelection])
+            }
             _ => panic!("Expected Start command"),
         }
     }
 
     #[test]
     fn test_parse_start_keyboard() {
-        let args = Cli::parse_from(&["handsfreectl", "start", "--output", "keyboard"]);
+        let args = Cli::parse_from(["handsfreectl", "start", "--output", "keyboard"]);
+        match args.command {
+            Commands::Start { output, .. } => assert_eq!(output, [CliOutputMode::Keyboard]),
+            _ => panic!("Expected Start command"),
+        }
+    }
+
+    #[test]
+    fn test_parse_start_multiple_outputs() {
+        let args = Cli::parse_from([
+            "handsfreectl",
+            "start",
+            "--output",
+            "keyboard",
+            "--output",
+            "clipboard",
+        ]);
+        match args.command {
+            Commands::Start { output, .. } => {
+                assert_eq!(output, [CliOutputMode::Keyboard, CliOutputMode::Clipboard])
+            }
+            _ => panic!("Expected Start command"),
+        }
+    }
+
+    #[test]
+    fn test_parse_start_device() {
+        let args = Cli::parse_from(["handsfreectl", "start", "--device", "usb-mic"]);
         match args.command {
-            Commands::Start { output } => assert_eq!(output, CliOutputMode::Keyboard),
+            Commands::Start {
+                device,
+                device_index,
+                ..
+            } => {
+                assert_eq!(device, Some("usb-mic".to_string()));
+                assert_eq!(device_index, None);
+            }
             _ => panic!("Expected Start command"),
         }
     }
 
+    #[test]
+    fn test_parse_start_device_index() {
+        let args = Cli::parse_from(["handsfreectl", "start", "--device-index", "2"]);
+        match args.command {
+            Commands::Start {
+                device,
+                device_index,
+                ..
+            } => {
+                assert_eq!(device, None);
+                assert_eq!(device_index, Some(2));
+            }
+            _ => panic!("Expected Start command"),
+        }
+    }
+
+    #[test]
+    fn test_parse_start_device_conflict() {
+        let result = Cli::try_parse_from([
+            "handsfreectl",
+            "start",
+            "--device",
+            "usb-mic",
+            "--device-index",
+            "2",
+        ]);
+        match result.unwrap_err().kind() {
+            ErrorKind::ArgumentConflict => (), // Test passes
+            other => panic!("Expected ArgumentConflict error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_start_resume_session() {
+        let args = Cli::parse_from(["handsfreectl", "start", "--resume-session", "a1b2-c3_d4"]);
+        match args.command {
+            Commands::Start { resume_session, .. } => {
+                assert_eq!(resume_session, Some("a1b2-c3_d4".to_string()))
+            }
+            _ => panic!("Expected Start command"),
+        }
+    }
+
+    #[test]
+    fn test_parse_start_invalid_resume_session() {
+        for id in ["", "has space", "semi;colon", &"x".repeat(65)] {
+            let result = Cli::try_parse_from(["handsfreectl", "start", "--resume-session", id]);
+            match result.unwrap_err().kind() {
+                ErrorKind::ValueValidation => (), // Test passes
+                other => panic!("Expected ValueValidation error, got {:?}", other),
+            }
+        }
+    }
+
     #[test]
     fn test_parse_toggle() {
-        let args = Cli::parse_from(&["handsfreectl", "toggle"]);
+        let args = Cli::parse_from(["handsfreectl", "toggle"]);
         match args.command {
-            Commands::Toggle { output } => assert_eq!(output, None),
+            Commands::Toggle { output, .. } => assert!(output.is_empty()),
             _ => panic!("Expected Toggle command"),
         }
     }
 
     #[test]
     fn test_parse_toggle_with_output() {
-        let args = Cli::parse_from(&["handsfreectl", "toggle", "--output", "clipboard"]);
+        let args = Cli::parse_from(["handsfreectl", "toggle", "--output", "clipboard"]);
         match args.command {
-            Commands::Toggle { output } => assert_eq!(output, Some(CliOutputMode::Clipboard)),
+            Commands::Toggle { output, .. } => {
+                assert_eq!(output, [CliOutputMode::Clipboard])
+            }
             _ => panic!("Expected Toggle command"),
         }
     }
 
+    #[test]
+    fn test_parse_toggle_conditions_conflict() {
+        let cli = Cli::parse_from(["handsfreectl", "toggle", "--start-if-stopped"]);
+        assert_eq!(
+            cli.command,
+            Commands::Toggle {
+                output: vec![],
+                output_path: None,
+                overwrite: false,
+                output_fallback: None,
+                start_if_stopped: true,
+                stop_if_running: false,
+                query: false,
+                debounce: None,
+            }
+        );
+
+        let result = Cli::try_parse_from([
+            "handsfreectl",
+            "toggle",
+            "--start-if-stopped",
+            "--stop-if-running",
+        ]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_output_env() {
+        let output_of = |args: &[&str], env: Option<&str>| {
+            let mut cli = Cli::try_parse_from(args).unwrap();
+            cli.apply_output_env(|name| {
+                assert_eq!(name, OUTPUT_ENV);
+                env.map(OsString::from)
+            })
+            .map(|()| cli.command)
+        };
+
+        match output_of(&["handsfreectl", "start"], Some("clipboard")).unwrap() {
+            Commands::Start { output, .. } => assert_eq!(output, [CliOutputMode::Clipboard]),
+            _ => panic!("Expected Start command"),
+        }
+        match output_of(&["handsfreectl", "toggle"], Some("PRIMARY")).unwrap() {
+            Commands::Toggle { output, .. } => {
+                assert_eq!(output, [CliOutputMode::PrimarySelection])
+            }
+            _ => panic!("Expected Toggle command"),
+        }
+        // The flag wins over the variable, which wins over the default
+        match output_of(
+            &["handsfreectl", "start", "--output", "keyboard"],
+            Some("clipboard"),
+        ) {
+            Ok(Commands::Start { output, .. }) => assert_eq!(output, [CliOutputMode::Keyboard]),
+            _ => panic!("Expected Start command"),
+        }
+        for env in [None, Some("")] {
+            match output_of(&["handsfreectl", "start"], env) {
+                Ok(Commands::Start { output, .. }) => assert!(output.is_empty()),
+                _ => panic!("Expected Start command"),
+            }
+        }
+
+        let err = output_of(&["handsfreectl", "start"], Some("speaker")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidValue);
+        assert!(err.to_string().contains("HANDSFREE_OUTPUT"));
+        // Other commands don't look at it
+        assert!(output_of(&["handsfreectl", "stop"], Some("speaker")).is_ok());
+    }
+
+    #[test]
+    fn test_output_env_profile() {
+        let output_of = |args: &[&str], vars: &[(&str, &str)]| {
+            let mut cli = Cli::try_parse_from(args).unwrap();
+            cli.apply_output_env(|name| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| OsString::from(value))
+            })
+            .map(|()| match cli.command {
+                Commands::Start { output, .. } => output,
+                _ => panic!("Expected Start command"),
+            })
+        };
+        let vars = [
+            ("HANDSFREE_OUTPUT_work", "clipboard"),
+            ("HANDSFREE_OUTPUT", "primary"),
+        ];
+        let start = ["handsfreectl", "--profile", "work", "start"];
+
+        // The flag wins over the profile's variable, which wins over the
+        // generic one, which wins over the default
+        let flagged = [
+            "handsfreectl",
+            "--profile",
+            "work",
+            "start",
+            "--output",
+            "file",
+        ];
+        assert_eq!(output_of(&flagged, &vars).unwrap(), [CliOutputMode::File]);
+        assert_eq!(
+            output_of(&start, &vars).unwrap(),
+            [CliOutputMode::Clipboard]
+        );
+        for unset in [&vars[1..], &[("HANDSFREE_OUTPUT_work", ""), vars[1]]] {
+            assert_eq!(
+                output_of(&start, unset).unwrap(),
+                [CliOutputMode::PrimarySelection]
+            );
+        }
+        assert!(output_of(&start, &[]).unwrap().is_empty());
+        // Without --profile, only the generic variable is read
+        assert_eq!(
+            output_of(&["handsfreectl", "start"], &vars).unwrap(),
+            [CliOutputMode::PrimarySelection]
+        );
+
+        let err = output_of(&start, &[("HANDSFREE_OUTPUT_work", "speaker")]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidValue);
+        assert!(err.to_string().contains("HANDSFREE_OUTPUT_work"));
+
+        for profile in ["", "my-work", "a=b"] {
+            let err =
+                Cli::try_parse_from(["handsfreectl", "--profile", profile, "status"]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ValueValidation);
+        }
+    }
+
+    #[test]
+    fn test_parse_socket() {
+        let args = Cli::parse_from(["handsfreectl", "status"]);
+        assert_eq!(args.socket, None);
+
+        let args = Cli::parse_from(["handsfreectl", "--socket", "/run/hf/test.sock", "status"]);
+        assert_eq!(args.socket, Some(PathBuf::from("/run/hf/test.sock")));
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        let args = Cli::parse_from(["handsfreectl", "status"]);
+        assert_eq!(args.read_timeout(), DEFAULT_READ_TIMEOUT);
+
+        let args = Cli::parse_from(["handsfreectl", "status", "--timeout", "30"]);
+        assert_eq!(args.read_timeout(), Duration::from_secs(30));
+
+        let args = Cli::parse_from(["handsfreectl", "--timeout", "0", "start"]);
+        assert!(args.read_timeout().is_zero());
+    }
+
+    #[test]
+    fn test_parse_verbose() {
+        let args = Cli::parse_from(["handsfreectl", "status"]);
+        assert_eq!(args.log_filter(), "handsfreectl=warn");
+
+        let args = Cli::parse_from(["handsfreectl", "-v", "status"]);
+        assert_eq!(args.log_filter(), "handsfreectl=info");
+
+        let args = Cli::parse_from(["handsfreectl", "status", "-vv"]);
+        assert_eq!(args.log_filter(), "handsfreectl=debug");
+
+        let args = Cli::parse_from(["handsfreectl", "-vvvv", "--verbose", "status"]);
+        assert_eq!(args.verbose, 5);
+        assert_eq!(args.log_filter(), "handsfreectl=trace");
+    }
+
+    #[test]
+    fn test_parse_connect_retries() {
+        let args = Cli::parse_from(["handsfreectl", "status"]);
+        assert_eq!(args.connect_retries, 0);
+        assert_eq!(args.connect_retry_delay(), DEFAULT_CONNECT_RETRY_DELAY);
+
+        let args = Cli::parse_from([
+            "handsfreectl",
+            "--connect-retries",
+            "4",
+            "--connect-retry-delay",
+            "250",
+            "start",
+        ]);
+        assert_eq!(args.connect_retries, 4);
+        assert_eq!(args.connect_retry_delay(), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_parse_state_alias() {
+        let args = Cli::parse_from([
+            "handsfreectl",
+            "status",
+            "--state-alias",
+            "running=listening",
+            "--state-alias",
+            "stopped=idle",
+        ]);
+        assert_eq!(
+            args.state_alias,
+            [
+                ("running".to_string(), "listening".to_string()),
+                ("stopped".to_string(), "idle".to_string())
+            ]
+        );
+
+        for invalid in ["running", "=idle", "running="] {
+            let result = Cli::try_parse_from(["handsfreectl", "status", "--state-alias", invalid]);
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::ValueValidation);
+        }
+    }
+
     #[test]
     fn test_parse_stop() {
-        let args = Cli::parse_from(&["handsfreectl", "stop"]);
-        assert_eq!(args.command, Commands::Stop);
+        let args = Cli::parse_from(["handsfreectl", "stop"]);
+        assert_eq!(args.command, Commands::Stop { wait: false });
     }
 
     #[test]
     fn test_parse_status() {
-        let args = Cli::parse_from(&["handsfreectl", "status"]);
-        assert_eq!(args.command, Commands::Status);
+        let args = Cli::parse_from(["handsfreectl", "status"]);
+        assert_eq!(
+            args.command,
+            Commands::Status {
+                flat: false,
+                format: None,
+                no_newline: false,
+                exit_code_only: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_status_format() {
+        let args = Cli::parse_from(["handsfreectl", "status", "--format", "short"]);
+        assert_eq!(
+            args.command,
+            Commands::Status {
+                flat: false,
+                format: Some(StatusFormat::Short),
+                no_newline: false,
+                exit_code_only: false,
+            }
+        );
+
+        let args = Cli::parse_from(["handsfreectl", "status", "--output-format", "json"]);
+        assert_eq!(
+            args.command,
+            Commands::Status {
+                flat: false,
+                format: Some(StatusFormat::Json),
+                no_newline: false,
+                exit_code_only: false,
+            }
+        );
+
+        let result = Cli::try_parse_from(["handsfreectl", "status", "--flat", "--format", "plain"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_parse_status_flat() {
+        let args = Cli::parse_from(["handsfreectl", "status", "--flat"]);
+        assert_eq!(
+            args.command,
+            Commands::Status {
+                flat: true,
+                format: None,
+                no_newline: false,
+                exit_code_only: false,
+            }
+        );
     }
 
     #[test]
     fn test_parse_watch() {
-        let args = Cli::parse_from(&["handsfreectl", "watch"]);
-        assert_eq!(args.command, Commands::Watch);
+        let args = Cli::parse_from(["handsfreectl", "watch"]);
+        assert_eq!(
+            args.command,
+            Commands::Watch {
+                annotate_transitions: false,
+                no_flush: false,
+                ndjson_strict: false,
+                reconnect_on_error: false,
+                reconnect: false,
+                reconnect_window: 60,
+                emit_connected: false,
+                detect_daemon_restart: false,
+                initial: true,
+                summary_on_exit: false,
+                compact: false,
+                format: None,
+                filter_state: vec![],
+                count: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_watch_annotate_transitions() {
+        let args = Cli::parse_from(["handsfreectl", "watch", "--annotate-transitions"]);
+        assert_eq!(
+            args.command,
+            Commands::Watch {
+                annotate_transitions: true,
+                no_flush: false,
+                ndjson_strict: false,
+                reconnect_on_error: false,
+                reconnect: false,
+                reconnect_window: 60,
+                emit_connected: false,
+                detect_daemon_restart: false,
+                initial: true,
+                summary_on_exit: false,
+                compact: false,
+                format: None,
+                filter_state: vec![],
+                count: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_watch_no_flush() {
+        let args = Cli::parse_from(["handsfreectl", "watch", "--no-flush"]);
+        assert_eq!(
+            args.command,
+            Commands::Watch {
+                annotate_transitions: false,
+                no_flush: true,
+                ndjson_strict: false,
+                reconnect_on_error: false,
+                reconnect: false,
+                reconnect_window: 60,
+                emit_connected: false,
+                detect_daemon_restart: false,
+                initial: true,
+                summary_on_exit: false,
+                compact: false,
+                format: None,
+                filter_state: vec![],
+                count: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_watch_format() {
+        let args = Cli::parse_from(["handsfreectl", "watch", "--format", "[{state}]"]);
+        match args.command {
+            Commands::Watch { format, .. } => assert_eq!(format, Some("[{state}]".to_string())),
+            _ => panic!("Expected Watch command"),
+        }
+    }
+
+    #[test]
+    fn test_parse_watch_reconnect_window() {
+        for flag in ["--reconnect", "--reconnect-on-error"] {
+            let args = ["handsfreectl", "watch", flag, "--reconnect-window", "5"];
+            match Cli::parse_from(args).command {
+                Commands::Watch {
+                    reconnect_window, ..
+                } => assert_eq!(reconnect_window, 5),
+                _ => panic!("Expected Watch command"),
+            }
+        }
+        let result = Cli::try_parse_from(["handsfreectl", "watch", "--reconnect-window", "5"]);
+        assert_eq!(
+            result.unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument
+        );
+        let result = Cli::try_parse_from(["handsfreectl", "watch", "--detect-daemon-restart"]);
+        assert_eq!(
+            result.unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument
+        );
+    }
+
+    #[test]
+    fn test_parse_watch_filter_state() {
+        let args = Cli::parse_from([
+            "handsfreectl",
+            "watch",
+            "--filter-state",
+            "idle,error",
+            "--filter-state",
+            "running",
+        ]);
+        match args.command {
+            Commands::Watch { filter_state, .. } => {
+                assert_eq!(filter_state, ["idle", "error", "running"])
+            }
+            _ => panic!("Expected Watch command"),
+        }
+    }
+
+    #[test]
+    fn test_parse_watch_count() {
+        let args = Cli::parse_from(["handsfreectl", "watch", "--count", "1"]);
+        match args.command {
+            Commands::Watch { count, .. } => assert_eq!(count, Some(1)),
+            _ => panic!("Expected Watch command"),
+        }
+
+        let result = Cli::try_parse_from(["handsfreectl", "watch", "--count", "0"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn test_parse_watch_initial() {
+        let initial = |args: &[&str]| match Cli::parse_from(args).command {
+            Commands::Watch { initial, .. } => initial,
+            _ => panic!("Expected Watch command"),
+        };
+        assert!(initial(&["handsfreectl", "watch"]));
+        assert!(!initial(&["handsfreectl", "watch", "--no-initial"]));
     }
 
     #[test]
     fn test_parse_shutdown() {
-        let args = Cli::parse_from(&["handsfreectl", "shutdown"]);
+        let args = Cli::parse_from(["handsfreectl", "shutdown"]);
         assert_eq!(args.command, Commands::Shutdown);
     }
 
+    #[test]
+    fn test_parse_ping() {
+        let args = Cli::parse_from(["handsfreectl", "ping"]);
+        assert_eq!(args.command, Commands::Ping { latency: false });
+        let args = Cli::parse_from(["handsfreectl", "ping", "--latency"]);
+        assert_eq!(args.command, Commands::Ping { latency: true });
+    }
+
+    #[test]
+    fn test_parse_config_path() {
+        let args = Cli::parse_from(["handsfreectl", "config", "path"]);
+        assert_eq!(
+            args.command,
+            Commands::Config {
+                action: ConfigAction::Path
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_wait_for() {
+        let args = Cli::parse_from(["handsfreectl", "wait-for", "listening"]);
+        assert_eq!(
+            args.command,
+            Commands::WaitFor {
+                state: "listening".to_string(),
+                timeout: None
+            }
+        );
+
+        let args = Cli::parse_from(["handsfreectl", "wait-for", "idle", "--within", "5"]);
+        assert_eq!(
+            args.command,
+            Commands::WaitFor {
+                state: "idle".to_string(),
+                timeout: Some(5)
+            }
+        );
+
+        // The read timeout is no way to bound the wait
+        let args = Cli::parse_from(["handsfreectl", "wait-for", "idle", "--timeout", "3"]);
+        assert_eq!(args.timeout, Some(3));
+        let err = args.validate().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+        assert!(err.to_string().contains("use --within"));
+    }
+
+    #[test]
+    fn test_parse_metrics() {
+        let args = Cli::parse_from(["handsfreectl", "metrics"]);
+        assert_eq!(args.command, Commands::Metrics { summary: false });
+        let args = Cli::parse_from(["handsfreectl", "metrics", "--summary"]);
+        assert_eq!(args.command, Commands::Metrics { summary: true });
+    }
+
+    #[test]
+    fn test_parse_sessions() {
+        let args = Cli::parse_from(["handsfreectl", "sessions"]);
+        assert_eq!(args.command, Commands::Sessions);
+        let args = Cli::parse_from(["handsfreectl", "list-sessions"]);
+        assert_eq!(args.command, Commands::Sessions);
+    }
+
+    #[test]
+    fn test_parse_version() {
+        let args = Cli::parse_from(["handsfreectl", "version"]);
+        assert_eq!(args.command, Commands::Version);
+    }
+
+    #[test]
+    fn test_parse_completions() {
+        let args = Cli::parse_from(["handsfreectl", "completions", "zsh"]);
+        assert_eq!(args.command, Commands::Completions { shell: Shell::Zsh });
+    }
+
+    #[test]
+    fn test_parse_inject() {
+        let args = Cli::parse_from(["handsfreectl", "inject", "Best regards"]);
+        assert_eq!(
+            args.command,
+            Commands::Inject {
+                text: "Best regards".to_string()
+            }
+        );
+
+        let args = Cli::parse_from(["handsfreectl", "inject-text", "-"]);
+        assert_eq!(
+            args.command,
+            Commands::Inject {
+                text: "-".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_send_text() {
+        let args = Cli::parse_from(["handsfreectl", "send-text", "Dear Sir,\nThanks"]);
+        assert_eq!(
+            args.command,
+            Commands::SendText {
+                text: "Dear Sir,\nThanks".to_string(),
+                output: CliOutputMode::Keyboard,
+            }
+        );
+
+        let args = Cli::parse_from(["handsfreectl", "send-text", "-", "--output", "clipboard"]);
+        assert_eq!(
+            args.command,
+            Commands::SendText {
+                text: "-".to_string(),
+                output: CliOutputMode::Clipboard,
+            }
+        );
+
+        let cli = Cli::parse_from(["handsfreectl", "send-text", "x", "--output", "file"]);
+        assert_eq!(cli.validate().unwrap_err().kind(), ErrorKind::InvalidValue);
+    }
+
+    #[test]
+    fn test_parse_restart() {
+        let args = Cli::parse_from(["handsfreectl", "restart", "--output", "clipboard"]);
+        assert_eq!(
+            args.command,
+            Commands::Restart {
+                output: Some(CliOutputMode::Clipboard),
+                output_path: None,
+                overwrite: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_stop_wait() {
+        let args = Cli::parse_from(["handsfreectl", "stop", "--wait"]);
+        assert_eq!(args.command, Commands::Stop { wait: true });
+    }
+
+    #[test]
+    fn test_parse_undo() {
+        let args = Cli::parse_from(["handsfreectl", "undo"]);
+        assert_eq!(args.command, Commands::Undo);
+    }
+
+    #[test]
+    fn test_parse_echo_command() {
+        let args = Cli::parse_from(["handsfreectl", "status"]);
+        assert!(!args.echo_command);
+
+        let args = Cli::parse_from(["handsfreectl", "stop", "--echo-command"]);
+        assert!(args.echo_command);
+        assert_eq!(args.command, Commands::Stop { wait: false });
+    }
+
+    #[test]
+    fn test_parse_then_wait() {
+        let args = Cli::parse_from(["handsfreectl", "start"]);
+        assert_eq!(args.then_wait, None);
+        assert_eq!(args.wait_limit(), Duration::from_secs(30));
+
+        let args = Cli::parse_from([
+            "handsfreectl",
+            "start",
+            "--then-wait",
+            "running",
+            "--wait-timeout",
+            "5",
+        ]);
+        assert_eq!(args.then_wait, Some("running".to_string()));
+        assert_eq!(args.wait_limit(), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_validate_valid_combinations() {
+        for args in [
+            &["handsfreectl", "start", "--then-wait", "listening"][..],
+            &["handsfreectl", "stop", "--then-wait", "idle"],
+            &[
+                "handsfreectl",
+                "toggle",
+                "--output",
+                "clipboard",
+                "--then-wait",
+                "idle",
+            ],
+            &["handsfreectl", "start", "--device-index", "1"],
+            &["handsfreectl", "status", "--flat"],
+            &["handsfreectl", "stop", "--require-running"],
+        ] {
+            let cli = Cli::try_parse_from(args).unwrap();
+            assert!(cli.validate().is_ok(), "{:?} should be valid", args);
+        }
+    }
+
+    #[test]
+    fn test_validate_then_wait_requires_ack_command() {
+        for args in [
+            &["handsfreectl", "status", "--then-wait", "idle"][..],
+            &["handsfreectl", "watch", "--then-wait", "idle"],
+            &["handsfreectl", "--then-wait", "idle", "shutdown"],
+        ] {
+            let cli = Cli::try_parse_from(args).unwrap();
+            let err = cli.validate().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+            assert!(
+                err.to_string()
+                    .contains("--then-wait can only be used with start, restart, stop or toggle")
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_require_running_with_status() {
+        for args in [
+            &["handsfreectl", "status", "--require-running"][..],
+            &["handsfreectl", "watch", "--require-running"],
+        ] {
+            let cli = Cli::try_parse_from(args).unwrap();
+            assert_eq!(
+                cli.validate().unwrap_err().kind(),
+                ErrorKind::ArgumentConflict
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_output_fallback() {
+        let cli = Cli::parse_from([
+            "handsfreectl",
+            "start",
+            "--output",
+            "clipboard",
+            "--output-fallback",
+            "keyboard",
+        ]);
+        assert!(cli.validate().is_ok());
+        match cli.command {
+            Commands::Start {
+                output_fallback, ..
+            } => assert_eq!(output_fallback, Some(CliOutputMode::Keyboard)),
+            _ => panic!("Expected Start command"),
+        }
+
+        for args in [
+            &["handsfreectl", "start", "--output-fallback", "keyboard"][..],
+            &[
+                "handsfreectl",
+                "toggle",
+                "--output",
+                "clipboard",
+                "--output-fallback",
+                "clipboard",
+            ],
+        ] {
+            let cli = Cli::try_parse_from(args).unwrap();
+            let err = cli.validate().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+        }
+    }
+
+    #[test]
+    fn test_validate_file_output_path() {
+        let cli = Cli::try_parse_from([
+            "handsfreectl",
+            "start",
+            "--output",
+            "file",
+            "--output-path",
+            "/tmp/notes.txt",
+        ])
+        .unwrap();
+        assert!(cli.validate().is_ok());
+        match cli.command {
+            Commands::Start {
+                output,
+                output_path,
+                ..
+            } => {
+                assert_eq!(output, [CliOutputMode::File]);
+                assert_eq!(output_path, Some(PathBuf::from("/tmp/notes.txt")));
+            }
+            _ => panic!("Expected Start command"),
+        }
+
+        let cli = Cli::try_parse_from([
+            "handsfreectl",
+            "toggle",
+            "--output",
+            "file",
+            "--output-path",
+            "/tmp/notes.txt",
+            "--overwrite",
+        ])
+        .unwrap();
+        assert!(cli.validate().is_ok());
+        assert!(matches!(
+            cli.command,
+            Commands::Toggle {
+                overwrite: true,
+                ..
+            }
+        ));
+        let result = Cli::try_parse_from(["handsfreectl", "start", "--overwrite"]);
+        assert_eq!(
+            result.unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument
+        );
+
+        let cli = Cli::try_parse_from([
+            "handsfreectl",
+            "restart",
+            "--output",
+            "file",
+            "--output-path",
+            "notes.txt",
+        ])
+        .unwrap();
+        assert!(cli.validate().is_ok());
+
+        for args in [
+            &["handsfreectl", "toggle", "--output", "file"][..],
+            &["handsfreectl", "restart", "--output", "file"],
+        ] {
+            let cli = Cli::try_parse_from(args).unwrap();
+            let err = cli.validate().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+        }
+
+        let cli = Cli::try_parse_from(["handsfreectl", "start", "--output-path", "/tmp/notes.txt"])
+            .unwrap();
+        let err = cli.validate().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_validate_error_exit_code() {
+        let cli = Cli::try_parse_from([
+            "handsfreectl",
+            "--parse-only",
+            "status",
+            "--then-wait",
+            "idle",
+        ])
+        .unwrap();
+        assert!(cli.parse_only);
+        assert_eq!(cli.validate().unwrap_err().exit_code(), 2);
+    }
+
+    #[test]
+    fn test_parse_max_line_length() {
+        let cli = Cli::parse_from(["handsfreectl", "--max-line-length", "1", "status"]);
+        assert_eq!(cli.max_line_length, NonZeroUsize::MIN);
+
+        let result = Cli::try_parse_from(["handsfreectl", "--max-line-length", "0", "status"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ValueValidation);
+    }
+
     #[test]
     fn test_parse_invalid_command() {
-        let result = Cli::try_parse_from(&["handsfreectl", "invalid_command"]);
+        let result = Cli::try_parse_from(["handsfreectl", "invalid_command"]);
         match result.unwrap_err().kind() {
             ErrorKind::InvalidSubcommand => (), // Test passes
             other => panic!("Expected InvalidSubcommand error, got {:?}", other),
@@ -129,7 +1588,7 @@ mod tests {
 
     #[test]
     fn test_parse_invalid_output_mode() {
-        let result = Cli::try_parse_from(&["handsfreectl", "start", "--output", "invalid"]);
+        let result = Cli::try_parse_from(["handsfreectl", "start", "--output", "invalid"]);
         match result.unwrap_err().kind() {
             ErrorKind::InvalidValue => (), // Test passes
             other => panic!("Expected InvalidValue error, got {:?}", other),
@@ -0,0 +1,300 @@
+//! Persistent client defaults, read from a TOML (or JSON) settings file.
+
+use crate::cli::{Cli, CliOutputMode, Commands};
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Defaults applied when the corresponding command-line option isn't given.
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientSettings {
+    /// Daemon socket to use instead of the one under the runtime directory
+    pub socket: Option<PathBuf>,
+    /// Output mode for `start` and `restart`
+    pub output: Option<CliOutputMode>,
+    /// Seconds to wait for a state with `--then-wait`
+    pub wait_timeout: Option<u64>,
+    /// State names to report under another name, as with `--state-alias`
+    pub state_aliases: BTreeMap<String, String>,
+}
+
+impl ClientSettings {
+    /// `$XDG_CONFIG_HOME/handsfree/handsfreectl.toml`, falling back to
+    /// `~/.config` when XDG_CONFIG_HOME isn't set.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_dir.join("handsfree").join("handsfreectl.toml"))
+    }
+
+    /// Reads settings from `path`, parsed as JSON if it has a `.json`
+    /// extension and as TOML otherwise.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {:?}", path))?;
+        if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid config file {:?}", path))
+        } else {
+            toml::from_str(&contents).with_context(|| format!("Invalid config file {:?}", path))
+        }
+    }
+
+    /// Loads the settings for `cli`: the `--config-file` if given (which must
+    /// exist), otherwise the default file if there is one.
+    pub fn for_cli(cli: &Cli) -> Result<Self> {
+        match &cli.config_file {
+            Some(path) => Self::load(path),
+            None => match Self::default_path() {
+                Some(path) if path.exists() => Self::load(&path),
+                _ => Ok(Self::default()),
+            },
+        }
+    }
+
+    /// Fills in options that weren't given on the command line.
+    pub fn apply(&self, cli: &mut Cli) {
+        if cli.wait_timeout.is_none() {
+            cli.wait_timeout = self.wait_timeout;
+        }
+        for (from, to) in &self.state_aliases {
+            if !cli.state_alias.iter().any(|(given, _)| given == from) {
+                cli.state_alias.push((from.clone(), to.clone()));
+            }
+        }
+        match &mut cli.command {
+            Commands::Start { output, .. } if output.is_empty() => {
+                output.extend(self.output.clone());
+            }
+            Commands::Restart { output, .. } if output.is_none() => {
+                *output = self.output.clone();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Reads the `KEY=VALUE` lines of an `--env-file`. Blank lines and lines
+/// starting with `#` are skipped, and a value may be wrapped in single or
+/// double quotes.
+pub fn read_env_file(path: &Path) -> Result<Vec<(String, String)>> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read env file {:?}", path))?;
+    parse_env_file(&contents).with_context(|| format!("Invalid env file {:?}", path))
+}
+
+fn parse_env_file(contents: &str) -> Result<Vec<(String, String)>> {
+    let mut vars = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            bail!("Line {}: expected KEY=VALUE", index + 1);
+        };
+        let key = key.trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            bail!("Line {}: invalid variable name {:?}", index + 1, key);
+        }
+        let value = value.trim();
+        let value = ['"', '\'']
+            .into_iter()
+            .find_map(|quote| value.strip_prefix(quote)?.strip_suffix(quote))
+            .unwrap_or(value);
+        vars.push((key.to_string(), value.to_string()));
+    }
+    Ok(vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::io::Write;
+
+    fn write_config(name: &str, contents: &str) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        fs::File::create(&path)
+            .unwrap()
+            .write_all(contents.as_bytes())
+            .unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn test_load_toml() {
+        let (_dir, path) = write_config(
+            "handsfreectl.toml",
+            "socket = \"/run/handsfree.sock\"\noutput = \"clipboard\"\nwait_timeout = 10\n",
+        );
+        assert_eq!(
+            ClientSettings::load(&path).unwrap(),
+            ClientSettings {
+                socket: Some(PathBuf::from("/run/handsfree.sock")),
+                output: Some(CliOutputMode::Clipboard),
+                wait_timeout: Some(10),
+                state_aliases: BTreeMap::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_state_aliases_merge_with_flags() {
+        let (_dir, path) = write_config(
+            "handsfreectl.toml",
+            "[state_aliases]\nrunning = \"listening\"\nstopped = \"idle\"\n",
+        );
+        let settings = ClientSettings::load(&path).unwrap();
+        let mut cli =
+            Cli::parse_from(["handsfreectl", "status", "--state-alias", "running=active"]);
+        settings.apply(&mut cli);
+
+        assert_eq!(
+            cli.state_alias,
+            [
+                ("running".to_string(), "active".to_string()),
+                ("stopped".to_string(), "idle".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_load_json() {
+        let (_dir, path) = write_config("handsfreectl.json", r#"{"output": "keyboard"}"#);
+        assert_eq!(
+            ClientSettings::load(&path).unwrap(),
+            ClientSettings {
+                output: Some(CliOutputMode::Keyboard),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_load_rejects_unknown_keys() {
+        let (_dir, path) = write_config("handsfreectl.toml", "outptu = \"clipboard\"\n");
+        let err = ClientSettings::load(&path).unwrap_err();
+        assert!(format!("{:#}", err).contains("unknown field `outptu`"));
+    }
+
+    #[test]
+    fn test_for_cli_requires_explicit_file() {
+        let cli = Cli::parse_from([
+            "handsfreectl",
+            "status",
+            "--config-file",
+            "/nonexistent/handsfreectl.toml",
+        ]);
+        assert!(ClientSettings::for_cli(&cli).is_err());
+    }
+
+    #[test]
+    fn test_read_env_file() {
+        let (_dir, path) = write_config(
+            "instance.env",
+            "# Second instance\n\nXDG_RUNTIME_DIR=/run/user/1000/alt\n  HANDSFREE_OUTPUT = \"clipboard\"\nEMPTY=\nQUOTED='a = b'\n",
+        );
+
+        assert_eq!(
+            read_env_file(&path).unwrap(),
+            [
+                ("XDG_RUNTIME_DIR", "/run/user/1000/alt"),
+                ("HANDSFREE_OUTPUT", "clipboard"),
+                ("EMPTY", ""),
+                ("QUOTED", "a = b"),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+    }
+
+    #[test]
+    fn test_read_env_file_rejects_malformed_lines() {
+        for (contents, message) in [
+            ("A=1\njust text\n", "Line 2: expected KEY=VALUE"),
+            ("=1\n", "Line 1: invalid variable name \"\""),
+            ("MY VAR=1\n", "Line 1: invalid variable name \"MY VAR\""),
+        ] {
+            let (_dir, path) = write_config("bad.env", contents);
+            let err = read_env_file(&path).unwrap_err();
+            assert_eq!(err.root_cause().to_string(), message);
+        }
+        assert!(read_env_file(Path::new("/nonexistent/handsfree.env")).is_err());
+    }
+
+    #[test]
+    fn test_apply_fills_missing_options() {
+        let settings = ClientSettings {
+            output: Some(CliOutputMode::Clipboard),
+            wait_timeout: Some(10),
+            ..Default::default()
+        };
+        let mut cli = Cli::parse_from(["handsfreectl", "start"]);
+        settings.apply(&mut cli);
+
+        assert_eq!(cli.wait_timeout, Some(10));
+        match cli.command {
+            Commands::Start { output, .. } => assert_eq!(output, [CliOutputMode::Clipboard]),
+            _ => panic!("Expected Start command"),
+        }
+    }
+
+    #[test]
+    fn test_output_fallback_checked_against_settings() {
+        let settings = ClientSettings {
+            output: Some(CliOutputMode::Clipboard),
+            ..Default::default()
+        };
+        let mut cli = Cli::parse_from(["handsfreectl", "start", "--output-fallback", "keyboard"]);
+        settings.apply(&mut cli);
+        assert!(cli.validate().is_ok());
+
+        let mut cli = Cli::parse_from(["handsfreectl", "start", "--output-fallback", "clipboard"]);
+        settings.apply(&mut cli);
+        assert!(cli.validate().is_err());
+    }
+
+    #[test]
+    fn test_output_path_with_file_from_settings() {
+        let settings = ClientSettings {
+            output: Some(CliOutputMode::File),
+            ..Default::default()
+        };
+        for command in ["start", "restart"] {
+            let mut cli = Cli::parse_from(["handsfreectl", command, "--output-path", "notes.txt"]);
+            settings.apply(&mut cli);
+            assert!(cli.validate().is_ok(), "{command} should be valid");
+        }
+    }
+
+    #[test]
+    fn test_flags_override_settings() {
+        let settings = ClientSettings {
+            output: Some(CliOutputMode::Clipboard),
+            wait_timeout: Some(10),
+            ..Default::default()
+        };
+        let mut cli = Cli::parse_from([
+            "handsfreectl",
+            "start",
+            "--output",
+            "keyboard",
+            "--wait-timeout",
+            "3",
+        ]);
+        settings.apply(&mut cli);
+
+        assert_eq!(cli.wait_timeout, Some(3));
+        match cli.command {
+            Commands::Start { output, .. } => assert_eq!(output, [CliOutputMode::Keyboard]),
+            _ => panic!("Expected Start command"),
+        }
+    }
+}
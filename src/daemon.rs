@@ -1,19 +1,288 @@
-use crate::protocol::{DaemonCommand, DaemonResponse};
+use crate::capture::{self, Direction};
+use crate::cli::{CliOutputMode, Framing};
+use crate::protocol::{
+    CommandEnvelope, DaemonCommand, DaemonResponse, DaemonStatus, ResponseEnvelope,
+};
 use anyhow::{Context, Result};
+use futures::{Stream, StreamExt, ready};
 use log::{debug, warn};
+use nix::sys::socket::{MsgFlags, send};
 use nix::unistd::getuid;
+use std::borrow::BorrowMut;
 use std::env;
+use std::fmt;
 use std::fs;
+use std::io;
+use std::num::NonZeroUsize;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context as TaskContext, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
 use tokio::net::UnixStream;
 use tokio::time::timeout;
 
-const READ_TIMEOUT_SECS: u64 = 5; // Timeout for waiting for response
+/// How long to wait for a response unless the caller says otherwise.
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait before the first connection retry unless the caller says otherwise.
+pub const DEFAULT_CONNECT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Longest wait between connection attempts, however often they have doubled.
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Longest response line accepted, in bytes, unless the caller says otherwise.
+pub const DEFAULT_MAX_LINE_LEN: NonZeroUsize = NonZeroUsize::new(1024 * 1024).unwrap();
+
+/// An `Error` response sent by the daemon.
+#[derive(Debug, thiserror::Error)]
+#[error("Daemon Error: {0}")]
+pub struct DaemonReportedError(pub String);
+
+/// A socket file that refuses connections, as one left behind by a daemon
+/// that crashed does.
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "Connection refused at {}; the socket may be stale (left behind by a crashed daemon) and can be removed with `rm {}`",
+    .path.display(),
+    .path.display()
+)]
+pub struct StaleSocketError {
+    pub path: PathBuf,
+    #[source]
+    pub source: io::Error,
+}
+
+/// Context for any failure to connect to the daemon socket at the given
+/// path, telling it apart from failures with local files, which end in an
+/// `io::Error` too.
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to connect to daemon socket at {0:?}")]
+pub struct ConnectError(pub PathBuf);
+
+/// A failure exchanging messages with the daemon over an open connection.
+#[derive(Debug, thiserror::Error)]
+pub enum DaemonError {
+    /// No response arrived within the read timeout
+    #[error("Read timeout after {} seconds while waiting for response.", .0.as_secs_f64())]
+    Timeout(Duration),
+    /// The daemon hung up before sending a response
+    #[error("Connection closed by daemon while waiting for response.")]
+    ConnectionClosed,
+    /// Reading from or writing to the socket failed while doing `action`
+    #[error("{action}")]
+    Io {
+        action: &'static str,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to serialize command")]
+    Serialize(#[source] serde_json::Error),
+    /// A line that isn't a valid response
+    #[error("Failed to deserialize daemon response '{raw}'")]
+    Deserialize {
+        raw: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// An answer to someone else's request
+    #[error("Response trace id '{received}' does not match '{sent}'")]
+    TraceIdMismatch { received: String, sent: String },
+    /// An answer to a different command sent on the same connection
+    #[error("Response id {received} does not match request id {sent}")]
+    RequestIdMismatch { received: u64, sent: u64 },
+    #[error("Received empty response line from daemon.")]
+    EmptyResponse,
+    /// A response line or frame longer than the limit set with
+    /// [`DaemonClient::with_max_line_len`]
+    #[error("Response line exceeds the maximum length of {0} bytes")]
+    LineTooLong(usize),
+}
+
+impl DaemonError {
+    fn io(action: &'static str) -> impl FnOnce(io::Error) -> Self {
+        move |source| DaemonError::Io { action, source }
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, DaemonError::Timeout(_))
+    }
+
+    /// Whether the connection was dropped mid-request.
+    pub fn is_connection_drop(&self) -> bool {
+        match self {
+            DaemonError::ConnectionClosed => true,
+            DaemonError::Io { source, .. } => matches!(
+                source.kind(),
+                io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// Writes `payload` as one length-prefixed frame: its length as a
+/// big-endian u32, followed by the bytes themselves.
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Frame too large"))?;
+    writer.write_all(&len.to_be_bytes()).await?;
+    writer.write_all(payload).await
+}
+
+/// Reads one frame written by [`write_frame`], or None if the stream ends
+/// before it starts. Frames longer than `max_len` bytes are rejected
+/// without reading their payload.
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_len: usize,
+) -> Result<Option<Vec<u8>>, DaemonError> {
+    let mut header = [0; 4];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(DaemonError::io("Failed to read response from daemon")(e)),
+    }
+    let len = frame_len(&header);
+    if len > max_len {
+        return Err(DaemonError::LineTooLong(max_len));
+    }
+    let mut payload = vec![0; len];
+    reader
+        .read_exact(&mut payload)
+        .await
+        .map_err(DaemonError::io("Failed to read response from daemon"))?;
+    Ok(Some(payload))
+}
+
+/// Payload length announced by a frame header.
+fn frame_len(header: &[u8]) -> usize {
+    u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize
+}
+
+// Id of the next request sent with `send_command`
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+fn next_request_id() -> u64 {
+    NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Serialize a command as it is written to the socket, without the newline
+/// or a request id, carrying `trace_id` if given.
+pub fn encode_command(
+    command: &DaemonCommand,
+    trace_id: Option<&str>,
+) -> Result<String, DaemonError> {
+    encode_command_with(command, trace_id, None)
+}
 
-/// Get the path to the daemon's Unix domain socket, matching daemon defaults.
-pub fn get_socket_path() -> Result<PathBuf> {
-    if let Ok(runtime_dir_str) = env::var("XDG_RUNTIME_DIR") {
+fn encode_command_with(
+    command: &DaemonCommand,
+    trace_id: Option<&str>,
+    id: Option<u64>,
+) -> Result<String, DaemonError> {
+    serde_json::to_string(&CommandEnvelope {
+        command,
+        trace_id,
+        id,
+    })
+    .map_err(DaemonError::Serialize)
+}
+
+/// Parse a response line. A response carrying a different trace id or
+/// request id than the one we sent is an answer to someone else's request.
+/// Responses without ids, from daemons that don't echo them, are accepted.
+fn decode_response_with(
+    line: &str,
+    trace_id: Option<&str>,
+    id: Option<u64>,
+) -> Result<DaemonResponse, DaemonError> {
+    let envelope: ResponseEnvelope =
+        serde_json::from_str(line).map_err(|source| DaemonError::Deserialize {
+            raw: line.to_string(),
+            source,
+        })?;
+    match (envelope.trace_id, trace_id) {
+        (Some(received), Some(sent)) if received != sent => {
+            return Err(DaemonError::TraceIdMismatch {
+                received,
+                sent: sent.to_string(),
+            });
+        }
+        _ => {}
+    }
+    match (envelope.id, id) {
+        (Some(received), Some(sent)) if received != sent => {
+            Err(DaemonError::RequestIdMismatch { received, sent })
+        }
+        _ => Ok(envelope.response),
+    }
+}
+
+/// First `len` bytes of `line`, with control characters and quotes escaped.
+fn response_preview(line: &str, len: usize) -> String {
+    let bytes = &line.as_bytes()[..len.min(line.len())];
+    String::from_utf8_lossy(bytes).escape_debug().to_string()
+}
+
+/// Logs the first `len` bytes of `line` at debug level; 0 logs nothing.
+fn log_response_preview(line: &str, len: usize) {
+    if len > 0 {
+        debug!(
+            "Response preview ({} of {} bytes): {}",
+            len.min(line.len()),
+            line.len(),
+            response_preview(line, len)
+        );
+    }
+}
+
+/// Where the socket path in use came from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SocketSource {
+    /// Given with `--socket`
+    Flag,
+    /// Set in the config file
+    ConfigFile,
+    /// Under `$XDG_RUNTIME_DIR/handsfree`
+    RuntimeDir,
+    /// The `/tmp` fallback, because `XDG_RUNTIME_DIR` is not set
+    RuntimeDirUnset,
+    /// The `/tmp` fallback, because the directory under `XDG_RUNTIME_DIR`
+    /// could not be created
+    RuntimeDirUnusable(String),
+}
+
+impl fmt::Display for SocketSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SocketSource::Flag => write!(f, "from --socket"),
+            SocketSource::ConfigFile => write!(f, "from the config file"),
+            SocketSource::RuntimeDir => write!(f, "from XDG_RUNTIME_DIR"),
+            SocketSource::RuntimeDirUnset => {
+                write!(f, "fallback in /tmp because XDG_RUNTIME_DIR is not set")
+            }
+            SocketSource::RuntimeDirUnusable(reason) => write!(
+                f,
+                "fallback in /tmp because the XDG_RUNTIME_DIR directory could not be created: {}",
+                reason
+            ),
+        }
+    }
+}
+
+/// Get the path to the daemon's Unix domain socket, matching daemon defaults,
+/// along with which of the defaults was used.
+pub fn get_socket_path() -> Result<(PathBuf, SocketSource)> {
+    let source = if let Ok(runtime_dir_str) = env::var("XDG_RUNTIME_DIR") {
         let runtime_dir = PathBuf::from(runtime_dir_str);
         let socket_dir = runtime_dir.join("handsfree");
 
@@ -22,7 +291,7 @@ pub fn get_socket_path() -> Result<PathBuf> {
             Ok(_) => {
                 let socket_path = socket_dir.join("daemon.sock");
                 debug!("Using socket path: {:?}", socket_path);
-                return Ok(socket_path);
+                return Ok((socket_path, SocketSource::RuntimeDir));
             }
             Err(e) => {
                 warn!(
@@ -30,165 +299,882 @@ pub fn get_socket_path() -> Result<PathBuf> {
                     socket_dir.display(),
                     e
                 );
-                // Fall through to /tmp fallback
+                SocketSource::RuntimeDirUnusable(e.to_string())
             }
         }
     } else {
         warn!("Warning: XDG_RUNTIME_DIR not set. Falling back to /tmp.");
-        // Fall through to /tmp fallback
+        SocketSource::RuntimeDirUnset
+    };
+
+    Ok((fallback_socket_path(), source))
+}
+
+/// Like [`get_socket_path`], but never creates the runtime directory and
+/// refuses to fall back to `/tmp` when something other than a directory is
+/// in its place, so a corrupted runtime dir is reported instead of hidden.
+pub fn get_socket_path_no_create() -> Result<(PathBuf, SocketSource)> {
+    let Ok(runtime_dir_str) = env::var("XDG_RUNTIME_DIR") else {
+        warn!("Warning: XDG_RUNTIME_DIR not set. Falling back to /tmp.");
+        return Ok((fallback_socket_path(), SocketSource::RuntimeDirUnset));
+    };
+
+    let socket_dir = PathBuf::from(runtime_dir_str).join("handsfree");
+    if let Ok(metadata) = fs::metadata(&socket_dir)
+        && !metadata.is_dir()
+    {
+        return Err(anyhow::anyhow!(
+            "expected a directory at {} but found a file",
+            socket_dir.display()
+        ));
     }
 
-    // Fallback logic: use uid-specific socket in /tmp
+    let socket_path = socket_dir.join("daemon.sock");
+    debug!("Using socket path: {:?}", socket_path);
+    Ok((socket_path, SocketSource::RuntimeDir))
+}
+
+/// The name of an abstract-namespace socket, given as `@name` in place of
+/// a path, or `None` for a socket in the filesystem.
+pub fn abstract_socket_name(socket_path: &Path) -> Option<&[u8]> {
+    socket_path.as_os_str().as_bytes().strip_prefix(b"@")
+}
+
+/// Checks that the directory holding a user-supplied socket path exists, so
+/// a typo is reported as such rather than as a failed connection.
+pub fn check_socket_dir(socket_path: &Path) -> Result<()> {
+    if abstract_socket_name(socket_path).is_some() {
+        return Ok(());
+    }
+    match socket_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() && !dir.is_dir() => Err(anyhow::anyhow!(
+            "Socket directory {} does not exist",
+            dir.display()
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Fallback logic: use uid-specific socket in /tmp
+fn fallback_socket_path() -> PathBuf {
     let uid = getuid();
     let socket_path = PathBuf::from(format!("/tmp/handsfree-{}.sock", uid));
     debug!("Using fallback socket path: {:?}", socket_path);
-    Ok(socket_path)
+    socket_path
+}
+
+/// Connects to an abstract-namespace socket. Connecting to a local socket
+/// doesn't block, so the standard library's blocking connect is fine.
+#[cfg(target_os = "linux")]
+fn connect_abstract(name: &[u8]) -> io::Result<UnixStream> {
+    use std::os::linux::net::SocketAddrExt;
+
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    let stream = std::os::unix::net::UnixStream::connect_addr(&addr)?;
+    stream.set_nonblocking(true)?;
+    UnixStream::from_std(stream)
 }
 
-/// Connect to the daemon's Unix domain socket
+#[cfg(not(target_os = "linux"))]
+fn connect_abstract(_name: &[u8]) -> io::Result<UnixStream> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "abstract sockets (@name) are only supported on Linux",
+    ))
+}
+
+/// Connect to the daemon's Unix domain socket, or to the abstract socket
+/// `@name` on Linux. Failures carry a [`ConnectError`] context.
 pub async fn connect_to_daemon(socket_path: &Path) -> Result<UnixStream> {
-    match UnixStream::connect(socket_path).await {
-        Ok(stream) => {
-            debug!("Successfully connected to daemon at {:?}", socket_path);
-            Ok(stream)
-        }
-        Err(e) => Err(e.into()),
-    }
+    let stream = connect_socket(socket_path)
+        .await
+        .with_context(|| ConnectError(socket_path.to_path_buf()))?;
+    debug!("Successfully connected to daemon at {:?}", socket_path);
+    capture::record(Direction::Connect, &socket_path.to_string_lossy(), false);
+    Ok(stream)
 }
 
-/// Reads and deserializes a JSON response line from the daemon stream.
-pub async fn receive_response(stream: &mut UnixStream) -> Result<DaemonResponse> {
-    let mut reader = BufReader::new(stream);
-    let mut response_json = String::new();
+async fn connect_socket(socket_path: &Path) -> Result<UnixStream> {
+    if let Some(name) = abstract_socket_name(socket_path) {
+        return Ok(connect_abstract(name)?);
+    }
 
-    // Add a timeout for reading the response
-    match timeout(
-        std::time::Duration::from_secs(READ_TIMEOUT_SECS),
-        reader.read_line(&mut response_json),
-    )
-    .await
+    // A leftover regular file would otherwise only give "connection refused"
+    if let Ok(metadata) = fs::metadata(socket_path)
+        && !metadata.file_type().is_socket()
     {
-        Ok(Ok(0)) | Err(_) => {
-            // Ok(Ok(0)) -> EOF, Err(_) -> TimeoutError
-            if response_json.is_empty() {
-                Err(anyhow::anyhow!(
-                    "Connection closed by daemon or timeout after {} seconds while waiting for response.",
-                    READ_TIMEOUT_SECS
-                ))
-            } else {
-                // Timeout occurred but maybe we read something? Less likely with read_line
-                Err(anyhow::anyhow!(
-                    "Timeout after {} seconds reading response line.",
-                    READ_TIMEOUT_SECS
-                ))
+        return Err(io::Error::other(format!(
+            "{} is not a socket; remove it or pass --socket",
+            socket_path.display()
+        ))
+        .into());
+    }
+
+    match UnixStream::connect(socket_path).await {
+        Ok(stream) => Ok(stream),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused && socket_path.exists() => {
+            Err(StaleSocketError {
+                path: socket_path.to_path_buf(),
+                source: e,
             }
+            .into())
         }
-        Ok(Ok(_)) => {
-            // Successfully read a line
-            let trimmed_response = response_json.trim_end_matches('\n');
-            if trimmed_response.is_empty() {
-                Err(anyhow::anyhow!("Received empty response line from daemon."))
-            } else {
-                serde_json::from_str::<DaemonResponse>(trimmed_response).context(format!(
-                    "Failed to deserialize daemon response '{}'",
-                    trimmed_response
-                ))
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Connects like [`connect_to_daemon`], making up to `attempts` tries while
+/// the socket is missing or refusing connections, as it is while the daemon
+/// starts up. Waits `base_delay` after the first failure, doubling the wait
+/// after each further one up to [`MAX_RETRY_DELAY`], and returns the last
+/// error if every attempt fails.
+pub async fn connect_with_retry(
+    socket_path: &Path,
+    attempts: u32,
+    base_delay: Duration,
+) -> Result<UnixStream> {
+    let mut delay = base_delay;
+    let mut attempt = 1;
+    loop {
+        match connect_to_daemon(socket_path).await {
+            Err(e) if attempt < attempts && is_not_listening(&e) => {
+                debug!(
+                    "Connection attempt {} of {} failed ({}), retrying in {:?}",
+                    attempt, attempts, e, delay
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+                attempt += 1;
             }
-        }
-        Ok(Err(e)) => {
-            // IO error from read_line
-            Err(anyhow::Error::new(e).context("Failed to read response from daemon"))
+            result => return result,
         }
     }
 }
 
-/// Send a command to the daemon and read its response
+/// Whether a connection failed because nothing is listening on the socket yet.
+fn is_not_listening(err: &anyhow::Error) -> bool {
+    has_io_error_kind(
+        err,
+        &[io::ErrorKind::NotFound, io::ErrorKind::ConnectionRefused],
+    )
+}
+
+/// Reads and deserializes a JSON response line from the daemon stream,
+/// giving up after `read_timeout`. A zero `read_timeout` waits indefinitely.
+/// Whatever the daemon sent after that line is lost with the read buffer;
+/// keep a [`DaemonClient`] to go on reading from the same connection.
+pub async fn receive_response(
+    stream: &mut UnixStream,
+    read_timeout: Duration,
+) -> Result<DaemonResponse, DaemonError> {
+    DaemonClient::from_stream(stream)
+        .with_read_timeout(read_timeout)
+        .next_response()
+        .await
+}
+
+/// Send a command to the daemon and read its response, waiting at most
+/// `read_timeout` for it (zero to wait indefinitely). Each command gets a
+/// new request id, which the response must echo if it carries one. As with
+/// [`receive_response`], anything sent after the response is lost.
 pub async fn send_command(
     stream: &mut UnixStream,
     command: &DaemonCommand,
-) -> Result<DaemonResponse> {
-    let command_json = serde_json::to_string(command).context("Failed to serialize command")?;
-    let command_json_with_newline = format!("{}\n", command_json);
-    debug!("Sending: {}", command_json_with_newline.trim()); // Trim newline for cleaner log
-
-    stream
-        .write_all(command_json_with_newline.as_bytes())
+    read_timeout: Duration,
+) -> Result<DaemonResponse, DaemonError> {
+    DaemonClient::from_stream(stream)
+        .with_read_timeout(read_timeout)
+        .send(command)
         .await
-        .context("Failed to write command to socket")?;
+}
 
-    stream.flush().await.context("Failed to flush socket")?;
+fn has_io_error_kind(err: &anyhow::Error, kinds: &[io::ErrorKind]) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(|io_err| kinds.contains(&io_err.kind()))
+}
 
-    debug!("Waiting for response...");
-    // Don't shutdown, we need to read the response
-    receive_response(stream).await
+/// Like [`DaemonClient::send_retrying`], replacing `stream` with the new
+/// connection to `socket_path` on reconnect.
+pub async fn send_command_retrying(
+    stream: &mut UnixStream,
+    socket_path: &Path,
+    command: &DaemonCommand,
+    timeout_retries: u32,
+    read_timeout: Duration,
+) -> Result<DaemonResponse> {
+    DaemonClient::from_stream(stream)
+        .with_socket_path(socket_path)
+        .with_read_timeout(read_timeout)
+        .send_retrying(command, timeout_retries)
+        .await
 }
 
 /// Serialize and send a command to the daemon without waiting for a response.
 /// Useful for commands like Subscribe where the response is a stream.
-pub async fn send_command_only(stream: &mut UnixStream, command: &DaemonCommand) -> Result<()> {
-    let command_json = serde_json::to_string(command).context("Failed to serialize command")?;
-    let command_json_with_newline = format!("{}\n", command_json);
-    debug!("Sending only: {}", command_json_with_newline.trim());
-
-    stream
-        .write_all(command_json_with_newline.as_bytes())
-        .await
-        .context("Failed to write command to socket")?;
-
-    stream.flush().await.context("Failed to flush socket")?;
-
-    Ok(())
+pub async fn send_command_only(
+    stream: &mut UnixStream,
+    command: &DaemonCommand,
+) -> Result<(), DaemonError> {
+    DaemonClient::from_stream(stream).send_only(command).await
 }
 
 /// A stream of responses from the daemon.
-/// Wraps the UnixStream and handles reading lines and deserializing JSON.
+/// Wraps the UnixStream and handles reading lines (or frames) and
+/// deserializing JSON. Blank lines and empty frames are skipped and the
+/// stream ends at EOF.
 pub struct ResponseStream {
     reader: BufReader<UnixStream>,
+    framing: Framing,
+    max_line_len: NonZeroUsize,
+    // Whether to print every line to stderr, as with `--raw`
+    raw: bool,
+    // Trace id of the client that subscribed, which events must echo
+    trace_id: Option<String>,
+    // Bytes of each line to log before parsing it (0 = off)
+    response_preview: usize,
+    // Whether we sent the Subscribe, and so owe the daemon an Unsubscribe
+    subscribed: bool,
+    // Bytes of the current line, or frame header and payload, read so far
+    line: Vec<u8>,
+    // Set after a line went over the length limit, until its end is skipped
+    discarding: bool,
+    // Bytes left to skip of a frame over the length limit
+    skip: usize,
 }
 
 impl ResponseStream {
+    /// Reads newline-terminated responses.
     pub fn new(stream: UnixStream) -> Self {
+        Self::with_framing(stream, Framing::Line)
+    }
+
+    pub fn with_framing(stream: UnixStream, framing: Framing) -> Self {
+        Self::from_reader(
+            BufReader::new(stream),
+            framing,
+            DEFAULT_MAX_LINE_LEN,
+            false,
+            None,
+            0,
+        )
+    }
+
+    /// Goes on reading from `reader`, including anything already buffered.
+    fn from_reader(
+        reader: BufReader<UnixStream>,
+        framing: Framing,
+        max_line_len: NonZeroUsize,
+        raw: bool,
+        trace_id: Option<String>,
+        response_preview: usize,
+    ) -> Self {
         Self {
-            reader: BufReader::new(stream),
+            reader,
+            framing,
+            max_line_len,
+            raw,
+            trace_id,
+            response_preview,
+            subscribed: false,
+            line: Vec::new(),
+            discarding: false,
+            skip: 0,
+        }
+    }
+
+    /// Parses a complete line, or returns None if it is blank.
+    fn parse_line(&self, line: &[u8]) -> Option<Result<DaemonResponse, DaemonError>> {
+        let line = match std::str::from_utf8(line) {
+            Ok(line) => line,
+            Err(e) => {
+                let e = io::Error::new(io::ErrorKind::InvalidData, e);
+                return Some(Err(DaemonError::io("IO Error")(e)));
+            }
+        };
+        log_response_preview(line, self.response_preview);
+        capture::record(Direction::Received, line.trim_end_matches('\n'), self.raw);
+        let trimmed = line.trim_end_matches('\n');
+        if trimmed.trim().is_empty() {
+            return None;
+        }
+        Some(decode_response_with(
+            trimmed,
+            self.trace_id.as_deref(),
+            None,
+        ))
+    }
+
+    /// Reads the next length-prefixed response.
+    fn poll_next_frame(
+        &mut self,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Option<Result<DaemonResponse, DaemonError>>> {
+        loop {
+            let available = match ready!(Pin::new(&mut self.reader).poll_fill_buf(cx)) {
+                Ok(available) => available,
+                Err(e) => return Poll::Ready(Some(Err(DaemonError::io("IO Error")(e)))),
+            };
+
+            if available.is_empty() {
+                // EOF; only an error if it cuts a frame short
+                if self.line.is_empty() && self.skip == 0 {
+                    return Poll::Ready(None);
+                }
+                self.line.clear();
+                self.skip = 0;
+                let e = io::Error::from(io::ErrorKind::UnexpectedEof);
+                return Poll::Ready(Some(Err(DaemonError::io("IO Error")(e))));
+            }
+
+            if self.skip > 0 {
+                // Drop the rest of an overlong frame, which was already reported
+                let len = self.skip.min(available.len());
+                Pin::new(&mut self.reader).consume(len);
+                self.skip -= len;
+                continue;
+            }
+
+            let needed = if self.line.len() < 4 {
+                4
+            } else {
+                4 + frame_len(&self.line)
+            };
+            let len = (needed - self.line.len()).min(available.len());
+            self.line.extend_from_slice(&available[..len]);
+            Pin::new(&mut self.reader).consume(len);
+            if self.line.len() < 4 {
+                continue;
+            }
+
+            let max_len = self.max_line_len.get();
+            let payload_len = frame_len(&self.line);
+            if payload_len > max_len {
+                self.line.clear();
+                self.skip = payload_len;
+                return Poll::Ready(Some(Err(DaemonError::LineTooLong(max_len))));
+            }
+            if self.line.len() == 4 + payload_len {
+                let frame = std::mem::take(&mut self.line);
+                if let Some(item) = self.parse_line(&frame[4..]) {
+                    return Poll::Ready(Some(item));
+                }
+            }
         }
     }
+}
+
+impl Drop for ResponseStream {
+    /// Tells the daemon the subscription is over so it can let go of it
+    /// without waiting to notice the hang-up. This is best-effort: drop
+    /// can't wait, so `Unsubscribe` is only sent if the socket takes it
+    /// right away, and failures are ignored. Streams wrapped around a
+    /// connection with [`ResponseStream::new`] leave that to their caller,
+    /// which may not have subscribed at all.
+    fn drop(&mut self) {
+        if !self.subscribed {
+            return;
+        }
+        let Ok(command_json) =
+            encode_command(&DaemonCommand::Unsubscribe, self.trace_id.as_deref())
+        else {
+            return;
+        };
+        let message = match self.framing {
+            Framing::Line => format!("{}\n", command_json).into_bytes(),
+            Framing::Length => {
+                let mut message = (command_json.len() as u32).to_be_bytes().to_vec();
+                message.extend_from_slice(command_json.as_bytes());
+                message
+            }
+        };
+        // Straight to the nonblocking socket: tokio's own try_write fails
+        // until the runtime has seen the socket become writable. A daemon
+        // that already hung up must not kill us with SIGPIPE.
+        let fd = self.reader.get_ref().as_raw_fd();
+        match send(fd, &message, MsgFlags::MSG_NOSIGNAL) {
+            Ok(written) if written == message.len() => {
+                capture::record(Direction::Sent, &command_json, self.raw);
+                debug!("Sent only: {}", command_json);
+            }
+            _ => debug!("Could not send unsubscribe, closing anyway"),
+        }
+    }
+}
+
+impl Stream for ResponseStream {
+    type Item = Result<DaemonResponse, DaemonError>;
 
-    /// Next response from the stream.
-    /// Returns None on EOF.
-    pub async fn next(&mut self) -> Option<Result<DaemonResponse>> {
-        let mut line = String::new();
+    fn poll_next(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.framing == Framing::Length {
+            return this.poll_next_frame(cx);
+        }
         loop {
-            line.clear();
-            match self.reader.read_line(&mut line).await {
-                Ok(0) => return None, // EOF
-                Ok(_) => {
-                    let trimmed = line.trim_end_matches('\n');
-                    if trimmed.trim().is_empty() {
-                        continue;
+            let available = match ready!(Pin::new(&mut this.reader).poll_fill_buf(cx)) {
+                Ok(available) => available,
+                Err(e) => return Poll::Ready(Some(Err(DaemonError::io("IO Error")(e)))),
+            };
+
+            let newline = available.iter().position(|&b| b == b'\n');
+            if this.discarding {
+                // Drop the rest of an overlong line, which was already reported
+                if available.is_empty() {
+                    return Poll::Ready(None);
+                }
+                let len = newline.map_or(available.len(), |end| end + 1);
+                Pin::new(&mut this.reader).consume(len);
+                this.discarding = newline.is_none();
+                continue;
+            }
+
+            let max_len = this.max_line_len.get();
+            if this.line.len() + newline.unwrap_or(available.len()) > max_len {
+                let len = newline.map_or(available.len(), |end| end + 1);
+                Pin::new(&mut this.reader).consume(len);
+                this.line.clear();
+                this.discarding = newline.is_none();
+                return Poll::Ready(Some(Err(DaemonError::LineTooLong(max_len))));
+            }
+
+            let line = if available.is_empty() {
+                // EOF; a final line without a newline still counts
+                if this.line.is_empty() {
+                    return Poll::Ready(None);
+                }
+                std::mem::take(&mut this.line)
+            } else if let Some(end) = newline {
+                this.line.extend_from_slice(&available[..=end]);
+                Pin::new(&mut this.reader).consume(end + 1);
+                std::mem::take(&mut this.line)
+            } else {
+                let len = available.len();
+                this.line.extend_from_slice(available);
+                Pin::new(&mut this.reader).consume(len);
+                continue;
+            };
+
+            if let Some(item) = this.parse_line(&line) {
+                return Poll::Ready(Some(item));
+            }
+        }
+    }
+}
+
+/// Wait until the daemon reports the `target` state (case-insensitive), as
+/// [`DaemonClient::wait_for_state`] does.
+pub async fn wait_for_state(
+    stream: UnixStream,
+    target: &str,
+    limit: Duration,
+) -> Result<DaemonStatus> {
+    DaemonClient::from_stream(stream)
+        .wait_for_state(target, limit)
+        .await
+}
+
+/// A connection to the daemon with a method per command, for programs that
+/// embed the client. Error responses from the daemon are returned as
+/// [`DaemonReportedError`], and any response of the wrong kind as an error.
+/// The free functions of this module each run one exchange through one.
+pub struct DaemonClient<S = UnixStream> {
+    // Kept for the whole connection, so nothing read ahead of a response
+    // is lost before the next one is read
+    reader: BufReader<S>,
+    // Where to reconnect to when a retried command's connection drops
+    socket_path: Option<PathBuf>,
+    read_timeout: Duration,
+    framing: Framing,
+    max_line_len: NonZeroUsize,
+    // Whether to print every line to stderr, as with `--raw`
+    raw: bool,
+    // Sent with every command, for correlating with the caller's logs
+    trace_id: Option<String>,
+    // Bytes of each response line to log before parsing it (0 = off)
+    response_preview: usize,
+    // Id of the last request sent, which the responses read after it answer
+    request_id: Option<u64>,
+}
+
+impl DaemonClient {
+    /// Connects to the daemon listening on `socket_path`.
+    pub async fn connect(socket_path: &Path) -> Result<Self> {
+        let stream = connect_to_daemon(socket_path).await?;
+        Ok(Self::from_stream(stream).with_socket_path(socket_path))
+    }
+
+    /// Subscribes to state changes. The connection is dedicated to the
+    /// subscription from then on, so this consumes the client.
+    pub async fn subscribe(mut self) -> Result<ResponseStream> {
+        self.send_only(&DaemonCommand::Subscribe).await?;
+        let mut stream = ResponseStream::from_reader(
+            self.reader,
+            self.framing,
+            self.max_line_len,
+            self.raw,
+            self.trace_id,
+            self.response_preview,
+        );
+        stream.subscribed = true;
+        Ok(stream)
+    }
+
+    /// Wait until the daemon reports the `target` state (case-insensitive).
+    /// Checks the current status first, then subscribes and follows state
+    /// changes on the same connection. Fails if the state isn't reached
+    /// within `limit` or the daemon closes the stream first.
+    pub async fn wait_for_state(mut self, target: &str, limit: Duration) -> Result<DaemonStatus> {
+        let wait = async {
+            // The overall limit below bounds this read
+            self.read_timeout = Duration::ZERO;
+            match self.send(&DaemonCommand::Status).await? {
+                DaemonResponse::Status { status }
+                    if status.state.as_str().eq_ignore_ascii_case(target) =>
+                {
+                    return Ok(status);
+                }
+                DaemonResponse::Error { message } => {
+                    return Err(DaemonReportedError(message).into());
+                }
+                _ => {}
+            }
+
+            let mut response_stream = self.subscribe().await?;
+            while let Some(result) = response_stream.next().await {
+                match result? {
+                    DaemonResponse::StateChange { status } | DaemonResponse::Status { status }
+                        if status.state.as_str().eq_ignore_ascii_case(target) =>
+                    {
+                        return Ok(status);
+                    }
+                    DaemonResponse::Error { message } => {
+                        return Err(DaemonReportedError(message).into());
                     }
-                    return Some(
-                        serde_json::from_str::<DaemonResponse>(trimmed)
-                            .context(format!("Failed to deserialize: (Line: {})", trimmed)),
-                    );
+                    _ => {}
+                }
+            }
+            Err(anyhow::anyhow!(
+                "Connection closed before daemon reached state '{}'",
+                target
+            ))
+        };
+
+        match timeout(limit, wait).await {
+            Ok(result) => result,
+            // As an io::Error so it is classified as a timeout
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "Timed out after {} seconds waiting for daemon to reach state '{}'",
+                    limit.as_secs(),
+                    target
+                ),
+            )
+            .into()),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> DaemonClient<S> {
+    /// Wraps an existing connection.
+    pub fn from_stream(stream: S) -> Self {
+        Self {
+            reader: BufReader::new(stream),
+            socket_path: None,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            framing: Framing::Line,
+            max_line_len: DEFAULT_MAX_LINE_LEN,
+            raw: false,
+            trace_id: None,
+            response_preview: 0,
+            request_id: None,
+        }
+    }
+
+    /// Sets how long to wait for each response (zero waits indefinitely).
+    pub fn with_read_timeout(mut self, read_timeout: Duration) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    /// Sets the socket to reconnect to when [`DaemonClient::send_retrying`]
+    /// loses the connection. Set by [`DaemonClient::connect`].
+    pub fn with_socket_path(mut self, socket_path: &Path) -> Self {
+        self.socket_path = Some(socket_path.to_path_buf());
+        self
+    }
+
+    /// Delimits commands and responses with `framing` rather than newlines,
+    /// here and on the [`ResponseStream`] of a subscription.
+    pub fn with_framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Rejects response lines (or frames) longer than `len` bytes, not
+    /// counting the newline, instead of buffering them, so a misbehaving
+    /// daemon can't exhaust memory.
+    pub fn with_max_line_len(mut self, len: NonZeroUsize) -> Self {
+        self.max_line_len = len;
+        self
+    }
+
+    /// Prints every line sent (prefixed with `>>`) or received (`<<`) to
+    /// stderr verbatim, regardless of the log level.
+    pub fn with_raw(mut self, raw: bool) -> Self {
+        self.raw = raw;
+        self
+    }
+
+    /// Attaches `id` to every command sent, here and on the
+    /// [`ResponseStream`] of a subscription. Responses carrying a different
+    /// trace id are rejected as answers to someone else's request.
+    pub fn with_trace_id(mut self, id: impl Into<String>) -> Self {
+        self.trace_id = Some(id.into());
+        self
+    }
+
+    /// Logs the first `len` bytes of every raw response line at debug level,
+    /// before it is parsed, here and on the [`ResponseStream`] of a
+    /// subscription. A `len` of 0 turns the preview off.
+    pub fn with_response_preview(mut self, len: usize) -> Self {
+        self.response_preview = len;
+        self
+    }
+
+    /// Sends `command` and reads the response, which must echo the new
+    /// request id the command is sent with if it carries one.
+    pub async fn send(&mut self, command: &DaemonCommand) -> Result<DaemonResponse, DaemonError> {
+        let id = next_request_id();
+        self.request_id = Some(id);
+        let command_json = encode_command_with(command, self.trace_id.as_deref(), Some(id))?;
+        debug!("Sending: {}", command_json);
+        self.write_command(&command_json).await?;
+
+        debug!("Waiting for response...");
+        // Don't shutdown, we need to read the response
+        self.next_response().await
+    }
+
+    /// Reads another response to the last command sent, e.g. the answer
+    /// following a progress report. Like the first, it must echo the
+    /// command's request id if it carries one.
+    pub async fn next_response(&mut self) -> Result<DaemonResponse, DaemonError> {
+        let read_timeout = self.read_timeout;
+        let read = self.read_message();
+        let message = if read_timeout.is_zero() {
+            read.await
+        } else {
+            timeout(read_timeout, read)
+                .await
+                .map_err(|_| DaemonError::Timeout(read_timeout))?
+        };
+        // EOF before a response
+        let message = message?.ok_or(DaemonError::ConnectionClosed)?;
+        let response_json = String::from_utf8(message)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            .map_err(DaemonError::io("Failed to read response from daemon"))?;
+        log_response_preview(&response_json, self.response_preview);
+        let response_json = response_json.trim_end_matches('\n');
+        capture::record(Direction::Received, response_json, self.raw);
+        if response_json.is_empty() {
+            Err(DaemonError::EmptyResponse)
+        } else {
+            decode_response_with(response_json, self.trace_id.as_deref(), self.request_id)
+        }
+    }
+
+    /// Sends `command` without waiting for a response, e.g. for Subscribe,
+    /// which is answered with a stream.
+    pub async fn send_only(&mut self, command: &DaemonCommand) -> Result<(), DaemonError> {
+        let command_json = encode_command(command, self.trace_id.as_deref())?;
+        debug!("Sending only: {}", command_json);
+        self.write_command(&command_json).await
+    }
+
+    /// Reads the next line, with its newline, or frame payload, or None at
+    /// EOF before one starts.
+    async fn read_message(&mut self) -> Result<Option<Vec<u8>>, DaemonError> {
+        let max_len = self.max_line_len.get();
+        if self.framing == Framing::Length {
+            return read_frame(&mut self.reader, max_len).await;
+        }
+        // One byte over the limit is enough to tell the line is too long
+        let mut line = Vec::new();
+        match (&mut self.reader)
+            .take(max_len as u64 + 1)
+            .read_until(b'\n', &mut line)
+            .await
+        {
+            Ok(0) => Ok(None),
+            Ok(_) if line.len() > max_len && !line.ends_with(b"\n") => {
+                Err(DaemonError::LineTooLong(max_len))
+            }
+            Ok(_) => Ok(Some(line)),
+            Err(e) => Err(DaemonError::io("Failed to read response from daemon")(e)),
+        }
+    }
+
+    /// Writes a serialized command to the daemon in our framing and flushes it.
+    async fn write_command(&mut self, command_json: &str) -> Result<(), DaemonError> {
+        let stream = self.reader.get_mut();
+        let written = match self.framing {
+            Framing::Line => {
+                stream
+                    .write_all(format!("{}\n", command_json).as_bytes())
+                    .await
+            }
+            Framing::Length => write_frame(stream, command_json.as_bytes()).await,
+        };
+        written.map_err(DaemonError::io("Failed to write command to socket"))?;
+        capture::record(Direction::Sent, command_json, self.raw);
+
+        stream
+            .flush()
+            .await
+            .map_err(DaemonError::io("Failed to flush socket"))
+    }
+
+    pub async fn status(&mut self) -> Result<DaemonStatus> {
+        match self.request(&DaemonCommand::Status).await? {
+            DaemonResponse::Status { status } => Ok(status),
+            other => Err(unexpected_response(&DaemonCommand::Status, other)),
+        }
+    }
+
+    /// Starts transcribing to all of `output_modes`.
+    pub async fn start(&mut self, output_modes: Vec<CliOutputMode>) -> Result<()> {
+        self.expect_ack(&DaemonCommand::start(output_modes)).await
+    }
+
+    pub async fn stop(&mut self) -> Result<()> {
+        self.expect_ack(&DaemonCommand::Stop).await
+    }
+
+    /// Starts with `output_modes` if idle, stops otherwise. Without output
+    /// modes the daemon uses its default.
+    pub async fn toggle(&mut self, output_modes: Vec<CliOutputMode>) -> Result<()> {
+        self.expect_ack(&DaemonCommand::Toggle {
+            output_modes,
+            output_path: None,
+            append: true,
+            fallback_output: None,
+        })
+        .await
+    }
+
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.expect_ack(&DaemonCommand::Shutdown).await
+    }
+
+    /// Pings the daemon, which answers with an `Ack`, and returns how long
+    /// the answer took to arrive.
+    pub async fn ping(&mut self) -> Result<Duration> {
+        let sent = Instant::now();
+        self.expect_ack(&DaemonCommand::Ping).await?;
+        Ok(sent.elapsed())
+    }
+
+    /// Gives back the underlying connection. Anything read from it but
+    /// not yet returned as a response is lost.
+    pub fn into_stream(self) -> S {
+        self.reader.into_inner()
+    }
+
+    async fn request(&mut self, command: &DaemonCommand) -> Result<DaemonResponse> {
+        let mut response = self.send(command).await?;
+        // Progress reports precede the answer to slow commands like Start
+        while let DaemonResponse::Progress { stage, .. } = &response {
+            debug!("Progress: {}", stage);
+            response = self.next_response().await?;
+        }
+        match response {
+            DaemonResponse::Error { message } => Err(DaemonReportedError(message).into()),
+            response => Ok(response),
+        }
+    }
+
+    async fn expect_ack(&mut self, command: &DaemonCommand) -> Result<()> {
+        match self.request(command).await? {
+            DaemonResponse::Ack => Ok(()),
+            other => Err(unexpected_response(command, other)),
+        }
+    }
+}
+
+impl<S: BorrowMut<UnixStream> + AsyncRead + AsyncWrite + Unpin> DaemonClient<S> {
+    /// Send a command and read its response, resending it on a new connection
+    /// once if the connection drops, and up to `timeout_retries` times if the
+    /// response times out. Only idempotent commands are resent; for anything
+    /// else the original error is returned so side effects are never duplicated.
+    /// Without a socket path to reconnect to, the error is returned too.
+    pub async fn send_retrying(
+        &mut self,
+        command: &DaemonCommand,
+        timeout_retries: u32,
+    ) -> Result<DaemonResponse> {
+        let mut reconnected_after_drop = false;
+        let mut timeout_retries_left = timeout_retries;
+        loop {
+            match self.send(command).await {
+                Err(e)
+                    if command.is_idempotent()
+                        && self.socket_path.is_some()
+                        && !reconnected_after_drop
+                        && e.is_connection_drop() =>
+                {
+                    warn!("Connection to daemon dropped ({}), reconnecting", e);
+                    reconnected_after_drop = true;
+                }
+                Err(e)
+                    if command.is_idempotent()
+                        && self.socket_path.is_some()
+                        && timeout_retries_left > 0
+                        && e.is_timeout() =>
+                {
+                    timeout_retries_left -= 1;
+                    warn!("{} Retrying ({} retries left)", e, timeout_retries_left);
                 }
-                Err(e) => return Some(Err(anyhow::Error::new(e).context("IO Error"))),
+                result => return Ok(result?),
+            }
+            // Resend on a fresh connection so a late reply to the first attempt
+            // can't be mistaken for the answer to the retry
+            if let Some(socket_path) = &self.socket_path {
+                *self.reader.get_mut().borrow_mut() = connect_to_daemon(socket_path).await?;
+                let buffered = self.reader.buffer().len();
+                Pin::new(&mut self.reader).consume(buffered);
             }
         }
     }
 }
 
+fn unexpected_response(command: &DaemonCommand, response: DaemonResponse) -> anyhow::Error {
+    anyhow::anyhow!("Unexpected response to {:?}: {:?}", command, response)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::cli::CliOutputMode;
+    use crate::protocol::DaemonState;
+    use crate::testutil::MockDaemon;
     use nix::unistd::getuid;
+    use serde_json::json;
     use std::fs;
     use std::os::unix::fs::PermissionsExt;
-    use std::time::Duration;
+    use std::sync::Mutex;
     use tempfile;
     use tokio::io::AsyncReadExt;
     use tokio::net::UnixListener;
 
+    // Tests touching XDG_RUNTIME_DIR hold this so they don't race each other
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    fn lock_env() -> std::sync::MutexGuard<'static, ()> {
+        ENV_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
     // Helper struct for tests to manage environment variables temporarily
     struct EnvVarGuard {
         key: String,
@@ -223,8 +1209,69 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_encode_command_with_trace_id() {
+        assert_eq!(
+            encode_command_with(&DaemonCommand::Stop, Some("abc-123"), None).unwrap(),
+            r#"{"command":"stop","trace_id":"abc-123"}"#
+        );
+        assert_eq!(
+            encode_command_with(&DaemonCommand::Stop, None, None).unwrap(),
+            r#"{"command":"stop"}"#
+        );
+        assert_eq!(
+            encode_command_with(&DaemonCommand::Stop, Some("abc-123"), Some(7)).unwrap(),
+            r#"{"command":"stop","trace_id":"abc-123","id":7}"#
+        );
+    }
+
+    #[test]
+    fn test_decode_response_matches_trace_id() {
+        let line = r#"{"response_type":"ack","trace_id":"abc-123"}"#;
+        assert_eq!(
+            decode_response_with(line, Some("abc-123"), None).unwrap(),
+            DaemonResponse::Ack
+        );
+        // Daemons that don't echo the id are still understood
+        assert_eq!(
+            decode_response_with(r#"{"response_type":"ack"}"#, Some("abc-123"), None).unwrap(),
+            DaemonResponse::Ack
+        );
+
+        let err = decode_response_with(line, Some("other"), None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Response trace id 'abc-123' does not match 'other'"
+        );
+    }
+
+    #[test]
+    fn test_decode_response_matches_request_id() {
+        let line = r#"{"response_type":"ack","id":3}"#;
+        assert_eq!(
+            decode_response_with(line, None, Some(3)).unwrap(),
+            DaemonResponse::Ack
+        );
+        // Older daemons don't echo ids, and events carry none
+        assert_eq!(
+            decode_response_with(r#"{"response_type":"ack"}"#, None, Some(3)).unwrap(),
+            DaemonResponse::Ack
+        );
+
+        let err = decode_response_with(line, None, Some(4)).unwrap_err();
+        assert!(matches!(
+            err,
+            DaemonError::RequestIdMismatch {
+                received: 3,
+                sent: 4
+            }
+        ));
+        assert_eq!(err.to_string(), "Response id 3 does not match request id 4");
+    }
+
     #[test]
     fn test_get_socket_path_success() {
+        let _lock = lock_env();
         // Mock XDG_RUNTIME_DIR for this test
         let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
         let temp_path = temp_dir.path();
@@ -241,7 +1288,10 @@ mod tests {
 
         let expected_path = temp_path.join("handsfree").join("daemon.sock");
         match get_socket_path() {
-            Ok(path) => assert_eq!(path, expected_path),
+            Ok((path, source)) => {
+                assert_eq!(path, expected_path);
+                assert_eq!(source, SocketSource::RuntimeDir);
+            }
             Err(e) => panic!("get_socket_path failed unexpectedly: {}", e),
         }
 
@@ -258,6 +1308,7 @@ mod tests {
 
     #[test]
     fn test_get_socket_path_no_xdg_runtime_dir() {
+        let _lock = lock_env();
         // Temporarily remove the var for this test's scope if set externally
         let _env_guard = EnvVarGuard::new("XDG_RUNTIME_DIR");
 
@@ -266,13 +1317,17 @@ mod tests {
         let expected_path = PathBuf::from(format!("/tmp/handsfree-{}.sock", uid));
 
         match get_socket_path() {
-            Ok(path) => assert_eq!(path, expected_path),
+            Ok((path, source)) => {
+                assert_eq!(path, expected_path);
+                assert_eq!(source, SocketSource::RuntimeDirUnset);
+            }
             Err(e) => panic!("get_socket_path failed unexpectedly: {}", e),
         }
     }
 
     #[test]
     fn test_get_socket_path_xdg_create_fails() {
+        let _lock = lock_env();
         let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
         let temp_path = temp_dir.path();
 
@@ -281,70 +1336,455 @@ mod tests {
         fs::write(&handsfree_path, "block directory creation")
             .expect("Failed to create blocking file");
 
-        let temp_path_str = temp_path.to_str().expect("Failed to get path string");
-        // SAFETY: We're in a test and managing the env var lifecycle
-        unsafe {
-            env::set_var("XDG_RUNTIME_DIR", temp_path_str);
+        let temp_path_str = temp_path.to_str().expect("Failed to get path string");
+        // SAFETY: We're in a test and managing the env var lifecycle
+        unsafe {
+            env::set_var("XDG_RUNTIME_DIR", temp_path_str);
+        }
+
+        // Should fall back to /tmp/handsfree-uid.sock
+        let uid = getuid();
+        let expected_path = PathBuf::from(format!("/tmp/handsfree-{}.sock", uid));
+
+        match get_socket_path() {
+            Ok((path, source)) => {
+                assert_eq!(path, expected_path);
+                assert!(matches!(source, SocketSource::RuntimeDirUnusable(_)));
+                assert!(source.to_string().starts_with("fallback in /tmp because"));
+            }
+            Err(e) => panic!("get_socket_path failed unexpectedly: {}", e),
+        }
+
+        // SAFETY: We're in a test and managing the env var lifecycle
+        unsafe {
+            env::remove_var("XDG_RUNTIME_DIR");
+        }
+    }
+
+    #[test]
+    fn test_get_socket_path_no_create_reports_file() {
+        let _lock = lock_env();
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let temp_path = temp_dir.path();
+
+        // Same blocking file as above, but this time it must be reported
+        let handsfree_path = temp_path.join("handsfree");
+        fs::write(&handsfree_path, "block directory creation")
+            .expect("Failed to create blocking file");
+
+        let temp_path_str = temp_path.to_str().expect("Failed to get path string");
+        // SAFETY: We're in a test and managing the env var lifecycle
+        unsafe {
+            env::set_var("XDG_RUNTIME_DIR", temp_path_str);
+        }
+
+        let result = get_socket_path_no_create();
+
+        // SAFETY: We're in a test and managing the env var lifecycle
+        unsafe {
+            env::remove_var("XDG_RUNTIME_DIR");
+        }
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            format!(
+                "expected a directory at {} but found a file",
+                handsfree_path.display()
+            )
+        );
+    }
+
+    #[test]
+    fn test_get_socket_path_no_create_missing_dir() {
+        let _lock = lock_env();
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let temp_path = temp_dir.path();
+
+        let temp_path_str = temp_path.to_str().expect("Failed to get path string");
+        // SAFETY: We're in a test and managing the env var lifecycle
+        unsafe {
+            env::set_var("XDG_RUNTIME_DIR", temp_path_str);
+        }
+
+        let result = get_socket_path_no_create();
+
+        // SAFETY: We're in a test and managing the env var lifecycle
+        unsafe {
+            env::remove_var("XDG_RUNTIME_DIR");
+        }
+
+        assert_eq!(
+            result.unwrap(),
+            (
+                temp_path.join("handsfree").join("daemon.sock"),
+                SocketSource::RuntimeDir
+            )
+        );
+        // The directory is left for the daemon to create
+        assert!(!temp_path.join("handsfree").exists());
+    }
+
+    // Test successful command sending and response parsing
+    #[tokio::test]
+    async fn test_send_command_with_response() {
+        // Create a temporary socket path
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+
+        // Create a listener
+        let listener = UnixListener::bind(&socket_path).unwrap();
+
+        // Spawn a task to handle the connection
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let n = socket.read(&mut buf).await.unwrap();
+            let received = String::from_utf8_lossy(&buf[..n]);
+
+            // Send back an Ack response
+            let response = r#"{"response_type":"ack"}"#;
+            socket
+                .write_all(format!("{}\n", response).as_bytes())
+                .await
+                .unwrap();
+            socket.flush().await.unwrap();
+            received.to_string()
+        });
+
+        // Connect and send a command
+        let mut stream = UnixStream::connect(&socket_path).await.unwrap();
+        let command = DaemonCommand::start(vec![CliOutputMode::Clipboard]);
+
+        // Send command and get response
+        let response = send_command(&mut stream, &command, DEFAULT_READ_TIMEOUT)
+            .await
+            .unwrap();
+
+        // Verify sent command, which carries a request id
+        let mut received: serde_json::Value = serde_json::from_str(&handle.await.unwrap()).unwrap();
+        let id = received.as_object_mut().unwrap().remove("id").unwrap();
+        assert!(id.is_u64());
+        assert_eq!(received, serde_json::to_value(&command).unwrap());
+
+        // Verify received response
+        assert!(matches!(response, DaemonResponse::Ack));
+    }
+
+    #[tokio::test]
+    async fn test_response_stream_lines() {
+        let (mut daemon, client) = UnixStream::pair().unwrap();
+        tokio::spawn(async move {
+            // Split a line across writes, with blank lines and no final newline
+            daemon.write_all(b"{\"response_type\":").await.unwrap();
+            daemon.flush().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            daemon
+                .write_all(b"\"ack\"}\n\n  \n{oops}\n{\"response_type\":\"ack\"}")
+                .await
+                .unwrap();
+        });
+
+        let items: Vec<_> = ResponseStream::new(client).collect().await;
+
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0].as_ref().unwrap(), &DaemonResponse::Ack);
+        assert!(
+            items[1]
+                .as_ref()
+                .unwrap_err()
+                .to_string()
+                .contains("Failed to deserialize")
+        );
+        assert_eq!(items[2].as_ref().unwrap(), &DaemonResponse::Ack);
+    }
+
+    #[tokio::test]
+    async fn test_length_framing_round_trip() {
+        let (mut daemon, client) = UnixStream::pair().unwrap();
+        let handle = tokio::spawn(async move {
+            let command = read_frame(&mut daemon, DEFAULT_MAX_LINE_LEN.get())
+                .await
+                .unwrap()
+                .unwrap();
+            // A newline inside a value doesn't end the message
+            let response = r#"{"response_type":"error","message":"Mic\nunplugged"}"#;
+            write_frame(&mut daemon, response.as_bytes()).await.unwrap();
+            String::from_utf8(command).unwrap()
+        });
+
+        let response = DaemonClient::from_stream(client)
+            .with_framing(Framing::Length)
+            .send(&DaemonCommand::Status)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response,
+            DaemonResponse::Error {
+                message: "Mic\nunplugged".to_string()
+            }
+        );
+        let command: serde_json::Value = serde_json::from_str(&handle.await.unwrap()).unwrap();
+        assert_eq!(command["command"], "status");
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_limits() {
+        let (mut daemon, mut client) = UnixStream::pair().unwrap();
+        write_frame(&mut daemon, &[b'x'; 16]).await.unwrap();
+        drop(daemon);
+
+        assert!(matches!(
+            read_frame(&mut client, 8).await,
+            Err(DaemonError::LineTooLong(8))
+        ));
+
+        let (daemon, mut client) = UnixStream::pair().unwrap();
+        drop(daemon);
+        assert!(read_frame(&mut client, 8).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_response_stream_frames() {
+        let (mut daemon, client) = UnixStream::pair().unwrap();
+        tokio::spawn(async move {
+            // Split a header across writes, with an empty frame in between
+            let ack = br#"{"response_type":"ack"}"#;
+            let len = (ack.len() as u32).to_be_bytes();
+            daemon.write_all(&len[..2]).await.unwrap();
+            daemon.flush().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            daemon.write_all(&len[2..]).await.unwrap();
+            daemon.write_all(ack).await.unwrap();
+            write_frame(&mut daemon, b"").await.unwrap();
+            write_frame(&mut daemon, b"{oops}").await.unwrap();
+            write_frame(&mut daemon, ack).await.unwrap();
+        });
+
+        let items: Vec<_> = ResponseStream::with_framing(client, Framing::Length)
+            .collect()
+            .await;
+
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0].as_ref().unwrap(), &DaemonResponse::Ack);
+        assert!(
+            items[1]
+                .as_ref()
+                .unwrap_err()
+                .to_string()
+                .contains("Failed to deserialize")
+        );
+        assert_eq!(items[2].as_ref().unwrap(), &DaemonResponse::Ack);
+    }
+
+    #[tokio::test]
+    async fn test_response_stream_unsubscribes_on_drop() {
+        let (daemon, client) = UnixStream::pair().unwrap();
+        drop(DaemonClient::from_stream(client).subscribe().await.unwrap());
+
+        let mut lines = BufReader::new(daemon).lines();
+        let mut commands = Vec::new();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            commands.push(serde_json::from_str::<DaemonCommand>(&line).unwrap());
+        }
+        assert_eq!(
+            commands,
+            [DaemonCommand::Subscribe, DaemonCommand::Unsubscribe]
+        );
+
+        let (mut daemon, client) = UnixStream::pair().unwrap();
+        let client = DaemonClient::from_stream(client).with_framing(Framing::Length);
+        drop(client.subscribe().await.unwrap());
+        let mut commands = Vec::new();
+        while let Some(frame) = read_frame(&mut daemon, DEFAULT_MAX_LINE_LEN.get())
+            .await
+            .unwrap()
+        {
+            commands.push(serde_json::from_slice::<DaemonCommand>(&frame).unwrap());
         }
+        assert_eq!(
+            commands,
+            [DaemonCommand::Subscribe, DaemonCommand::Unsubscribe]
+        );
 
-        // Should fall back to /tmp/handsfree-uid.sock
-        let uid = getuid();
-        let expected_path = PathBuf::from(format!("/tmp/handsfree-{}.sock", uid));
+        // A daemon that already went away is no reason to fail
+        let (daemon, client) = UnixStream::pair().unwrap();
+        let events = DaemonClient::from_stream(client).subscribe().await.unwrap();
+        drop(daemon);
+        drop(events);
+    }
 
-        match get_socket_path() {
-            Ok(path) => assert_eq!(path, expected_path),
-            Err(e) => panic!("get_socket_path failed unexpectedly: {}", e),
-        }
+    #[tokio::test]
+    async fn test_response_stream_without_subscribe_sends_nothing() {
+        let (mut daemon, client) = UnixStream::pair().unwrap();
+        drop(ResponseStream::new(client));
 
-        // SAFETY: We're in a test and managing the env var lifecycle
-        unsafe {
-            env::remove_var("XDG_RUNTIME_DIR");
-        }
+        let mut sent = Vec::new();
+        daemon.read_to_end(&mut sent).await.unwrap();
+        assert!(sent.is_empty(), "sent {:?}", String::from_utf8_lossy(&sent));
     }
 
-    // Test successful command sending and response parsing
     #[tokio::test]
-    async fn test_send_command_with_response() {
-        // Create a temporary socket path
-        let dir = tempfile::tempdir().unwrap();
-        let socket_path = dir.path().join("test.sock");
+    async fn test_send_command_rejects_other_request_id() {
+        let (daemon, mut client) = UnixStream::pair().unwrap();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(daemon);
+            let mut line = String::new();
+            lines.read_line(&mut line).await.unwrap();
+            // Ids start at 1, so this answers some other request
+            lines
+                .get_mut()
+                .write_all(b"{\"response_type\":\"ack\",\"id\":0}\n")
+                .await
+                .unwrap();
+        });
 
-        // Create a listener
-        let listener = UnixListener::bind(&socket_path).unwrap();
+        let err = send_command(&mut client, &DaemonCommand::Status, DEFAULT_READ_TIMEOUT)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            DaemonError::RequestIdMismatch { received: 0, .. }
+        ));
+    }
 
-        // Spawn a task to handle the connection
-        let handle = tokio::spawn(async move {
-            let (mut socket, _) = listener.accept().await.unwrap();
-            let mut buf = [0u8; 1024];
-            let n = socket.read(&mut buf).await.unwrap();
-            let received = String::from_utf8_lossy(&buf[..n]);
+    #[tokio::test]
+    async fn test_overlong_line_rejected() {
+        let overlong = format!("{}\n", "x".repeat(DEFAULT_MAX_LINE_LEN.get() + 1));
 
-            // Send back an Ack response
-            let response = r#"{"response_type":"ack"}"#;
-            socket
-                .write_all(format!("{}\n", response).as_bytes())
+        let (mut daemon, mut client) = UnixStream::pair().unwrap();
+        let line = overlong.clone();
+        tokio::spawn(async move {
+            // The client stops reading once over the limit
+            let _ = daemon.write_all(line.as_bytes()).await;
+        });
+        let err = receive_response(&mut client, DEFAULT_READ_TIMEOUT)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            DaemonError::LineTooLong(len) if len == DEFAULT_MAX_LINE_LEN.get()
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Response line exceeds the maximum length of 1048576 bytes"
+        );
+
+        // The stream reports the line and carries on with the next one
+        let (mut daemon, client) = UnixStream::pair().unwrap();
+        tokio::spawn(async move {
+            daemon.write_all(overlong.as_bytes()).await.unwrap();
+            daemon
+                .write_all(b"{\"response_type\":\"ack\"}\n")
                 .await
                 .unwrap();
-            socket.flush().await.unwrap();
-            received.to_string()
         });
+        let items: Vec<_> = ResponseStream::new(client).collect().await;
+        assert_eq!(items.len(), 2);
+        assert!(matches!(items[0], Err(DaemonError::LineTooLong(_))));
+        assert_eq!(items[1].as_ref().unwrap(), &DaemonResponse::Ack);
+
+        // A client's limit carries over to its subscription
+        let (mut daemon, client) = UnixStream::pair().unwrap();
+        daemon
+            .write_all(b"{\"response_type\":\"ack\"}\n")
+            .await
+            .unwrap();
+        let client = DaemonClient::from_stream(client).with_max_line_len(NonZeroUsize::MIN);
+        let mut events = client.subscribe().await.unwrap();
+        assert!(matches!(
+            events.next().await,
+            Some(Err(DaemonError::LineTooLong(1)))
+        ));
+    }
 
-        // Connect and send a command
-        let mut stream = UnixStream::connect(&socket_path).await.unwrap();
-        let command = DaemonCommand::Start {
-            output_mode: CliOutputMode::Clipboard,
-        };
+    #[tokio::test]
+    async fn test_response_stream_combinators() {
+        let (mut daemon, client) = UnixStream::pair().unwrap();
+        daemon
+            .write_all(
+                b"{\"response_type\":\"ack\"}\n{\"response_type\":\"error\",\"message\":\"x\"}\n",
+            )
+            .await
+            .unwrap();
+
+        let errors: Vec<_> = ResponseStream::new(client)
+            .filter_map(|item| async move {
+                match item {
+                    Ok(DaemonResponse::Error { message }) => Some(message),
+                    _ => None,
+                }
+            })
+            .take(1)
+            .collect()
+            .await;
 
-        // Send command and get response
-        let response = send_command(&mut stream, &command).await.unwrap();
+        assert_eq!(errors, ["x"]);
+    }
+
+    #[test]
+    fn test_check_socket_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(check_socket_dir(&dir.path().join("daemon.sock")).is_ok());
+        assert!(check_socket_dir(Path::new("daemon.sock")).is_ok());
+
+        let missing = dir.path().join("missing").join("daemon.sock");
+        assert_eq!(
+            check_socket_dir(&missing).unwrap_err().to_string(),
+            format!(
+                "Socket directory {} does not exist",
+                dir.path().join("missing").display()
+            )
+        );
 
-        // Verify sent command
-        let received = handle.await.unwrap();
-        let expected = format!("{}\n", serde_json::to_string(&command).unwrap());
-        assert_eq!(received, expected);
+        // Abstract names aren't paths, even with a slash in them
+        assert!(check_socket_dir(Path::new("@handsfree/daemon")).is_ok());
+    }
 
-        // Verify received response
-        assert!(matches!(response, DaemonResponse::Ack));
+    #[test]
+    fn test_abstract_socket_name() {
+        assert_eq!(
+            abstract_socket_name(Path::new("@handsfree")),
+            Some(&b"handsfree"[..])
+        );
+        assert_eq!(abstract_socket_name(Path::new("/run/handsfree.sock")), None);
+        assert_eq!(abstract_socket_name(Path::new("./@handsfree")), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_connect_abstract_socket() {
+        use std::os::linux::net::SocketAddrExt;
+
+        let name = format!("handsfreectl-test-{}", std::process::id());
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(&name).unwrap();
+        let listener = std::os::unix::net::UnixListener::bind_addr(&addr).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let listener = UnixListener::from_std(listener).unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 256];
+            let _ = socket.read(&mut buf).await.unwrap();
+            socket
+                .write_all(b"{\"response_type\":\"ack\"}\n")
+                .await
+                .unwrap();
+        });
+
+        let socket_path = PathBuf::from(format!("@{}", name));
+        let mut stream = connect_to_daemon(&socket_path).await.unwrap();
+        let response = send_command(&mut stream, &DaemonCommand::Ping, DEFAULT_READ_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(response, DaemonResponse::Ack);
+        server.await.unwrap();
+
+        // Nothing listening under that name any more
+        let err = connect_to_daemon(&socket_path).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<io::Error>().unwrap().kind(),
+            io::ErrorKind::ConnectionRefused
+        );
     }
 
     // Test response timeout
@@ -364,10 +1804,36 @@ mod tests {
 
         // Connect and try to receive
         let mut stream = UnixStream::connect(&socket_path).await.unwrap();
-        let result = receive_response(&mut stream).await;
+        let result = receive_response(&mut stream, Duration::from_millis(100)).await;
 
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("timeout"));
+        let err = result.unwrap_err();
+        assert!(err.is_timeout());
+        assert_eq!(
+            err.to_string(),
+            "Read timeout after 0.1 seconds while waiting for response."
+        );
+    }
+
+    #[tokio::test]
+    async fn test_receive_response_zero_timeout_waits() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+
+        // Answer later than a short timeout would allow
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            socket
+                .write_all(b"{\"response_type\":\"ack\"}\n")
+                .await
+                .unwrap();
+        });
+
+        let mut stream = UnixStream::connect(&socket_path).await.unwrap();
+        let response = receive_response(&mut stream, Duration::ZERO).await.unwrap();
+
+        assert_eq!(response, DaemonResponse::Ack);
     }
 
     // Test invalid response JSON
@@ -387,15 +1853,11 @@ mod tests {
 
         // Connect and try to receive
         let mut stream = UnixStream::connect(&socket_path).await.unwrap();
-        let result = receive_response(&mut stream).await;
+        let result = receive_response(&mut stream, DEFAULT_READ_TIMEOUT).await;
 
-        assert!(result.is_err());
-        assert!(
-            result
-                .unwrap_err()
-                .to_string()
-                .contains("Failed to deserialize")
-        );
+        let err = result.unwrap_err();
+        assert!(err.to_string().contains("Failed to deserialize"));
+        assert!(matches!(err, DaemonError::Deserialize { raw, .. } if raw == "{invalid_json}"));
     }
 
     #[tokio::test]
@@ -418,7 +1880,9 @@ mod tests {
 
         // Connect and try to receive
         let mut stream = UnixStream::connect(&socket_path).await.unwrap();
-        let response = receive_response(&mut stream).await.unwrap();
+        let response = receive_response(&mut stream, DEFAULT_READ_TIMEOUT)
+            .await
+            .unwrap();
 
         match response {
             DaemonResponse::Error { message } => {
@@ -428,6 +1892,172 @@ mod tests {
         }
     }
 
+    // Accepts one connection, reads a command and hangs up without replying
+    async fn accept_and_drop(listener: &UnixListener) {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buf = [0u8; 1024];
+        let _ = socket.read(&mut buf).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_send_command_retrying_idempotent() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+
+        // First connection drops, second one answers
+        let handle = tokio::spawn(async move {
+            accept_and_drop(&listener).await;
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let n = socket.read(&mut buf).await.unwrap();
+            let response =
+                r#"{"response_type":"status","status":{"state":"idle","last_error":null}}"#;
+            socket
+                .write_all(format!("{}\n", response).as_bytes())
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&buf[..n]).to_string()
+        });
+
+        let mut stream = UnixStream::connect(&socket_path).await.unwrap();
+        let response = send_command_retrying(
+            &mut stream,
+            &socket_path,
+            &DaemonCommand::Status,
+            0,
+            DEFAULT_READ_TIMEOUT,
+        )
+        .await
+        .unwrap();
+
+        let resent = handle.await.unwrap();
+        assert!(resent.starts_with(r#"{"command":"status","id":"#));
+        assert!(matches!(response, DaemonResponse::Status { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_send_command_retrying_non_idempotent() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+
+        // Report whether the client came back after the drop
+        let handle = tokio::spawn(async move {
+            accept_and_drop(&listener).await;
+            tokio::time::timeout(Duration::from_millis(200), listener.accept())
+                .await
+                .is_ok()
+        });
+
+        let mut stream = UnixStream::connect(&socket_path).await.unwrap();
+        let command = DaemonCommand::start(vec![CliOutputMode::Keyboard]);
+        let result =
+            send_command_retrying(&mut stream, &socket_path, &command, 0, DEFAULT_READ_TIMEOUT)
+                .await;
+
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Connection closed")
+        );
+        assert!(!handle.await.unwrap(), "Start must not be resent");
+    }
+
+    #[tokio::test]
+    async fn test_non_idempotent_not_retried_on_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+
+        // Never answer, and report whether the client came back
+        let handle = tokio::spawn(async move {
+            let (_socket, _) = listener.accept().await.unwrap();
+            tokio::time::timeout(Duration::from_secs(1), listener.accept())
+                .await
+                .is_ok()
+        });
+
+        let mut stream = UnixStream::connect(&socket_path).await.unwrap();
+        let result = send_command_retrying(
+            &mut stream,
+            &socket_path,
+            &DaemonCommand::Shutdown,
+            3,
+            Duration::from_millis(100),
+        )
+        .await;
+
+        let err = result.unwrap_err();
+        assert!(err.downcast_ref::<DaemonError>().unwrap().is_timeout());
+        assert!(!handle.await.unwrap(), "Shutdown must not be resent");
+    }
+
+    #[test]
+    fn test_response_preview() {
+        assert_eq!(response_preview("{\"a\":1}\n", 100), "{\\\"a\\\":1}\\n");
+        assert_eq!(response_preview("{\"response_type\"", 5), "{\\\"res");
+        // A cut through a multi-byte character doesn't panic
+        assert_eq!(response_preview("é", 1), "\u{fffd}");
+    }
+
+    // Logger recording every message, for asserting on log output
+    struct CapturingLogger;
+
+    static CAPTURED_LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    impl log::Log for CapturingLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            CAPTURED_LOGS
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn capture_logs() {
+        static LOGGER: CapturingLogger = CapturingLogger;
+        if log::set_logger(&LOGGER).is_ok() {
+            log::set_max_level(log::LevelFilter::Debug);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_response_preview_logged() {
+        capture_logs();
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            socket
+                .write_all(b"{\"response_type\":\"ack\"}\n")
+                .await
+                .unwrap();
+        });
+
+        let stream = UnixStream::connect(&socket_path).await.unwrap();
+        let mut client = DaemonClient::from_stream(stream).with_response_preview(12);
+        let response = client.next_response().await;
+
+        assert!(matches!(response.unwrap(), DaemonResponse::Ack));
+        let logs = CAPTURED_LOGS.lock().unwrap().clone();
+        assert!(
+            logs.iter()
+                .any(|line| line == "Response preview (12 of 24 bytes): {\\\"response_t"),
+            "preview not logged: {:?}",
+            logs
+        );
+    }
+
     #[tokio::test]
     async fn test_connection_timeout() {
         // Try to connect to a non-existent socket
@@ -442,6 +2072,80 @@ mod tests {
         assert!(result.is_err() || result.unwrap().is_err());
     }
 
+    #[tokio::test]
+    async fn test_connect_to_non_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+
+        // A missing socket is still reported as such
+        let err = connect_to_daemon(&socket_path).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<io::Error>().unwrap().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        fs::write(&socket_path, "stale").unwrap();
+        let err = connect_to_daemon(&socket_path).await.unwrap_err();
+        assert!(err.downcast_ref::<ConnectError>().is_some());
+        assert_eq!(
+            err.root_cause().to_string(),
+            format!(
+                "{} is not a socket; remove it or pass --socket",
+                socket_path.display()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connect_to_stale_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        // Dropping the listener leaves the socket file behind
+        drop(UnixListener::bind(&socket_path).unwrap());
+
+        let err = connect_to_daemon(&socket_path).await.unwrap_err();
+        let stale = err.downcast_ref::<StaleSocketError>().unwrap();
+        assert_eq!(stale.path, socket_path);
+        assert_eq!(stale.source.kind(), io::ErrorKind::ConnectionRefused);
+        let message = format!("{:#}", err);
+        assert!(message.contains("the socket may be stale"));
+        assert!(message.contains(&format!("rm {}", socket_path.display())));
+        // Nothing is removed
+        assert!(socket_path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_retry_waits_for_listener() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("late.sock");
+
+        let listener_path = socket_path.clone();
+        let server = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let listener = UnixListener::bind(&listener_path).unwrap();
+            listener.accept().await.unwrap();
+        });
+
+        connect_with_retry(&socket_path, 5, Duration::from_millis(20))
+            .await
+            .unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_retry_returns_last_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("nonexistent.sock");
+
+        let err = connect_with_retry(&socket_path, 3, Duration::from_millis(1))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<io::Error>().unwrap().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
     #[test]
     fn test_error_kind_for_nonexistent_socket() {
         // This test verifies that the error kind for a nonexistent socket file is NotFound
@@ -460,4 +2164,156 @@ mod tests {
 
         assert_eq!(io_err.kind(), std::io::ErrorKind::NotFound);
     }
+
+    #[tokio::test]
+    async fn test_daemon_client() {
+        let dir = tempfile::tempdir().unwrap();
+        let daemon = MockDaemon::spawn(&dir.path().join("mock.sock")).unwrap();
+
+        let mut client = DaemonClient::connect(daemon.socket_path()).await.unwrap();
+        assert_eq!(client.status().await.unwrap().state, DaemonState::Idle);
+        client.start(vec![CliOutputMode::Clipboard]).await.unwrap();
+        assert_eq!(client.status().await.unwrap().state, DaemonState::Listening);
+        client.toggle(vec![]).await.unwrap();
+        assert_eq!(client.status().await.unwrap().state, DaemonState::Idle);
+
+        let mut events = client.subscribe().await.unwrap();
+        match events.next().await.unwrap().unwrap() {
+            DaemonResponse::StateChange { status } => assert_eq!(status.state, DaemonState::Idle),
+            other => panic!("Expected a state change, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_daemon_client_reads_past_progress() {
+        let (daemon, client) = UnixStream::pair().unwrap();
+        let server = tokio::spawn(async move {
+            let mut lines = tokio::io::BufReader::new(daemon).lines();
+            for answer_id in [None, Some(0)] {
+                let line = lines.next_line().await.unwrap().unwrap();
+                let id = serde_json::from_str::<serde_json::Value>(&line).unwrap()["id"].clone();
+                let answer_id = answer_id.map_or(id.clone(), serde_json::Value::from);
+                // In one write, so the client buffers the answer with the report
+                let replies = format!(
+                    "{}\n{}\n",
+                    json!({ "response_type": "progress", "stage": "Loading model", "id": id }),
+                    json!({ "response_type": "ack", "id": answer_id })
+                );
+                lines.get_mut().write_all(replies.as_bytes()).await.unwrap();
+            }
+        });
+
+        let mut client = DaemonClient::from_stream(client);
+        client.start(vec![CliOutputMode::Keyboard]).await.unwrap();
+        // The answer must be to the same request as the progress report
+        let err = client.stop().await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<DaemonError>(),
+            Some(DaemonError::RequestIdMismatch { received: 0, .. })
+        ));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_daemon_client_trace_id() {
+        // Each client keeps its own id, so two can run side by side
+        for trace_id in ["abc-123", "def-456"] {
+            let (daemon, client) = UnixStream::pair().unwrap();
+            let server = tokio::spawn(async move {
+                let mut lines = tokio::io::BufReader::new(daemon).lines();
+                let mut sent = Vec::new();
+                for reply in [
+                    json!({ "response_type": "ack", "trace_id": trace_id }),
+                    json!({ "response_type": "ack", "trace_id": "someone-else" }),
+                ] {
+                    let line = lines.next_line().await.unwrap().unwrap();
+                    sent.push(serde_json::from_str::<serde_json::Value>(&line).unwrap());
+                    let reply = format!("{}\n", reply);
+                    lines.get_mut().write_all(reply.as_bytes()).await.unwrap();
+                }
+                sent
+            });
+
+            let mut client = DaemonClient::from_stream(client).with_trace_id(trace_id);
+            client.stop().await.unwrap();
+            // Events must carry the subscriber's id too
+            let mut events = client.subscribe().await.unwrap();
+            assert!(matches!(
+                events.next().await,
+                Some(Err(DaemonError::TraceIdMismatch { .. }))
+            ));
+            let sent = server.await.unwrap();
+            assert_eq!(sent[0]["trace_id"], trace_id);
+            assert_eq!(sent[1]["command"], "subscribe");
+            assert_eq!(sent[1]["trace_id"], trace_id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_daemon_client_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 256];
+            let _ = socket.read(&mut buf).await.unwrap();
+            socket
+                .write_all(b"{\"response_type\":\"error\",\"message\":\"Busy\"}\n")
+                .await
+                .unwrap();
+            let _ = socket.read(&mut buf).await.unwrap();
+            socket
+                .write_all(b"{\"response_type\":\"ack\"}\n")
+                .await
+                .unwrap();
+        });
+
+        let mut client = DaemonClient::connect(&socket_path).await.unwrap();
+        let err = client.stop().await.unwrap_err();
+        assert!(err.is::<DaemonReportedError>());
+        assert_eq!(err.to_string(), "Daemon Error: Busy");
+
+        let err = client.status().await.unwrap_err();
+        assert_eq!(err.to_string(), "Unexpected response to Status: Ack");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_daemon_client_ping() {
+        let dir = tempfile::tempdir().unwrap();
+        let daemon = MockDaemon::spawn(&dir.path().join("mock.sock")).unwrap();
+        let mut client = DaemonClient::connect(daemon.socket_path()).await.unwrap();
+        let latency = client.ping().await.unwrap();
+        assert!(latency >= Duration::ZERO && latency < DEFAULT_READ_TIMEOUT);
+
+        // A daemon that answers with anything but the pong
+        let socket_path = dir.path().join("status.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| {
+            vec![
+                r#"{"response_type":"status","status":{"state":"idle","last_error":null}}"#
+                    .to_string(),
+            ]
+        })
+        .unwrap();
+        let mut client = DaemonClient::connect(daemon.socket_path()).await.unwrap();
+        let err = client.ping().await.unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Unexpected response to Ping: Status")
+        );
+
+        // And one that doesn't answer at all
+        let socket_path = dir.path().join("silent.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| vec![]).unwrap();
+        let mut client = DaemonClient::connect(daemon.socket_path())
+            .await
+            .unwrap()
+            .with_read_timeout(Duration::from_millis(50));
+        let err = client.ping().await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<DaemonError>(),
+            Some(DaemonError::Timeout(_))
+        ));
+    }
 }
@@ -1,3 +1,9 @@
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod capture;
 pub mod cli;
+pub mod config;
 pub mod daemon;
 pub mod protocol;
+#[cfg(any(test, feature = "test-support"))]
+pub mod testutil;
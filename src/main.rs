@@ -1,139 +1,4584 @@
-use anyhow::{Context, Result, anyhow};
-use handsfreectl::cli::{Cli, Commands};
+use anyhow::{Context, Result};
+use chrono::{SecondsFormat, Utc};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, ValueEnum};
+use clap_complete::Shell;
+use env_logger::WriteStyle;
+use futures::StreamExt;
+use handsfreectl::capture::{Capture, with_capture};
+use handsfreectl::cli::{Cli, CliOutputMode, Commands, ConfigAction, LogFormat, StatusFormat};
+use handsfreectl::config::{ClientSettings, read_env_file};
 use handsfreectl::daemon::{
-    ResponseStream, connect_to_daemon, get_socket_path, send_command, send_command_only,
+    ConnectError, DaemonClient, DaemonError, DaemonReportedError, MAX_RETRY_DELAY, ResponseStream,
+    SocketSource, abstract_socket_name, check_socket_dir, connect_to_daemon, connect_with_retry,
+    encode_command, get_socket_path, get_socket_path_no_create,
 };
-use handsfreectl::protocol::{DaemonCommand, DaemonResponse};
-use log::{debug, error, warn};
+use handsfreectl::protocol::{
+    DaemonCommand, DaemonResponse, DaemonState, DaemonStatus, ReportedState, SessionInfo,
+    StartCommand,
+};
+use log::{debug, error, info, warn};
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg, OFlag};
+use nix::unistd::getuid;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Read, Seek, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::UnixStream;
+use tokio::signal::unix::SignalKind;
+
+// Exit codes by kind of failure, see `failure_code`. Invalid arguments
+// exit with clap's usage code, 2.
+const EXIT_OTHER: u8 = 1;
+const EXIT_DAEMON: u8 = 3;
+const EXIT_PROTOCOL: u8 = 4;
+const EXIT_TIMEOUT: u8 = 5;
+const EXIT_CONNECTION: u8 = 6;
+const EXIT_STATE_IDLE: u8 = 10;
+const EXIT_STATE_ERROR: u8 = 20;
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    env_logger::Builder::from_env(
-        env_logger::Env::default().default_filter_or("handsfreectl=warn"),
-    )
-    .init();
+/// Ends `status --exit-code-only` with the code for the daemon's state.
+#[derive(Debug, thiserror::Error)]
+#[error("Daemon state maps to exit code {0}")]
+struct StateExitCode(u8);
 
-    let cli = Cli::parse();
+fn main() -> ExitCode {
+    let mut cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => return usage_error(&e),
+    };
+    // Ahead of HANDSFREE_OUTPUT and RUST_LOG, which the file may set too
+    if let Some(path) = cli.env_file.as_deref().filter(|_| !cli.parse_only)
+        && let Err(e) = load_env_file(path)
+    {
+        return usage_error(&Cli::command().error(ErrorKind::Io, format!("{:#}", e)));
+    }
+    if let Err(e) = cli.apply_output_env(|name| env::var_os(name)) {
+        return usage_error(&e);
+    }
 
-    let socket_path = get_socket_path().context("Error determining socket path")?;
+    let colorize = should_colorize(
+        cli.plain,
+        env::var_os("NO_COLOR").as_deref(),
+        io::stderr().is_terminal(),
+    );
+    logger_builder(cli.log_filter(), cli.log_format, colorize).init();
 
-    let mut stream = match connect_to_daemon(&socket_path).await {
-        Ok(stream) => stream,
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
         Err(e) => {
-            if let Commands::Status = cli.command {
-                // Check if it's a connection error (NotFound or ConnectionRefused)
-                if let Some(io_err) = e.root_cause().downcast_ref::<std::io::Error>() {
-                    if matches!(
-                        io_err.kind(),
-                        std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused
-                    ) {
-                        println!("Inactive");
-                        return Ok(());
-                    }
+            eprintln!("Error: Failed to start the async runtime: {}", e);
+            return ExitCode::from(EXIT_OTHER);
+        }
+    };
+    match runtime.block_on(try_main(&mut cli)) {
+        Ok(()) => ExitCode::SUCCESS,
+        // Arguments that conflict with the config file's defaults
+        Err(e) if e.is::<clap::Error>() => usage_error(e.downcast_ref().unwrap()),
+        Err(e) => {
+            if let Commands::Status {
+                exit_code_only: true,
+                ..
+            } = cli.command
+            {
+                // Health checks only look at the code
+            } else if cli.json_errors {
+                eprintln!(
+                    "{}",
+                    json_error(error_kind(&e), &format!("{:#}", e), cli.trace_id.as_deref())
+                );
+            } else if cli.json {
+                eprintln!("{}", json!({ "error": format!("{:#}", e) }));
+            } else {
+                eprintln!("Error: {:?}", e);
+                if let Some(id) = &cli.trace_id {
+                    eprintln!("Trace ID: {}", id);
                 }
             }
+            ExitCode::from(failure_code(&cli.command, &e))
+        }
+    }
+}
+
+/// Sets the variables of the `--env-file` at `path` in the process
+/// environment, replacing those already set.
+fn load_env_file(path: &Path) -> Result<()> {
+    for (key, value) in read_env_file(path)? {
+        // SAFETY: `main` calls this before starting the runtime, while
+        // there is no other thread to read or write the environment
+        unsafe {
+            env::set_var(key, value);
+        }
+    }
+    Ok(())
+}
+
+/// Reports invalid arguments like clap does, or as JSON with
+/// `--json-errors`, and exits with clap's usage code.
+fn usage_error(e: &clap::Error) -> ExitCode {
+    // Help and version requests are not failures
+    if e.use_stderr() && env::args_os().any(|arg| arg == "--json-errors") {
+        let rendered = e.to_string();
+        let message = rendered.lines().next().unwrap_or_default();
+        let message = message.strip_prefix("error: ").unwrap_or(message);
+        eprintln!("{}", json_error("usage", message, None));
+        return ExitCode::from(e.exit_code() as u8);
+    }
+    e.exit()
+}
 
-            return Err(e).with_context(|| {
-                format!(
-                    "Connection Error: Failed to connect to daemon socket at {:?}. Is the daemon running?",
-                    socket_path
-                )
+/// The logger for `main`: `filter` unless `RUST_LOG` is set, in `format`.
+fn logger_builder(filter: &str, format: LogFormat, colorize: bool) -> env_logger::Builder {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(filter));
+    match format {
+        LogFormat::Text => {
+            builder.write_style(if colorize {
+                WriteStyle::Auto
+            } else {
+                WriteStyle::Never
             });
         }
+        LogFormat::Json => {
+            builder
+                .write_style(WriteStyle::Never)
+                .format(|buf, record| {
+                    let line = json!({
+                        "timestamp": iso_timestamp(),
+                        "level": record.level().as_str(),
+                        "target": record.target(),
+                        "message": record.args().to_string(),
+                    });
+                    writeln!(buf, "{}", line)
+                });
+        }
+    }
+    builder
+}
+
+/// Exit code for a failed command, by the same classification as
+/// `--json-errors`. For `ping`, anything but a daemon that isn't there
+/// counts as the daemon misbehaving.
+fn failure_code(command: &Commands, err: &anyhow::Error) -> u8 {
+    if let Some(StateExitCode(code)) = err.downcast_ref() {
+        return *code;
+    }
+    match (command, error_kind(err)) {
+        (Commands::Ping { .. }, _) if is_daemon_down(err) => EXIT_CONNECTION,
+        (Commands::Ping { .. }, _) => EXIT_DAEMON,
+        (_, "connection") => EXIT_CONNECTION,
+        (_, "daemon") => EXIT_DAEMON,
+        (_, "protocol") => EXIT_PROTOCOL,
+        (_, "timeout") => EXIT_TIMEOUT,
+        _ => EXIT_OTHER,
+    }
+}
+
+/// Exit code for `status --exit-code-only`: 0 while the daemon is running
+/// (listening, processing, or some state this client doesn't know), 10 when
+/// idle and 20 in the error state.
+fn state_exit_code(state: &DaemonState) -> u8 {
+    match state {
+        DaemonState::Idle => EXIT_STATE_IDLE,
+        DaemonState::Error => EXIT_STATE_ERROR,
+        _ => 0,
+    }
+}
+
+/// Whether `err` means nothing is listening on the daemon socket.
+fn is_daemon_down(err: &anyhow::Error) -> bool {
+    err.root_cause()
+        .downcast_ref::<io::Error>()
+        .is_some_and(|io_err| {
+            matches!(
+                io_err.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            )
+        })
+}
+
+async fn try_main(cli: &mut Cli) -> Result<()> {
+    // Needs neither the config file nor a socket, e.g. when run by packagers
+    if let Commands::Completions { shell } = cli.command {
+        write_completions(shell, &mut io::stdout())?;
+        return Ok(());
+    }
+    // --parse-only touches neither the config file nor the socket
+    let settings = if cli.parse_only {
+        ClientSettings::default()
+    } else {
+        ClientSettings::for_cli(cli)?
+    };
+    settings.apply(cli);
+    cli.validate()?;
+    if cli.parse_only {
+        writeln!(io::stdout(), "{:?}", cli.command)?;
+        return Ok(());
+    }
+
+    let (socket_path, socket_source) = resolve_socket_path(cli, settings.socket)?;
+    if let Commands::Config {
+        action: ConfigAction::Path,
+    } = cli.command
+    {
+        if !cli.quiet || cli.json {
+            write_socket_path(cli, &socket_path, &socket_source, &mut io::stdout())?;
+        }
+        return Ok(());
+    }
+
+    let (mut out, mut err) = (io::stdout(), io::stderr());
+    let operation = async {
+        match &cli.capture {
+            Some(path) => {
+                let capture = Capture::create(path)?;
+                with_capture(capture, run(cli, &socket_path, &mut out, &mut err)).await
+            }
+            None => run(cli, &socket_path, &mut out, &mut err).await,
+        }
     };
+    within_deadline(cli.deadline(), operation).await
+}
 
-    match cli.command {
-        Commands::Status => {
-            debug!("Sending command: {:?}", DaemonCommand::Status);
+/// Writes the completion script for `shell` to `out`.
+fn write_completions(shell: Shell, out: &mut dyn Write) -> io::Result<()> {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    // clap_complete panics on write errors, so render to memory first
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut command, name, &mut script);
+    out.write_all(&script)?;
+    out.flush()
+}
 
-            match send_command(&mut stream, &DaemonCommand::Status).await {
-                Ok(response) => match response {
-                    DaemonResponse::Status { status } => {
-                        println!("{}", status.state);
-                        if let Some(err) = status.last_error {
-                            println!("{}", err);
-                        }
-                    }
-                    DaemonResponse::Error { message } => {
-                        return Err(anyhow!("Daemon Error: {}", message));
-                    }
-                    _ => {
-                        warn!("Received unexpected response for Status command");
+/// Classifies a failure for `--json-errors` and the exit code, by the
+/// daemon error it came from or else by its root cause. An `io::Error`
+/// only counts as a connection failure when connecting failed; otherwise
+/// it came from a local file or stream.
+fn error_kind(err: &anyhow::Error) -> &'static str {
+    if let Some(daemon_err) = err
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<DaemonError>())
+    {
+        return match daemon_err {
+            DaemonError::Timeout(_) => "timeout",
+            DaemonError::ConnectionClosed | DaemonError::Io { .. } => "connection",
+            DaemonError::Serialize(_)
+            | DaemonError::Deserialize { .. }
+            | DaemonError::TraceIdMismatch { .. }
+            | DaemonError::RequestIdMismatch { .. }
+            | DaemonError::EmptyResponse
+            | DaemonError::LineTooLong(_) => "protocol",
+        };
+    }
+
+    let root = err.root_cause();
+    if root.is::<DaemonReportedError>() {
+        "daemon"
+    } else if root.is::<serde_json::Error>() {
+        "protocol"
+    } else if let Some(io_err) = root.downcast_ref::<io::Error>() {
+        if io_err.kind() == io::ErrorKind::TimedOut {
+            "timeout"
+        } else if err.downcast_ref::<ConnectError>().is_some() {
+            "connection"
+        } else {
+            "io"
+        }
+    } else {
+        "other"
+    }
+}
+
+/// The `--json-errors` report: `{"error":{"kind":...,"message":...}}`,
+/// plus the `trace_id` if one was given.
+fn json_error(kind: &str, message: &str, trace_id: Option<&str>) -> String {
+    let mut error = json!({ "kind": kind, "message": message });
+    if let Some(id) = trace_id {
+        error["trace_id"] = json!(id);
+    }
+    json!({ "error": error }).to_string()
+}
+
+/// Prints the serialized command to `err` if `--echo-command` is set.
+fn echo_command(cli: &Cli, err: &mut dyn Write, command: &DaemonCommand) -> Result<()> {
+    if cli.echo_command {
+        let command_json = encode_command(command, cli.trace_id.as_deref())?;
+        writeln!(err, "{}", command_json)?;
+    }
+    Ok(())
+}
+
+/// Hoists the fields of nested objects to the top level, so consumers get
+/// the same flat shape regardless of how the status is nested on the wire.
+/// Fails if two fields would end up with the same name, rather than
+/// silently dropping one.
+fn flatten_json(value: serde_json::Value) -> Result<serde_json::Value> {
+    fn hoist(
+        fields: serde_json::Map<String, serde_json::Value>,
+        flat: &mut serde_json::Map<String, serde_json::Value>,
+    ) -> Result<()> {
+        for (key, value) in fields {
+            match value {
+                serde_json::Value::Object(nested) => hoist(nested, flat)?,
+                value => {
+                    if flat.contains_key(&key) {
+                        return Err(anyhow::anyhow!(
+                            "Can't flatten the status: field '{}' appears more than once",
+                            key
+                        ));
                     }
-                },
-                Err(e) => {
-                    return Err(e).context("Communication Error");
+                    flat.insert(key, value);
                 }
             }
         }
-        Commands::Watch => {
-            debug!("Sending command: {:?}", DaemonCommand::Subscribe);
+        Ok(())
+    }
 
-            send_command_only(&mut stream, &DaemonCommand::Subscribe)
-                .await
-                .context("Failed to send subscribe command")?;
-
-            let mut response_stream = ResponseStream::new(stream);
-
-            while let Some(result) = response_stream.next().await {
-                match result {
-                    Ok(response) => match response {
-                        DaemonResponse::StateChange { status }
-                        | DaemonResponse::Status { status } => {
-                            println!("State changed: {}", status.state);
-                            if let Some(err) = status.last_error {
-                                println!("Error: {}", err);
-                            }
+    Ok(match value {
+        serde_json::Value::Object(fields) => {
+            let mut flat = serde_json::Map::new();
+            hoist(fields, &mut flat)?;
+            serde_json::Value::Object(flat)
+        }
+        value => value,
+    })
+}
+
+/// Replaces `{name}` placeholders in `template` with the value of the
+/// matching field. Unknown placeholders are left untouched.
+fn render_template(template: &str, fields: &[(&str, &str)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        let substitution = placeholder.find('}').and_then(|end| {
+            let name = &placeholder[1..end];
+            fields
+                .iter()
+                .find(|(field, _)| *field == name)
+                .map(|(_, value)| (end, *value))
+        });
+        match substitution {
+            Some((end, value)) => {
+                rendered.push_str(value);
+                rest = &placeholder[end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = &placeholder[1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// The `status --format` to use, defaulting to JSON under `--json`. With
+/// `--no-newline` the default is short, as a second line would break a prompt.
+fn status_format(cli: &Cli, format: Option<StatusFormat>, no_newline: bool) -> StatusFormat {
+    format.unwrap_or(if cli.json {
+        StatusFormat::Json
+    } else if no_newline {
+        StatusFormat::Short
+    } else {
+        StatusFormat::Plain
+    })
+}
+
+/// Writes the output of `status`, ending it with a newline unless `--no-newline`.
+fn write_status(out: &mut dyn Write, text: &str, no_newline: bool) -> io::Result<()> {
+    if no_newline {
+        write!(out, "{}", text)
+    } else {
+        writeln!(out, "{}", text)
+    }
+}
+
+/// Whether one-shot `--json` results are indented (`--json --pretty`).
+fn pretty_json(cli: &Cli) -> bool {
+    cli.json && cli.pretty
+}
+
+/// Serializes `value`, indented if `pretty`.
+fn to_json(value: &impl Serialize, pretty: bool) -> serde_json::Result<String> {
+    if pretty {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    }
+}
+
+/// Serializes a one-shot `--json` result, indented with `--pretty`.
+fn json_text(cli: &Cli, value: &impl Serialize) -> serde_json::Result<String> {
+    to_json(value, pretty_json(cli))
+}
+
+/// Writes a one-shot `--json` result to `out` as by [`json_text`].
+fn write_json(cli: &Cli, out: &mut dyn Write, value: &impl Serialize) -> io::Result<()> {
+    writeln!(out, "{}", json_text(cli, value)?)
+}
+
+/// Renders a status for `status` or a `watch` event, without a trailing
+/// newline. `pretty` indents the `json` format.
+fn format_status(
+    status: &DaemonStatus,
+    format: StatusFormat,
+    pretty: bool,
+) -> serde_json::Result<String> {
+    Ok(match format {
+        StatusFormat::Plain => {
+            let mut text = status.state.to_string();
+            if let Some(last_error) = &status.last_error {
+                text.push_str(&format!("\n{}", last_error));
+            }
+            if let Some(since) = &status.running_since {
+                text.push_str(&format!("\nRunning since: {}", since));
+            }
+            if let Some(secs) = status.idle_timeout_secs {
+                text.push_str(&format!("\nIdle timeout: {}s", secs));
+            }
+            text
+        }
+        StatusFormat::Json => to_json(status, pretty)?,
+        StatusFormat::Short => status.state.to_string(),
+    })
+}
+
+/// Renders a `watch` event in one of the `status` formats, on one line:
+/// `plain` puts the details after the state, separated by `; `.
+fn format_watch_status(status: &DaemonStatus, format: StatusFormat) -> serde_json::Result<String> {
+    Ok(format_status(status, format, false)?.replace('\n', "; "))
+}
+
+/// Renders a daemon error for `watch` in one of the `status` formats, on
+/// one line like the state events.
+fn format_watch_error(message: &str, format: StatusFormat) -> String {
+    match format {
+        StatusFormat::Json => json!({ "error": message }).to_string(),
+        StatusFormat::Plain | StatusFormat::Short => {
+            format!("Error: {}", message.replace('\n', "; "))
+        }
+    }
+}
+
+/// Name of the kind of event a response represents, as shown to users.
+fn event_name(response: &DaemonResponse) -> &'static str {
+    match response {
+        DaemonResponse::Ack => "ack",
+        DaemonResponse::Status { .. } => "status",
+        DaemonResponse::Error { .. } => "error",
+        DaemonResponse::StateChange { .. } => "state_change",
+        DaemonResponse::Hello { .. } => "hello",
+        DaemonResponse::Metrics { .. } => "metrics",
+        DaemonResponse::Sessions { .. } => "sessions",
+        DaemonResponse::Progress { .. } => "progress",
+    }
+}
+
+/// The current time in ISO 8601 (UTC, millisecond precision), for `watch --json`.
+fn iso_timestamp() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Removes ANSI escape sequences (CSI, OSC and two-byte escapes) and other
+/// control characters from `text`, keeping newlines and tabs.
+fn strip_ansi(text: &str) -> String {
+    let mut clean = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI: parameters and intermediates up to a final byte in @..~
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
                         }
-                        DaemonResponse::Error { message } => {
-                            error!("Daemon Error: {}", message);
+                    }
+                }
+                // OSC: terminated by BEL or ESC \
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' {
+                            break;
+                        }
+                        if c == '\x1b' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
                         }
-                        _ => {}
-                    },
-                    Err(e) => {
-                        warn!("{}", e);
                     }
                 }
+                _ => {}
+            },
+            '\n' | '\t' => clean.push(c),
+            c if c.is_control() => {}
+            c => clean.push(c),
+        }
+    }
+    clean
+}
+
+/// Whether output may be styled. `--plain` and a non-empty `NO_COLOR` (see
+/// no-color.org) rule it out; otherwise only a terminal gets styling.
+fn should_colorize(plain: bool, no_color: Option<&OsStr>, is_terminal: bool) -> bool {
+    if plain || no_color.is_some_and(|value| !value.is_empty()) {
+        return false;
+    }
+    is_terminal
+}
+
+/// Applies `--strip-ansi` (or `--plain`) to text that came from the daemon.
+fn sanitize(cli: &Cli, text: String) -> String {
+    if cli.strip_ansi || cli.plain {
+        strip_ansi(&text)
+    } else {
+        text
+    }
+}
+
+/// Sanitizes a state reported by the daemon and applies any `--state-alias`,
+/// so that every consumer of the state sees the same vocabulary.
+fn normalize_state(cli: &Cli, state: ReportedState) -> ReportedState {
+    let state = sanitize(cli, state.into());
+    cli.state_alias
+        .iter()
+        .find(|(from, _)| from.eq_ignore_ascii_case(&state))
+        .map(|(_, to)| to.clone())
+        .unwrap_or(state)
+        .into()
+}
+
+/// Single-character code for a state, for `watch --compact`.
+fn state_char(state: &DaemonState) -> char {
+    match state {
+        DaemonState::Idle => 'I',
+        DaemonState::Listening => 'L',
+        DaemonState::Processing => 'P',
+        DaemonState::Running => 'R',
+        DaemonState::Paused => 'Z',
+        DaemonState::Starting => 'S',
+        DaemonState::Stopping => 'T',
+        DaemonState::Error => 'E',
+        DaemonState::Unknown(_) => '?',
+    }
+}
+
+/// Tracks the current daemon state and when it was entered, so that each
+/// transition can be annotated with the time spent in the previous state.
+#[derive(Default)]
+struct TransitionTracker {
+    current: Option<(String, Instant)>,
+}
+
+impl TransitionTracker {
+    /// Records `state` as observed at `now`. If it differs from the current
+    /// state, returns the previous state and how long it lasted.
+    fn observe(&mut self, state: &str, now: Instant) -> Option<(String, Duration)> {
+        match &self.current {
+            Some((current, _)) if current == state => None,
+            _ => {
+                let previous = self.current.replace((state.to_string(), now));
+                previous.map(|(previous, since)| (previous, now.duration_since(since)))
             }
-            debug!("Stream closed");
         }
-        _ => {
-            let daemon_command = match &cli.command {
-                Commands::Start { output } => DaemonCommand::Start {
-                    output_mode: output.clone(),
-                },
-                Commands::Stop => DaemonCommand::Stop,
-                Commands::Shutdown => DaemonCommand::Shutdown,
-                Commands::Toggle { output } => DaemonCommand::Toggle {
-                    output_mode: output.clone(),
-                },
-                _ => unreachable!(), // Handled in other branches
-            };
+    }
+}
 
-            debug!("Sending command: {:?}", daemon_command);
+/// Aggregate counters for a `watch` session, kept across reconnects.
+struct WatchSummary {
+    started: Instant,
+    events: u64,
+    reconnects: u32,
+    states: BTreeMap<String, u64>,
+}
 
-            match send_command(&mut stream, &daemon_command).await {
-                Ok(response) => match response {
-                    DaemonResponse::Ack => {
-                        println!("OK");
-                    }
-                    DaemonResponse::Status { .. } => {
-                        warn!("Received unexpected Status response for non-status command");
-                        println!("OK");
-                    }
-                    DaemonResponse::Error { message } => {
-                        return Err(anyhow!("Daemon Error: {}", message));
-                    }
-                    _ => {
-                        warn!("Received unexpected response");
-                    }
-                },
-                Err(e) => {
-                    return Err(e).context("Communication Error");
-                }
+impl WatchSummary {
+    fn new(started: Instant) -> Self {
+        Self {
+            started,
+            events: 0,
+            reconnects: 0,
+            states: BTreeMap::new(),
+        }
+    }
+
+    fn write(&self, out: &mut dyn Write, now: Instant) -> io::Result<()> {
+        writeln!(
+            out,
+            "Summary: {} events over {:.1}s, {} reconnects",
+            self.events,
+            now.duration_since(self.started).as_secs_f64(),
+            self.reconnects
+        )?;
+        for (state, count) in &self.states {
+            writeln!(out, "  {}: {}", state, count)?;
+        }
+        Ok(())
+    }
+
+    fn to_json(&self, now: Instant) -> serde_json::Value {
+        json!({
+            "event": "summary",
+            "events": self.events,
+            "duration_secs": now.duration_since(self.started).as_secs_f64(),
+            "reconnects": self.reconnects,
+            "states": self.states,
+        })
+    }
+}
+
+/// Rates derived from the daemon's counters for `metrics --summary`, rounded
+/// to two decimals. A rate is 0 while there is no uptime or no session to
+/// divide by.
+#[derive(Debug, PartialEq)]
+struct MetricsSummary {
+    words_per_hour: f64,
+    words_per_session: f64,
+}
+
+impl MetricsSummary {
+    fn new(words_transcribed: u64, sessions: u64, uptime_secs: u64) -> Self {
+        let rate = |words: f64, per: u64| match per {
+            0 => 0.0,
+            per => (words / per as f64 * 100.0).round() / 100.0,
+        };
+        Self {
+            words_per_hour: rate(words_transcribed as f64 * 3600.0, uptime_secs),
+            words_per_session: rate(words_transcribed as f64, sessions),
+        }
+    }
+}
+
+/// Runs `status`, `start`, `stop` and `watch` against an in-process mock
+/// daemon and reports each check. Fails if any check did not pass.
+#[cfg(feature = "test-support")]
+async fn self_test(out: &mut dyn Write) -> Result<()> {
+    use anyhow::anyhow;
+    use handsfreectl::cli::CliOutputMode;
+    use handsfreectl::testutil::MockDaemon;
+
+    let socket_path =
+        std::env::temp_dir().join(format!("handsfreectl-selftest-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&socket_path);
+    let daemon = MockDaemon::spawn(&socket_path).context("Failed to start mock daemon")?;
+
+    async fn expect_response(
+        socket_path: &Path,
+        command: DaemonCommand,
+        expected: fn(&DaemonResponse) -> bool,
+    ) -> Result<()> {
+        let mut client = DaemonClient::connect(socket_path).await?;
+        let response = client.send(&command).await?;
+        if expected(&response) {
+            Ok(())
+        } else {
+            Err(anyhow!("unexpected response {:?}", response))
+        }
+    }
+
+    async fn expect_event(socket_path: &Path) -> Result<()> {
+        let client = DaemonClient::connect(socket_path).await?;
+        match client.subscribe().await?.next().await {
+            Some(Ok(DaemonResponse::StateChange { .. } | DaemonResponse::Status { .. })) => Ok(()),
+            Some(Ok(other)) => Err(anyhow!("unexpected event {:?}", other)),
+            Some(Err(e)) => Err(e.into()),
+            None => Err(anyhow!("stream closed without an event")),
+        }
+    }
+
+    let checks = [
+        (
+            "status",
+            expect_response(daemon.socket_path(), DaemonCommand::Status, |r| {
+                matches!(r, DaemonResponse::Status { .. })
+            })
+            .await,
+        ),
+        (
+            "start",
+            expect_response(
+                daemon.socket_path(),
+                DaemonCommand::start(vec![CliOutputMode::Keyboard]),
+                |r| matches!(r, DaemonResponse::Ack),
+            )
+            .await,
+        ),
+        (
+            "stop",
+            expect_response(daemon.socket_path(), DaemonCommand::Stop, |r| {
+                matches!(r, DaemonResponse::Ack)
+            })
+            .await,
+        ),
+        ("watch", expect_event(daemon.socket_path()).await),
+    ];
+
+    let mut failures = 0;
+    for (name, result) in &checks {
+        match result {
+            Ok(()) => writeln!(out, "{}: pass", name)?,
+            Err(e) => {
+                failures += 1;
+                writeln!(out, "{}: FAIL ({:#})", name, e)?;
             }
         }
     }
 
+    if failures > 0 {
+        return Err(anyhow!(
+            "{} of {} self-test checks failed",
+            failures,
+            checks.len()
+        ));
+    }
     Ok(())
 }
+
+/// Subscribes to state change notifications over `client`'s connection.
+/// With `emit_connected`, a `connected` line is written to `out` once the
+/// subscription has been sent.
+async fn subscribe(
+    cli: &Cli,
+    client: DaemonClient,
+    emit_connected: bool,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> Result<ResponseStream> {
+    debug!("Sending command: {:?}", DaemonCommand::Subscribe);
+    echo_command(cli, err, &DaemonCommand::Subscribe)?;
+
+    let response_stream = client
+        .subscribe()
+        .await
+        .context("Failed to send subscribe command")?;
+
+    if emit_connected {
+        if cli.json {
+            writeln!(
+                out,
+                "{}",
+                json!({ "event": "connected", "timestamp": iso_timestamp() })
+            )?;
+        } else {
+            writeln!(out, "connected")?;
+        }
+        out.flush()?;
+    }
+
+    Ok(response_stream)
+}
+
+/// The id of the daemon instance behind `client`, asked for with a `Hello`,
+/// to tell whether a reconnect reached the same daemon. `None` for daemons
+/// that don't report one.
+async fn instance_id(
+    cli: &Cli,
+    client: &mut DaemonClient,
+    err: &mut dyn Write,
+) -> Result<Option<String>> {
+    let hello = DaemonCommand::Hello {
+        client_version: env!("CARGO_PKG_VERSION").to_string(),
+    };
+    debug!("Sending command: {:?}", hello);
+    echo_command(cli, err, &hello)?;
+
+    match client.send(&hello).await.context("Failed to send hello")? {
+        DaemonResponse::Hello { instance_id, .. } => Ok(instance_id),
+        other => {
+            debug!("No instance id in answer to hello: {:?}", other);
+            Ok(None)
+        }
+    }
+}
+
+/// A client for `stream` with the connection options from the command
+/// line, reconnecting to `socket_path` when a command is retried.
+fn daemon_client(cli: &Cli, socket_path: &Path, stream: UnixStream) -> DaemonClient {
+    let client = DaemonClient::from_stream(stream)
+        .with_socket_path(socket_path)
+        .with_read_timeout(cli.read_timeout())
+        .with_framing(cli.framing)
+        .with_max_line_len(cli.max_line_length)
+        .with_raw(cli.raw)
+        .with_response_preview(cli.response_preview.unwrap_or(0));
+    match &cli.trace_id {
+        Some(id) => client.with_trace_id(id.as_str()),
+        None => client,
+    }
+}
+
+/// Bounds `connect` by `--connect-timeout`, failing with a `TimedOut` error
+/// so it isn't mistaken for a refused or missing socket.
+async fn within_connect_timeout<T>(
+    limit: Option<Duration>,
+    connect: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let Some(limit) = limit else {
+        return connect.await;
+    };
+    match tokio::time::timeout(limit, connect).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("Connection timed out after {} ms", limit.as_millis()),
+        )
+        .into()),
+    }
+}
+
+/// Bounds the whole `operation` by `--deadline`, failing with a `TimedOut`
+/// error like the per-read timeout does.
+async fn within_deadline<T>(
+    limit: Option<Duration>,
+    operation: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let Some(limit) = limit else {
+        return operation.await;
+    };
+    match tokio::time::timeout(limit, operation).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("Operation deadline exceeded after {} ms", limit.as_millis()),
+        )
+        .into()),
+    }
+}
+
+/// Where `toggle --debounce` keeps the time of the last toggle: next to the
+/// socket, named after it so that daemons sharing a directory don't share
+/// it. Abstract sockets have nowhere to keep it, and a directory others can
+/// write to, such as `/tmp` for the fallback socket, is no place for it.
+fn debounce_stamp_path(socket_path: &Path) -> Result<PathBuf> {
+    if abstract_socket_name(socket_path).is_some() {
+        anyhow::bail!("--debounce needs a socket in the filesystem");
+    }
+    check_socket_dir(socket_path)?;
+    let dir = match socket_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let metadata = fs::metadata(dir)
+        .with_context(|| format!("Failed to inspect socket directory {}", dir.display()))?;
+    if metadata.uid() != getuid().as_raw() || metadata.mode() & 0o022 != 0 {
+        anyhow::bail!(
+            "Socket directory {} is writable by other users, so --debounce can't keep its timestamp there",
+            dir.display()
+        );
+    }
+    let Some(name) = socket_path.file_name() else {
+        anyhow::bail!("Socket path {:?} has no file name", socket_path);
+    };
+    let mut name = name.to_os_string();
+    name.push(".toggle");
+    Ok(socket_path.with_file_name(name))
+}
+
+/// The `toggle --debounce` stamp file, locked while a toggle checks it and
+/// until the daemon has taken the toggle, so that a toggle racing another
+/// one in flight is debounced rather than sent as well.
+struct DebounceStamp {
+    file: Flock<fs::File>,
+}
+
+impl DebounceStamp {
+    /// Opens and locks the stamp at `path`, or returns `None` if another
+    /// toggle holds it. Never waits, and never follows a symlink planted in
+    /// the stamp's place.
+    fn lock(path: &Path) -> Result<Option<Self>> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .custom_flags((OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC).bits())
+            .open(path)
+            .with_context(|| format!("Failed to open toggle timestamp {:?}", path))?;
+        match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+            Ok(file) => Ok(Some(Self { file })),
+            Err((_, Errno::EWOULDBLOCK)) => Ok(None),
+            Err((_, errno)) => Err(io::Error::from(errno))
+                .with_context(|| format!("Failed to lock toggle timestamp {:?}", path)),
+        }
+    }
+
+    /// How long ago the last recorded toggle was, if that is less than
+    /// `window` before `now`. An empty or unreadable stamp never debounces.
+    fn debounced(&mut self, window: Duration, now: SystemTime) -> Option<Duration> {
+        let mut contents = String::new();
+        self.file.rewind().ok()?;
+        self.file.read_to_string(&mut contents).ok()?;
+        let last_ms = contents.trim().parse::<u64>().ok()?;
+        let now_ms = unix_millis(now);
+        // A stamp from the future (clock change) doesn't count
+        let since = Duration::from_millis(now_ms.saturating_sub(last_ms));
+        (last_ms <= now_ms && since < window).then_some(since)
+    }
+
+    /// Records `now` as the time of the last toggle.
+    fn record(&mut self, now: SystemTime) -> Result<()> {
+        self.file.set_len(0)?;
+        self.file.rewind()?;
+        write!(self.file, "{}", unix_millis(now)).context("Failed to write toggle timestamp")
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Connects and subscribes again after a watch stream was lost, retrying
+/// with backoff from `--connect-retry-delay` until `window` has passed.
+/// With `detect_restart`, the new daemon's instance id is asked for first
+/// and returned alongside the stream.
+async fn resubscribe(
+    cli: &Cli,
+    socket_path: &Path,
+    window: Duration,
+    emit_connected: bool,
+    detect_restart: bool,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> Result<(ResponseStream, Option<String>)> {
+    let deadline = Instant::now() + window;
+    let mut delay = cli.connect_retry_delay();
+    loop {
+        match connect_to_daemon(socket_path).await {
+            Ok(stream) => {
+                let mut client = daemon_client(cli, socket_path, stream);
+                let instance = if detect_restart {
+                    instance_id(cli, &mut client, err).await?
+                } else {
+                    None
+                };
+                let stream = subscribe(cli, client, emit_connected, out, err).await?;
+                return Ok((stream, instance));
+            }
+            Err(e) if Instant::now() + delay < deadline => {
+                debug!("Reconnect failed ({}), retrying in {:?}", e, delay);
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!(
+                        "Failed to reconnect to daemon socket at {:?} within {} seconds",
+                        socket_path,
+                        window.as_secs()
+                    )
+                });
+            }
+        }
+    }
+}
+
+/// `--output-path` resolved against the current directory, as the daemon
+/// has a working directory of its own.
+fn absolute_path(path: Option<&Path>) -> Result<Option<PathBuf>> {
+    path.map(|path| {
+        std::path::absolute(path)
+            .with_context(|| format!("Failed to resolve output path {:?}", path))
+    })
+    .transpose()
+}
+
+/// The text for `inject` and `send-text`: `text` itself, or everything on `stdin` when it
+/// is `-`, minus the trailing newline most producers add.
+fn read_text(text: &str, stdin: &mut dyn Read) -> Result<String> {
+    if text != "-" {
+        return Ok(text.to_string());
+    }
+    let mut input = String::new();
+    stdin
+        .read_to_string(&mut input)
+        .context("Failed to read text from stdin")?;
+    let trimmed = input.strip_suffix('\n').unwrap_or(&input);
+    Ok(trimmed.strip_suffix('\r').unwrap_or(trimmed).to_string())
+}
+
+/// Sends each command in `input` (JSON lines, blank lines skipped) over
+/// `client`'s connection and writes each response to `out` as a JSON line. Stops at the
+/// first error response unless `continue_on_error` is set.
+async fn run_batch(
+    cli: &Cli,
+    client: &mut DaemonClient,
+    input: &mut dyn BufRead,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+    continue_on_error: bool,
+) -> Result<()> {
+    let mut failed = None;
+    for (i, line) in input.lines().enumerate() {
+        let line = line.context("Failed to read commands from stdin")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let command: DaemonCommand = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid command on line {}", i + 1))?;
+        if command == DaemonCommand::Subscribe {
+            return Err(anyhow::anyhow!(
+                "Line {}: subscribe can't be used in a batch; use watch instead",
+                i + 1
+            ));
+        }
+
+        debug!("Sending command: {:?}", command);
+        echo_command(cli, err, &command)?;
+        let response = client.send(&command).await.context("Communication Error")?;
+        writeln!(out, "{}", serde_json::to_string(&response)?)?;
+        out.flush()?;
+
+        if let DaemonResponse::Error { message } = response {
+            if !continue_on_error {
+                return Err(DaemonReportedError(sanitize(cli, message)).into());
+            }
+            failed.get_or_insert(message);
+        }
+    }
+    match failed {
+        // Still fail the batch as a whole, after running the rest
+        Some(message) => Err(DaemonReportedError(sanitize(cli, message)).into()),
+        None => Ok(()),
+    }
+}
+
+/// Reports an acknowledged command: `OK`, or `{"ok":true}` with `--json`.
+fn write_ok(cli: &Cli, out: &mut dyn Write) -> io::Result<()> {
+    if cli.json {
+        write_json(cli, out, &json!({ "ok": true }))
+    } else {
+        writeln!(out, "OK")
+    }
+}
+
+/// Reports a `Progress` response received while waiting for a command's answer.
+fn write_progress(
+    cli: &Cli,
+    out: &mut dyn Write,
+    stage: &str,
+    percent: Option<u8>,
+) -> io::Result<()> {
+    if cli.json {
+        // One line per report, like watch events
+        let progress = json!({ "event": "progress", "stage": stage, "percent": percent });
+        writeln!(out, "{}", progress)?;
+    } else if let Some(percent) = percent {
+        writeln!(out, "{}... {}%", stage, percent)?;
+    } else {
+        writeln!(out, "{}...", stage)?;
+    }
+    out.flush()
+}
+
+/// Prints `sessions` as a table with a header row, columns aligned.
+fn write_sessions(out: &mut dyn Write, sessions: &[SessionInfo]) -> io::Result<()> {
+    let mut rows = vec![["ID", "STATE", "OUTPUT", "STARTED"].map(String::from)];
+    rows.extend(sessions.iter().map(|session| {
+        [
+            session.id.clone(),
+            session.state.to_string(),
+            session.output_mode.clone(),
+            session.started_at.clone(),
+        ]
+    }));
+    let widths: Vec<usize> = (0..3)
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+    for [id, state, output_mode, started_at] in rows {
+        writeln!(
+            out,
+            "{:<id_width$}  {:<state_width$}  {:<output_width$}  {}",
+            id,
+            state,
+            output_mode,
+            started_at,
+            id_width = widths[0],
+            state_width = widths[1],
+            output_width = widths[2],
+        )?;
+    }
+    Ok(())
+}
+
+/// Asks the daemon for its current state ahead of a command that depends on
+/// it. The state is returned as reported, before any `--state-alias`, so
+/// decisions don't depend on how the user likes it displayed.
+async fn query_state(
+    cli: &Cli,
+    client: &mut DaemonClient,
+    err: &mut dyn Write,
+) -> Result<ReportedState> {
+    debug!("Sending command: {:?}", DaemonCommand::Status);
+    echo_command(cli, err, &DaemonCommand::Status)?;
+
+    match client
+        .send_retrying(&DaemonCommand::Status, cli.timeout_retries)
+        .await
+        .context("Communication Error")?
+    {
+        DaemonResponse::Status { status } => Ok(status.state),
+        DaemonResponse::Error { message } => {
+            Err(DaemonReportedError(sanitize(cli, message)).into())
+        }
+        other => Err(anyhow::anyhow!(
+            "Unexpected response to status query: {:?}",
+            other
+        )),
+    }
+}
+
+/// The socket to talk to: `--socket`, then the config file, then the
+/// daemon's default location.
+fn resolve_socket_path(cli: &Cli, configured: Option<PathBuf>) -> Result<(PathBuf, SocketSource)> {
+    let given = match (&cli.socket, configured) {
+        (Some(socket_path), _) => Some((socket_path.clone(), SocketSource::Flag)),
+        (None, Some(socket_path)) => Some((socket_path, SocketSource::ConfigFile)),
+        (None, None) => None,
+    };
+    match given {
+        Some((socket_path, source)) => {
+            check_socket_dir(&socket_path)?;
+            Ok((socket_path, source))
+        }
+        None if cli.no_create_runtime_dir => {
+            get_socket_path_no_create().context("Error determining socket path")
+        }
+        None => get_socket_path().context("Error determining socket path"),
+    }
+}
+
+/// Prints the socket path for `config path`, followed by where it came from.
+fn write_socket_path(
+    cli: &Cli,
+    socket_path: &Path,
+    source: &SocketSource,
+    out: &mut dyn Write,
+) -> Result<()> {
+    if cli.json {
+        write_json(
+            cli,
+            out,
+            &json!({ "path": socket_path, "source": source.to_string() }),
+        )?;
+    } else {
+        writeln!(out, "{}", socket_path.display())?;
+        writeln!(out, "({})", source)?;
+    }
+    Ok(())
+}
+
+/// Follows the daemon's state changes for `watch`, the command `cli` must
+/// hold, on a subscription made through `client`. Runs until the stream
+/// closes (or reconnecting gives up), `--count` events were shown or Ctrl-C.
+async fn watch(
+    cli: &Cli,
+    socket_path: &Path,
+    mut client: DaemonClient,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> Result<()> {
+    let Commands::Watch {
+        annotate_transitions,
+        no_flush,
+        ndjson_strict,
+        reconnect_on_error,
+        reconnect,
+        reconnect_window,
+        emit_connected,
+        detect_daemon_restart,
+        initial,
+        summary_on_exit,
+        compact,
+        ref format,
+        ref filter_state,
+        count,
+    } = cli.command
+    else {
+        unreachable!("watch run for {:?}", cli.command);
+    };
+    // `plain`, `short` and `json` render state events like `status`;
+    // anything else is a template
+    let status_format = format
+        .as_deref()
+        .and_then(|format| StatusFormat::from_str(format, true).ok());
+    let template = format.as_ref().filter(|_| status_format.is_none());
+    // Only watch runs until interrupted, so only it handles Ctrl-C.
+    // Listen before subscribing, so that once `connected` is out an
+    // interrupt always ends the watch cleanly.
+    let mut interrupt = tokio::signal::unix::signal(SignalKind::interrupt())
+        .context("Failed to listen for Ctrl-C")?;
+    let mut instance = None;
+    if detect_daemon_restart {
+        instance = instance_id(cli, &mut client, err).await?;
+    }
+    // Shown like any other event, but doesn't count towards --count
+    let mut initial_status = None;
+    if initial {
+        debug!("Sending command: {:?}", DaemonCommand::Status);
+        echo_command(cli, err, &DaemonCommand::Status)?;
+        let response = client
+            .send(&DaemonCommand::Status)
+            .await
+            .context("Failed to get the initial status")?;
+        initial_status = Some(response);
+    }
+    let mut response_stream = subscribe(cli, client, emit_connected, out, err).await?;
+    let mut tracker = TransitionTracker::default();
+    let mut summary = WatchSummary::new(Instant::now());
+    let mut state_events = 0;
+    let mut interrupted = false;
+    // Set when the stream has to be replaced by a new subscription
+    let mut lost = false;
+
+    // Ends early on errors, which still get a summary below
+    let watched = async {
+        loop {
+            if std::mem::take(&mut lost) {
+                tokio::select! {
+                    resubscribed = resubscribe(
+                        cli,
+                        socket_path,
+                        Duration::from_secs(reconnect_window),
+                        emit_connected,
+                        detect_daemon_restart,
+                        out,
+                        err,
+                    ) => {
+                        let (stream, new_instance) = resubscribed?;
+                        response_stream = stream;
+                        summary.reconnects += 1;
+                        // Only a daemon that reports its id can be told apart
+                        if let (Some(previous), Some(current)) = (&instance, &new_instance)
+                            && previous != current
+                        {
+                            info!("Daemon restarted, now instance {}", current);
+                            if cli.json {
+                                writeln!(
+                                    out,
+                                    "{}",
+                                    json!({
+                                        "event": "daemon_restarted",
+                                        "timestamp": iso_timestamp(),
+                                        "instance_id": current,
+                                    })
+                                )?;
+                            } else {
+                                writeln!(out, "daemon restarted")?;
+                            }
+                            out.flush()?;
+                            // Time spent in a state doesn't carry over
+                            tracker = TransitionTracker::default();
+                        }
+                        instance = new_instance.or(instance);
+                    }
+                    _ = interrupt.recv() => {
+                        info!("Interrupted, stopping watch");
+                        interrupted = true;
+                        break;
+                    }
+                }
+            }
+            let is_initial = initial_status.is_some();
+            let next = match initial_status.take() {
+                Some(response) => Some(Ok(response)),
+                None => tokio::select! {
+                    next = response_stream.next() => next,
+                    _ = interrupt.recv() => {
+                        info!("Interrupted, stopping watch");
+                        interrupted = true;
+                        break;
+                    }
+                },
+            };
+            let result = match next {
+                Some(Err(DaemonError::Io { .. })) | None if reconnect => {
+                    info!("Stream to daemon lost, reconnecting...");
+                    lost = true;
+                    continue;
+                }
+                Some(result) => result,
+                None => {
+                    info!("Stream closed by daemon");
+                    break;
+                }
+            };
+            match result {
+                Ok(response) => {
+                    let event = event_name(&response);
+                    summary.events += 1;
+                    match response {
+                        DaemonResponse::StateChange { mut status }
+                        | DaemonResponse::Status { mut status } => {
+                            status.state = normalize_state(cli, status.state);
+                            status.last_error = status.last_error.map(|e| sanitize(cli, e));
+                            *summary.states.entry(status.state.to_string()).or_default() += 1;
+                            let transition = tracker.observe(status.state.as_str(), Instant::now());
+                            if !filter_state.is_empty()
+                                && !filter_state
+                                    .iter()
+                                    .any(|state| state.eq_ignore_ascii_case(status.state.as_str()))
+                            {
+                                continue;
+                            }
+                            if !is_initial {
+                                state_events += 1;
+                            }
+                            if compact {
+                                write!(out, "{}", state_char(&status.state))?;
+                            } else if let Some(status_format) = status_format {
+                                let text = format_watch_status(&status, status_format)?;
+                                writeln!(out, "{}", text)?;
+                            } else if let Some(template) = template {
+                                let fields = [
+                                    ("event", event),
+                                    ("state", status.state.as_str()),
+                                    ("error", status.last_error.as_deref().unwrap_or("")),
+                                    ("ts", &unix_timestamp().to_string()),
+                                ];
+                                writeln!(out, "{}", render_template(template, &fields))?;
+                            } else if cli.json {
+                                let mut event_json = json!({
+                                    "event": event,
+                                    "timestamp": iso_timestamp(),
+                                    "state": status.state,
+                                    "last_error": status.last_error,
+                                });
+                                if let Some((previous, lasted)) = transition
+                                    && annotate_transitions
+                                {
+                                    event_json["previous_state"] = json!(previous);
+                                    event_json["duration_in_previous_secs"] =
+                                        json!(lasted.as_secs());
+                                }
+                                writeln!(out, "{}", event_json)?;
+                            } else {
+                                match transition {
+                                    Some((previous, lasted)) if annotate_transitions => writeln!(
+                                        out,
+                                        "State changed: {} -> {} (after {}s)",
+                                        previous,
+                                        status.state,
+                                        lasted.as_secs()
+                                    )?,
+                                    _ if is_initial => {
+                                        writeln!(out, "Current state: {}", status.state)?
+                                    }
+                                    _ => writeln!(out, "State changed: {}", status.state)?,
+                                }
+                                if let Some(last_error) = status.last_error {
+                                    writeln!(out, "Error: {}", last_error)?;
+                                }
+                            }
+                        }
+                        DaemonResponse::Error { message } => {
+                            let message = sanitize(cli, message);
+                            if let Some(template) = template {
+                                let fields = [
+                                    ("event", event),
+                                    ("state", ""),
+                                    ("error", message.as_str()),
+                                    ("ts", &unix_timestamp().to_string()),
+                                ];
+                                writeln!(out, "{}", render_template(template, &fields))?;
+                            } else if let Some(status_format) = status_format {
+                                writeln!(out, "{}", format_watch_error(&message, status_format))?;
+                            } else if cli.json {
+                                writeln!(
+                                    out,
+                                    "{}",
+                                    json!({
+                                        "event": event,
+                                        "timestamp": iso_timestamp(),
+                                        "message": message,
+                                    })
+                                )?;
+                            } else {
+                                error!("Daemon Error: {}", message);
+                            }
+                        }
+                        _ => {}
+                    }
+                    // Stdout only flushes itself at a newline, which --compact
+                    // never writes, and other writers may not flush at all
+                    if !no_flush {
+                        out.flush()?;
+                    }
+                }
+                Err(e) if ndjson_strict => {
+                    return Err(e).context("Invalid line in watch stream");
+                }
+                Err(e) if reconnect_on_error => {
+                    // The rest of the stream may be corrupt; start over
+                    warn!("{}. Reconnecting...", e);
+                    lost = true;
+                }
+                Err(e) => {
+                    warn!("{}", e);
+                }
+            }
+            if count.is_some_and(|count| state_events >= count) {
+                break;
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    }
+    .await;
+    debug!("Watch ended after {} state events", state_events);
+    if summary_on_exit {
+        if cli.json {
+            writeln!(out, "{}", summary.to_json(Instant::now()))?;
+        } else {
+            summary.write(out, Instant::now())?;
+        }
+    }
+    watched?;
+    if let Some(count) = count
+        && state_events < count
+        && !interrupted
+    {
+        return Err(anyhow::anyhow!(
+            "Watch stream closed after {} of {} state events",
+            state_events,
+            count
+        ));
+    }
+    Ok(())
+}
+
+/// Runs the parsed command against the daemon listening on `socket_path`.
+/// Regular output is written to `out`, diagnostics to `err`.
+async fn run(
+    cli: &Cli,
+    socket_path: &Path,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> Result<()> {
+    let mut sink = io::sink();
+    let out: &mut dyn Write = if cli.quiet && !cli.json {
+        &mut sink
+    } else {
+        out
+    };
+
+    #[cfg(feature = "test-support")]
+    if let Commands::SelfTest = cli.command {
+        return self_test(out).await;
+    }
+
+    // Held until the daemon has taken the toggle, which is when it's recorded
+    let mut debounce_stamp = None;
+    if let Commands::Toggle {
+        debounce: Some(window),
+        ..
+    } = cli.command
+    {
+        match debounce_stamp_path(socket_path) {
+            Ok(path) => {
+                let mut stamp = DebounceStamp::lock(&path)?;
+                let window = Duration::from_millis(window);
+                let debounced = match &mut stamp {
+                    Some(stamp) => stamp
+                        .debounced(window, SystemTime::now())
+                        .map(|since| format!("last toggle was {} ms ago", since.as_millis())),
+                    None => Some("another toggle is in flight".to_string()),
+                };
+                if let Some(reason) = debounced {
+                    if cli.json {
+                        write_json(
+                            cli,
+                            out,
+                            &json!({ "ok": true, "changed": false, "debounced": true }),
+                        )?;
+                    } else {
+                        writeln!(out, "Debounced: {}", reason)?;
+                    }
+                    return Ok(());
+                }
+                debounce_stamp = stamp;
+            }
+            Err(e) => warn!("{:#}; ignoring --debounce", e),
+        }
+    }
+
+    let connect = connect_with_retry(
+        socket_path,
+        cli.connect_retries.saturating_add(1),
+        cli.connect_retry_delay(),
+    );
+    let mut client = match within_connect_timeout(cli.connect_timeout(), connect).await {
+        Ok(stream) => daemon_client(cli, socket_path, stream),
+        Err(e) => {
+            if let Commands::Status {
+                format,
+                no_newline,
+                exit_code_only: false,
+                ..
+            } = cli.command
+                && is_daemon_down(&e)
+            {
+                let status = DaemonStatus {
+                    state: DaemonState::Unknown("Inactive".to_string()).into(),
+                    last_error: None,
+                    idle_timeout_secs: None,
+                    running_since: None,
+                };
+                let format = status_format(cli, format, no_newline);
+                write_status(
+                    out,
+                    &format_status(&status, format, pretty_json(cli))?,
+                    no_newline,
+                )?;
+                return Ok(());
+            }
+
+            return Err(e).context("Connection Error: Is the daemon running?");
+        }
+    };
+
+    match cli.command {
+        Commands::Status {
+            flat,
+            format,
+            no_newline,
+            exit_code_only,
+        } => {
+            debug!("Sending command: {:?}", DaemonCommand::Status);
+            echo_command(cli, err, &DaemonCommand::Status)?;
+
+            match client
+                .send_retrying(&DaemonCommand::Status, cli.timeout_retries)
+                .await
+            {
+                Ok(response) => match response {
+                    DaemonResponse::Status { status } if exit_code_only => {
+                        match state_exit_code(&normalize_state(cli, status.state)) {
+                            0 => {}
+                            code => return Err(StateExitCode(code).into()),
+                        }
+                    }
+                    DaemonResponse::Status { mut status } => {
+                        status.state = normalize_state(cli, status.state);
+                        status.last_error = status.last_error.map(|e| sanitize(cli, e));
+                        let text = if flat {
+                            json_text(cli, &flatten_json(serde_json::to_value(&status)?)?)?
+                        } else {
+                            let format = status_format(cli, format, no_newline);
+                            format_status(&status, format, pretty_json(cli))?
+                        };
+                        write_status(out, &text, no_newline)?;
+                    }
+                    DaemonResponse::Error { message } => {
+                        return Err(DaemonReportedError(sanitize(cli, message)).into());
+                    }
+                    _ => {
+                        warn!("Received unexpected response for Status command");
+                    }
+                },
+                Err(e) => {
+                    return Err(e).context("Communication Error");
+                }
+            }
+        }
+        Commands::Watch { .. } => watch(cli, socket_path, client, out, err).await?,
+        Commands::WaitFor { ref state, timeout } => {
+            let limit = timeout.map_or_else(|| cli.wait_limit(), Duration::from_secs);
+            let status = client.wait_for_state(state, limit).await?;
+            if cli.json {
+                write_json(cli, out, &status)?;
+            } else {
+                writeln!(out, "{}", status.state)?;
+            }
+        }
+        Commands::Metrics { summary } => {
+            debug!("Sending command: {:?}", DaemonCommand::Metrics);
+            echo_command(cli, err, &DaemonCommand::Metrics)?;
+
+            match client
+                .send_retrying(&DaemonCommand::Metrics, cli.timeout_retries)
+                .await
+                .context("Communication Error")?
+            {
+                DaemonResponse::Metrics {
+                    words_transcribed,
+                    sessions,
+                    uptime_secs,
+                    last_session_words,
+                } => {
+                    let mut metrics = vec![
+                        ("words_transcribed", json!(words_transcribed)),
+                        ("sessions", json!(sessions)),
+                        ("uptime_secs", json!(uptime_secs)),
+                        ("last_session_words", json!(last_session_words)),
+                    ];
+                    if summary {
+                        let rates = MetricsSummary::new(words_transcribed, sessions, uptime_secs);
+                        metrics.push(("words_per_hour", json!(rates.words_per_hour)));
+                        metrics.push(("words_per_session", json!(rates.words_per_session)));
+                    }
+                    if cli.json {
+                        let metrics: serde_json::Map<_, _> = metrics
+                            .into_iter()
+                            .map(|(name, value)| (name.to_string(), value))
+                            .collect();
+                        write_json(cli, out, &metrics)?;
+                    } else {
+                        let width = metrics
+                            .iter()
+                            .map(|(name, _)| name.len())
+                            .max()
+                            .unwrap_or(0);
+                        for (name, value) in metrics {
+                            writeln!(out, "{:<width$}  {}", name, value)?;
+                        }
+                    }
+                }
+                DaemonResponse::Error { message } => {
+                    return Err(DaemonReportedError(sanitize(cli, message)).into());
+                }
+                other => {
+                    return Err(anyhow::anyhow!(
+                        "Unexpected response to metrics: {:?}",
+                        other
+                    ));
+                }
+            }
+        }
+        Commands::Sessions => {
+            debug!("Sending command: {:?}", DaemonCommand::Sessions);
+            echo_command(cli, err, &DaemonCommand::Sessions)?;
+
+            match client
+                .send_retrying(&DaemonCommand::Sessions, cli.timeout_retries)
+                .await
+                .context("Communication Error")?
+            {
+                DaemonResponse::Sessions { mut sessions } => {
+                    for session in &mut sessions {
+                        session.id = sanitize(cli, std::mem::take(&mut session.id));
+                        session.state = normalize_state(cli, session.state.clone());
+                        session.output_mode =
+                            sanitize(cli, std::mem::take(&mut session.output_mode));
+                        session.started_at = sanitize(cli, std::mem::take(&mut session.started_at));
+                    }
+                    if cli.json {
+                        write_json(cli, out, &sessions)?;
+                    } else if sessions.is_empty() {
+                        writeln!(out, "No active sessions")?;
+                    } else {
+                        write_sessions(out, &sessions)?;
+                    }
+                }
+                DaemonResponse::Error { message } => {
+                    return Err(DaemonReportedError(sanitize(cli, message)).into());
+                }
+                other => {
+                    return Err(anyhow::anyhow!(
+                        "Unexpected response to sessions: {:?}",
+                        other
+                    ));
+                }
+            }
+        }
+        Commands::Version => {
+            let client_version = env!("CARGO_PKG_VERSION");
+            let hello = DaemonCommand::Hello {
+                client_version: client_version.to_string(),
+            };
+            debug!("Sending command: {:?}", hello);
+            echo_command(cli, err, &hello)?;
+
+            let response = client
+                .send_retrying(&hello, cli.timeout_retries)
+                .await
+                .context("Communication Error")?;
+            // Daemons that predate the handshake answer with an error
+            let daemon = match response {
+                DaemonResponse::Hello {
+                    protocol_version,
+                    daemon_version,
+                    ..
+                } => Some((protocol_version, sanitize(cli, daemon_version))),
+                DaemonResponse::Error { message } => {
+                    debug!("Daemon rejected hello: {}", message);
+                    None
+                }
+                other => {
+                    debug!("Unexpected response to hello: {:?}", other);
+                    None
+                }
+            };
+
+            if cli.json {
+                write_json(
+                    cli,
+                    out,
+                    &json!({
+                        "client_version": client_version,
+                        "daemon_version": daemon.as_ref().map(|(_, version)| version),
+                        "protocol_version": daemon.as_ref().map(|(protocol, _)| protocol),
+                    }),
+                )?;
+            } else {
+                writeln!(out, "handsfreectl {}", client_version)?;
+                match daemon {
+                    Some((protocol_version, daemon_version)) => writeln!(
+                        out,
+                        "handsfreed {} (protocol {})",
+                        daemon_version, protocol_version
+                    )?,
+                    None => writeln!(out, "handsfreed version unavailable")?,
+                }
+            }
+        }
+        Commands::Batch { continue_on_error } => {
+            run_batch(
+                cli,
+                &mut client,
+                &mut io::stdin().lock(),
+                out,
+                err,
+                continue_on_error,
+            )
+            .await?;
+        }
+        Commands::Ping { latency: true } => {
+            debug!("Sending command: {:?}", DaemonCommand::Ping);
+            echo_command(cli, err, &DaemonCommand::Ping)?;
+
+            let latency_ms = client.ping().await?.as_secs_f64() * 1000.0;
+            if cli.json {
+                write_json(cli, out, &json!({ "latency_ms": latency_ms }))?;
+            } else {
+                writeln!(out, "{:.3} ms", latency_ms)?;
+            }
+        }
+        Commands::Ping { latency: false } => {
+            debug!("Sending command: {:?}", DaemonCommand::Ping);
+            echo_command(cli, err, &DaemonCommand::Ping)?;
+
+            match client
+                .send_retrying(&DaemonCommand::Ping, cli.timeout_retries)
+                .await
+                .context("Communication Error")?
+            {
+                DaemonResponse::Ack if cli.json => write_ok(cli, out)?,
+                DaemonResponse::Ack => writeln!(out, "pong")?,
+                DaemonResponse::Error { message } => {
+                    return Err(DaemonReportedError(sanitize(cli, message)).into());
+                }
+                other => {
+                    return Err(anyhow::anyhow!("Unexpected response to ping: {:?}", other));
+                }
+            }
+        }
+        _ => {
+            let toggle_guard = match cli.command {
+                Commands::Toggle {
+                    start_if_stopped,
+                    stop_if_running,
+                    ..
+                } if start_if_stopped || stop_if_running => Some(start_if_stopped),
+                _ => None,
+            };
+            let toggle_query = matches!(cli.command, Commands::Toggle { query: true, .. });
+
+            // Whether the daemon is running, when the status had to be queried first
+            let mut running = None;
+            if cli.require_running || toggle_guard.is_some() || toggle_query {
+                let state = query_state(cli, &mut client, err).await?;
+                if toggle_query && state == DaemonState::Error {
+                    return Err(anyhow::anyhow!(
+                        "Daemon is in an error state; not toggling (check `handsfreectl status`)"
+                    ));
+                }
+                running = Some(state.is_active());
+                let idle = *state == DaemonState::Idle;
+                let state = normalize_state(cli, state);
+                if cli.require_running && running == Some(false) {
+                    return Err(anyhow::anyhow!(
+                        "Daemon is not running (state: {}); command not sent",
+                        state
+                    ));
+                }
+                // Some(true) only starts from idle, Some(false) only stops
+                // from anything else
+                if toggle_guard.is_some_and(|only_start| only_start != idle) {
+                    if cli.json {
+                        write_json(
+                            cli,
+                            out,
+                            &json!({ "ok": true, "changed": false, "state": state }),
+                        )?;
+                    } else {
+                        writeln!(out, "No change: daemon is {}", state)?;
+                    }
+                    return Ok(());
+                }
+            }
+
+            let daemon_command = match &cli.command {
+                Commands::Start {
+                    output,
+                    output_path,
+                    overwrite,
+                    device,
+                    device_index,
+                    resume_session,
+                    output_fallback,
+                } => {
+                    let output_modes = if output.is_empty() {
+                        vec![CliOutputMode::default()]
+                    } else {
+                        output.clone()
+                    };
+                    DaemonCommand::Start(StartCommand {
+                        output_path: absolute_path(output_path.as_deref())?,
+                        append: !*overwrite,
+                        device: device.clone(),
+                        device_index: *device_index,
+                        resume_session: resume_session.clone(),
+                        fallback_output: output_fallback.clone(),
+                        ..StartCommand::new(output_modes)
+                    })
+                }
+                Commands::Restart {
+                    output,
+                    output_path,
+                    overwrite,
+                } => DaemonCommand::Restart {
+                    output_mode: output.clone().unwrap_or_default(),
+                    output_path: absolute_path(output_path.as_deref())?,
+                    append: !*overwrite,
+                },
+                Commands::Stop { .. } => DaemonCommand::Stop,
+                Commands::Pause => DaemonCommand::Pause,
+                Commands::Resume => DaemonCommand::Resume,
+                Commands::Shutdown => DaemonCommand::Shutdown,
+                Commands::Inject { text } => DaemonCommand::InjectText {
+                    text: read_text(text, &mut io::stdin().lock())?,
+                },
+                Commands::SendText { text, output } => DaemonCommand::SendText {
+                    text: read_text(text, &mut io::stdin().lock())?,
+                    output_mode: output.clone(),
+                },
+                Commands::Undo => DaemonCommand::Undo,
+                Commands::Toggle { query: true, .. } if running == Some(true) => {
+                    DaemonCommand::Stop
+                }
+                Commands::Toggle {
+                    output,
+                    output_path,
+                    overwrite,
+                    output_fallback,
+                    query: true,
+                    ..
+                } => DaemonCommand::Start(StartCommand {
+                    output_path: absolute_path(output_path.as_deref())?,
+                    append: !*overwrite,
+                    fallback_output: output_fallback.clone(),
+                    ..StartCommand::new(if output.is_empty() {
+                        vec![CliOutputMode::default()]
+                    } else {
+                        output.clone()
+                    })
+                }),
+                Commands::Toggle {
+                    output,
+                    output_path,
+                    overwrite,
+                    output_fallback,
+                    ..
+                } => DaemonCommand::Toggle {
+                    output_modes: output.clone(),
+                    output_path: absolute_path(output_path.as_deref())?,
+                    append: !*overwrite,
+                    fallback_output: output_fallback.clone(),
+                },
+                _ => unreachable!(), // Handled in other branches
+            };
+
+            debug!("Sending command: {:?}", daemon_command);
+            echo_command(cli, err, &daemon_command)?;
+
+            match client
+                .send_retrying(&daemon_command, cli.timeout_retries)
+                .await
+            {
+                Ok(mut response) => {
+                    // Start reports progress while the model loads, then answers
+                    while let DaemonResponse::Progress { stage, percent } = response {
+                        write_progress(cli, out, &sanitize(cli, stage), percent)?;
+                        response = client
+                            .next_response()
+                            .await
+                            .context("Communication Error")?;
+                    }
+                    match response {
+                        DaemonResponse::Ack => {
+                            // Unlocked as soon as it's recorded, so --then-wait
+                            // doesn't hold up the next toggle
+                            if let Some(mut stamp) = debounce_stamp.take() {
+                                stamp.record(SystemTime::now())?;
+                            }
+                            write_ok(cli, out)?;
+                            let target = match cli.command {
+                                Commands::Stop { wait: true } => {
+                                    cli.then_wait.as_deref().or(Some("idle"))
+                                }
+                                _ => cli.then_wait.as_deref(),
+                            };
+                            if let Some(target) = target {
+                                debug!("Waiting for daemon to reach state '{}'", target);
+                                client.wait_for_state(target, cli.wait_limit()).await?;
+                            }
+                        }
+                        DaemonResponse::Status { .. } => {
+                            warn!("Received unexpected Status response for non-status command");
+                            write_ok(cli, out)?;
+                        }
+                        DaemonResponse::Error { message } => {
+                            return Err(DaemonReportedError(sanitize(cli, message)).into());
+                        }
+                        _ => {
+                            warn!("Received unexpected response");
+                        }
+                    }
+                }
+                Err(e) => {
+                    return Err(e).context("Communication Error");
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use handsfreectl::testutil::MockDaemon;
+
+    const ACK: &str = r#"{"response_type":"ack"}"#;
+
+    fn state_change(state: &str) -> String {
+        format!(
+            r#"{{"response_type":"state_change","status":{{"state":"{}","last_error":null}}}}"#,
+            state
+        )
+    }
+
+    fn status_is(state: &str) -> String {
+        format!(
+            r#"{{"response_type":"status","status":{{"state":"{}","last_error":null}}}}"#,
+            state
+        )
+    }
+
+    async fn run_args(args: &[&str], socket_path: &Path) -> (Result<()>, String, String) {
+        let cli = Cli::try_parse_from(args).unwrap();
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let result = run(&cli, socket_path, &mut out, &mut err).await;
+        (
+            result,
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(err).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_echo_command_matches_sent_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| vec![ACK.to_string()]).unwrap();
+
+        let (result, out, err) = run_args(
+            &[
+                "handsfreectl",
+                "--echo-command",
+                "start",
+                "--output",
+                "clipboard",
+            ],
+            &socket_path,
+        )
+        .await;
+        result.unwrap();
+
+        let received = daemon.finish().await;
+        assert_eq!(received.len(), 1);
+        assert_eq!(err, format!("{}\n", received[0]));
+        assert_eq!(out, "OK\n");
+    }
+
+    #[test]
+    fn test_transition_tracker_durations() {
+        let start = Instant::now();
+        let mut tracker = TransitionTracker::default();
+
+        assert_eq!(tracker.observe("idle", start), None);
+        // Repeating the current state is not a transition
+        assert_eq!(
+            tracker.observe("idle", start + Duration::from_secs(5)),
+            None
+        );
+        assert_eq!(
+            tracker.observe("listening", start + Duration::from_secs(42)),
+            Some(("idle".to_string(), Duration::from_secs(42)))
+        );
+        assert_eq!(
+            tracker.observe("idle", start + Duration::from_secs(50)),
+            Some(("listening".to_string(), Duration::from_secs(8)))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch_annotate_transitions() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| {
+            ["idle", "listening", "listening", "idle"]
+                .iter()
+                .map(|state| state_change(state))
+                .collect()
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(
+            &[
+                "handsfreectl",
+                "watch",
+                "--no-initial",
+                "--annotate-transitions",
+            ],
+            &socket_path,
+        )
+        .await;
+        result.unwrap();
+        daemon.finish().await;
+
+        assert_eq!(
+            out,
+            "State changed: idle\n\
+             State changed: idle -> listening (after 0s)\n\
+             State changed: listening\n\
+             State changed: listening -> idle (after 0s)\n"
+        );
+    }
+
+    // Writer that counts how often it gets flushed
+    #[derive(Default)]
+    struct FlushCounter {
+        buf: Vec<u8>,
+        flushes: usize,
+    }
+
+    impl Write for FlushCounter {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.buf.extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    async fn count_watch_flushes(args: &[&str]) -> usize {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| {
+            vec![state_change("idle"), state_change("listening")]
+        })
+        .unwrap();
+
+        let cli = Cli::try_parse_from(args).unwrap();
+        let mut out = FlushCounter::default();
+        run(&cli, &socket_path, &mut out, &mut io::sink())
+            .await
+            .unwrap();
+        daemon.finish().await;
+
+        assert_eq!(
+            String::from_utf8(out.buf).unwrap(),
+            "State changed: idle\nState changed: listening\n"
+        );
+        out.flushes
+    }
+
+    #[tokio::test]
+    async fn test_watch_flushes_each_event() {
+        assert_eq!(
+            count_watch_flushes(&["handsfreectl", "watch", "--no-initial"]).await,
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch_no_flush() {
+        assert_eq!(
+            count_watch_flushes(&["handsfreectl", "watch", "--no-initial", "--no-flush"]).await,
+            0
+        );
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    async fn test_selftest_all_pass() {
+        let mut out = Vec::new();
+        self_test(&mut out).await.unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "status: pass\nstart: pass\nstop: pass\nwatch: pass\n"
+        );
+    }
+
+    #[test]
+    fn test_flatten_json() {
+        let nested = serde_json::json!({
+            "status": {"state": "idle", "last_error": null},
+            "output_mode": "keyboard"
+        });
+        assert_eq!(
+            flatten_json(nested).unwrap(),
+            serde_json::json!({"state": "idle", "last_error": null, "output_mode": "keyboard"})
+        );
+
+        let colliding = serde_json::json!({
+            "state": "idle",
+            "session": {"state": "listening"}
+        });
+        assert_eq!(
+            flatten_json(colliding).unwrap_err().to_string(),
+            "Can't flatten the status: field 'state' appears more than once"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 2, |_| {
+            vec![
+                state_change("listening"),
+                r#"{"response_type":"error","message":"Mic glitch"}"#.to_string(),
+                state_change("processing"),
+                state_change("idle"),
+            ]
+        })
+        .unwrap();
+
+        // Errors don't count towards the limit
+        let (result, out, _) = run_args(
+            &["handsfreectl", "watch", "--no-initial", "--count", "2"],
+            &socket_path,
+        )
+        .await;
+        result.unwrap();
+        assert_eq!(out, "State changed: listening\nState changed: processing\n");
+
+        let (result, out, _) = run_args(
+            &["handsfreectl", "watch", "--no-initial", "--count", "4"],
+            &socket_path,
+        )
+        .await;
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Watch stream closed after 3 of 4 state events"
+        );
+        assert_eq!(out.lines().count(), 3);
+        daemon.finish().await;
+    }
+
+    #[tokio::test]
+    async fn test_watch_on_client() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| {
+            vec![state_change("listening"), state_change("idle")]
+        })
+        .unwrap();
+
+        // Follows the client it is handed, without connecting itself
+        let cli = Cli::parse_from(["handsfreectl", "watch", "--no-initial", "--count", "2"]);
+        let client = DaemonClient::connect(&socket_path).await.unwrap();
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        watch(&cli, &socket_path, client, &mut out, &mut err)
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "State changed: listening\nState changed: idle\n"
+        );
+        assert_eq!(daemon.finish().await, [r#"{"command":"subscribe"}"#]);
+    }
+
+    #[tokio::test]
+    async fn test_watch_initial() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 3, |command| match command {
+            DaemonCommand::Status => vec![status_is("idle")],
+            _ => vec![state_change("listening"), state_change("processing")],
+        })
+        .unwrap();
+
+        // The current state comes first, without counting as a change
+        let (result, out, _) =
+            run_args(&["handsfreectl", "watch", "--count", "1"], &socket_path).await;
+        result.unwrap();
+        assert_eq!(out, "Current state: idle\nState changed: listening\n");
+
+        let (result, out, _) = run_args(
+            &["handsfreectl", "--json", "watch", "--count", "1"],
+            &socket_path,
+        )
+        .await;
+        result.unwrap();
+        let first: serde_json::Value = serde_json::from_str(out.lines().next().unwrap()).unwrap();
+        assert_eq!(first["event"], "status");
+        assert_eq!(first["state"], "idle");
+
+        let (result, out, _) = run_args(
+            &[
+                "handsfreectl",
+                "watch",
+                "--annotate-transitions",
+                "--count",
+                "1",
+            ],
+            &socket_path,
+        )
+        .await;
+        result.unwrap();
+        assert!(out.starts_with("Current state: idle\nState changed: idle -> listening (after "));
+
+        let received = daemon.finish().await;
+        assert_eq!(
+            received,
+            [r#"{"command":"status"}"#, r#"{"command":"subscribe"}"#].repeat(3)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch_initial_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |command| match command {
+            DaemonCommand::Status => {
+                vec![r#"{"response_type":"error","message":"Model loading"}"#.to_string()]
+            }
+            _ => vec![state_change("listening")],
+        })
+        .unwrap();
+
+        // Reported like an error event, then the subscription goes ahead
+        let (result, out, _) = run_args(
+            &["handsfreectl", "--json", "watch", "--count", "1"],
+            &socket_path,
+        )
+        .await;
+        daemon.finish().await;
+        result.unwrap();
+        let events: Vec<serde_json::Value> = out
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"], "error");
+        assert_eq!(events[0]["message"], "Model loading");
+        assert_eq!(events[1]["state"], "listening");
+    }
+
+    #[tokio::test]
+    async fn test_watch_filter_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 2, |_| {
+            vec![
+                state_change("idle"),
+                state_change("listening"),
+                state_change("processing"),
+                state_change("Listening"),
+                state_change("idle"),
+            ]
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(
+            &[
+                "handsfreectl",
+                "watch",
+                "--no-initial",
+                "--filter-state",
+                "LISTENING,idle",
+            ],
+            &socket_path,
+        )
+        .await;
+        result.unwrap();
+        assert_eq!(
+            out,
+            "State changed: idle\nState changed: listening\nState changed: Listening\nState changed: idle\n"
+        );
+
+        // Wait until the daemon is processing
+        let (result, out, _) = run_args(
+            &[
+                "handsfreectl",
+                "watch",
+                "--no-initial",
+                "--filter-state",
+                "processing",
+                "--count",
+                "1",
+            ],
+            &socket_path,
+        )
+        .await;
+        result.unwrap();
+        assert_eq!(out, "State changed: processing\n");
+        daemon.finish().await;
+    }
+
+    #[test]
+    fn test_format_status() {
+        let status = DaemonStatus {
+            state: DaemonState::Error.into(),
+            last_error: Some("Model failed".to_string()),
+            idle_timeout_secs: None,
+            running_since: None,
+        };
+
+        assert_eq!(
+            format_status(&status, StatusFormat::Plain, false).unwrap(),
+            "error\nModel failed"
+        );
+        assert_eq!(
+            format_status(&status, StatusFormat::Json, false).unwrap(),
+            r#"{"state":"error","last_error":"Model failed"}"#
+        );
+        assert_eq!(
+            format_status(&status, StatusFormat::Short, false).unwrap(),
+            "error"
+        );
+
+        let status = DaemonStatus {
+            state: DaemonState::Running.into(),
+            last_error: None,
+            idle_timeout_secs: None,
+            running_since: None,
+        };
+        assert_eq!(
+            format_status(&status, StatusFormat::Plain, false).unwrap(),
+            "running"
+        );
+        assert_eq!(
+            format_status(&status, StatusFormat::Short, false).unwrap(),
+            "running"
+        );
+
+        let status = DaemonStatus {
+            state: DaemonState::Listening.into(),
+            last_error: None,
+            idle_timeout_secs: Some(300),
+            running_since: Some("2025-01-01T12:00:00Z".to_string()),
+        };
+        assert_eq!(
+            format_status(&status, StatusFormat::Plain, false).unwrap(),
+            "listening\nRunning since: 2025-01-01T12:00:00Z\nIdle timeout: 300s"
+        );
+        assert_eq!(
+            format_status(&status, StatusFormat::Json, false).unwrap(),
+            r#"{"state":"listening","last_error":null,"idle_timeout_secs":300,"running_since":"2025-01-01T12:00:00Z"}"#
+        );
+        assert_eq!(
+            format_status(&status, StatusFormat::Short, false).unwrap(),
+            "listening"
+        );
+    }
+
+    #[test]
+    fn test_format_status_pretty() {
+        let status = DaemonStatus {
+            state: DaemonState::Idle.into(),
+            last_error: None,
+            idle_timeout_secs: None,
+            running_since: None,
+        };
+        let format = |args: &[&str]| {
+            let cli = Cli::try_parse_from(args).unwrap();
+            format_status(&status, StatusFormat::Json, pretty_json(&cli)).unwrap()
+        };
+
+        assert_eq!(
+            format(&["handsfreectl", "--json", "--pretty", "status"]),
+            "{\n  \"state\": \"idle\",\n  \"last_error\": null\n}"
+        );
+        let compact = r#"{"state":"idle","last_error":null}"#;
+        assert_eq!(format(&["handsfreectl", "--json", "status"]), compact);
+        // Only indents --json output
+        assert_eq!(
+            format(&["handsfreectl", "--pretty", "status", "--format", "json"]),
+            compact
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_flat() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| {
+            vec![
+                r#"{"response_type":"status","status":{"state":"error","last_error":"Model failed"}}"#
+                    .to_string(),
+            ]
+        }).unwrap();
+
+        let (result, out, _) = run_args(&["handsfreectl", "status", "--flat"], &socket_path).await;
+        result.unwrap();
+        daemon.finish().await;
+
+        let value: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert!(value.get("status").is_none());
+        assert_eq!(
+            value,
+            serde_json::json!({"state": "error", "last_error": "Model failed"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_no_newline() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 2, |_| {
+            vec![
+                r#"{"response_type":"status","status":{"state":"error","last_error":"Model failed"}}"#
+                    .to_string(),
+            ]
+        }).unwrap();
+
+        // The error line is left out unless a format asks for it
+        let (result, out, _) =
+            run_args(&["handsfreectl", "status", "--no-newline"], &socket_path).await;
+        result.unwrap();
+        assert_eq!(out, "error");
+
+        let (result, out, _) = run_args(
+            &[
+                "handsfreectl",
+                "status",
+                "--no-newline",
+                "--format",
+                "plain",
+            ],
+            &socket_path,
+        )
+        .await;
+        result.unwrap();
+        assert_eq!(out, "error\nModel failed");
+        daemon.finish().await;
+
+        let missing = dir.path().join("missing.sock");
+        let (result, out, _) =
+            run_args(&["handsfreectl", "status", "--no-newline"], &missing).await;
+        result.unwrap();
+        assert_eq!(out, "Inactive");
+    }
+
+    #[tokio::test]
+    async fn test_start_then_wait() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |command| match command {
+            DaemonCommand::Status => vec![
+                r#"{"response_type":"status","status":{"state":"idle","last_error":null}}"#
+                    .to_string(),
+            ],
+            DaemonCommand::Subscribe => vec![state_change("starting"), state_change("running")],
+            _ => vec![ACK.to_string()],
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(
+            &["handsfreectl", "start", "--then-wait", "running"],
+            &socket_path,
+        )
+        .await;
+        result.unwrap();
+
+        let received = daemon.finish().await;
+        assert_eq!(
+            received,
+            [
+                r#"{"command":"start","output_modes":["keyboard"]}"#,
+                r#"{"command":"status"}"#,
+                r#"{"command":"subscribe"}"#
+            ]
+        );
+        assert_eq!(out, "OK\n");
+    }
+
+    #[tokio::test]
+    async fn test_restart_output_path_is_absolute() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| vec![ACK.to_string()]).unwrap();
+
+        let args = [
+            "handsfreectl",
+            "restart",
+            "--output",
+            "file",
+            "--output-path",
+            "notes.txt",
+        ];
+        let (result, _, _) = run_args(&args, &socket_path).await;
+        result.unwrap();
+
+        let path = env::current_dir().unwrap().join("notes.txt");
+        let expected = json!({ "command": "restart", "output_mode": "file", "output_path": path });
+        assert_eq!(daemon.finish().await, [expected.to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 2, |command| match command {
+            DaemonCommand::Status => vec![status_is("idle")],
+            DaemonCommand::Subscribe => vec![state_change("starting"), state_change("listening")],
+            _ => vec![ACK.to_string()],
+        })
+        .unwrap();
+
+        let (result, out, _) =
+            run_args(&["handsfreectl", "wait-for", "Listening"], &socket_path).await;
+        result.unwrap();
+        assert_eq!(out, "listening\n");
+
+        // Already there: no need to subscribe
+        let (result, out, _) = run_args(&["handsfreectl", "wait-for", "idle"], &socket_path).await;
+        result.unwrap();
+        assert_eq!(out, "idle\n");
+
+        let received = daemon.finish().await;
+        assert_eq!(
+            received,
+            [
+                r#"{"command":"status"}"#,
+                r#"{"command":"subscribe"}"#,
+                r#"{"command":"status"}"#
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stop_wait_until_idle() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |command| match command {
+            DaemonCommand::Status => vec![status_is("processing")],
+            DaemonCommand::Subscribe => vec![state_change("processing"), state_change("idle")],
+            _ => vec![ACK.to_string()],
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(&["handsfreectl", "stop", "--wait"], &socket_path).await;
+        let received = daemon.finish().await;
+
+        result.unwrap();
+        assert_eq!(out, "OK\n");
+        assert_eq!(
+            received,
+            [
+                r#"{"command":"stop"}"#,
+                r#"{"command":"status"}"#,
+                r#"{"command":"subscribe"}"#
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stop_wait_daemon_never_idle() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |command| match command {
+            DaemonCommand::Status => vec![status_is("processing")],
+            DaemonCommand::Subscribe => vec![state_change("processing")],
+            _ => vec![ACK.to_string()],
+        })
+        .unwrap();
+
+        let (result, _, _) = run_args(&["handsfreectl", "stop", "--wait"], &socket_path).await;
+        daemon.finish().await;
+
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("before daemon reached state 'idle'")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_then_wait_state_never_reached() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |command| match command {
+            DaemonCommand::Status => vec![
+                r#"{"response_type":"status","status":{"state":"listening","last_error":null}}"#
+                    .to_string(),
+            ],
+            DaemonCommand::Subscribe => vec![state_change("listening")],
+            _ => vec![ACK.to_string()],
+        })
+        .unwrap();
+
+        let (result, _, _) = run_args(
+            &["handsfreectl", "stop", "--then-wait", "idle"],
+            &socket_path,
+        )
+        .await;
+        daemon.finish().await;
+
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("before daemon reached state 'idle'")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resume_unknown_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| {
+            vec![r#"{"response_type":"error","message":"Unknown session 'gone'"}"#.to_string()]
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(
+            &["handsfreectl", "start", "--resume-session", "gone"],
+            &socket_path,
+        )
+        .await;
+
+        let received = daemon.finish().await;
+        assert_eq!(
+            received,
+            [r#"{"command":"start","output_modes":["keyboard"],"resume_session":"gone"}"#]
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Daemon Error: Unknown session 'gone'"
+        );
+        assert!(out.is_empty());
+    }
+
+    fn spawn_daemon_with_malformed_event(socket_path: &Path) -> MockDaemon {
+        MockDaemon::scripted(socket_path, 1, |_| {
+            vec![
+                state_change("idle"),
+                "{not json".to_string(),
+                state_change("listening"),
+            ]
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_watch_skips_malformed_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = spawn_daemon_with_malformed_event(&socket_path);
+
+        let (result, out, _) =
+            run_args(&["handsfreectl", "watch", "--no-initial"], &socket_path).await;
+        daemon.finish().await;
+
+        result.unwrap();
+        assert_eq!(out, "State changed: idle\nState changed: listening\n");
+    }
+
+    #[tokio::test]
+    async fn test_watch_ndjson_strict() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = spawn_daemon_with_malformed_event(&socket_path);
+
+        let (result, out, _) = run_args(
+            &["handsfreectl", "watch", "--no-initial", "--ndjson-strict"],
+            &socket_path,
+        )
+        .await;
+        daemon.finish().await;
+
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Invalid line in watch stream")
+        );
+        assert_eq!(out, "State changed: idle\n");
+    }
+
+    #[tokio::test]
+    async fn test_status_timeout_retries() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        // Stay silent on the first connection, answer on the second
+        let connections = std::sync::atomic::AtomicUsize::new(0);
+        let daemon = MockDaemon::scripted(&socket_path, 2, move |_| {
+            match connections.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => vec![],
+                _ => vec![status_is("idle")],
+            }
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(
+            &[
+                "handsfreectl",
+                "--timeout",
+                "1",
+                "--timeout-retries",
+                "1",
+                "status",
+            ],
+            &socket_path,
+        )
+        .await;
+        daemon.finish().await;
+
+        result.unwrap();
+        assert_eq!(out, "idle\n");
+    }
+
+    #[test]
+    fn test_render_template() {
+        let fields = [
+            ("event", "state_change"),
+            ("state", "listening"),
+            ("error", ""),
+            ("ts", "1700000000"),
+        ];
+        assert_eq!(
+            render_template("{ts} {event}: {state}{error}", &fields),
+            "1700000000 state_change: listening"
+        );
+
+        let fields = [
+            ("event", "error"),
+            ("state", ""),
+            ("error", "Model {state} failed"),
+            ("ts", "1700000000"),
+        ];
+        // Values are not expanded again, unknown and unclosed braces are kept
+        assert_eq!(
+            render_template("{event}: {error} {unknown} {", &fields),
+            "error: Model {state} failed {unknown} {"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch_format_template() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| {
+            vec![
+                state_change("listening"),
+                r#"{"response_type":"error","message":"Mic unplugged"}"#.to_string(),
+            ]
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(
+            &[
+                "handsfreectl",
+                "watch",
+                "--no-initial",
+                "--format",
+                "<{event}|{state}|{error}>",
+            ],
+            &socket_path,
+        )
+        .await;
+        daemon.finish().await;
+
+        result.unwrap();
+        assert_eq!(out, "<state_change|listening|>\n<error||Mic unplugged>\n");
+    }
+
+    #[tokio::test]
+    async fn test_watch_format_keywords() {
+        let events = || {
+            vec![
+                state_change("listening"),
+                r#"{"response_type":"error","message":"Mic unplugged"}"#.to_string(),
+                r#"{"response_type":"state_change","status":{"state":"error","last_error":"Model failed"}}"#
+                    .to_string(),
+            ]
+        };
+        let cases = [
+            ("short", "listening\nError: Mic unplugged\nerror\n"),
+            ("SHORT", "listening\nError: Mic unplugged\nerror\n"),
+            (
+                "plain",
+                "listening\nError: Mic unplugged\nerror; Model failed\n",
+            ),
+            (
+                "json",
+                concat!(
+                    r#"{"state":"listening","last_error":null}"#,
+                    "\n",
+                    r#"{"error":"Mic unplugged"}"#,
+                    "\n",
+                    r#"{"state":"error","last_error":"Model failed"}"#,
+                    "\n"
+                ),
+            ),
+        ];
+        for (format, expected) in cases {
+            let dir = tempfile::tempdir().unwrap();
+            let socket_path = dir.path().join("test.sock");
+            let daemon = MockDaemon::scripted(&socket_path, 1, move |_| events()).unwrap();
+
+            let (result, out, _) = run_args(
+                &[
+                    "handsfreectl",
+                    "--json",
+                    "--pretty",
+                    "watch",
+                    "--no-initial",
+                    "--format",
+                    format,
+                ],
+                &socket_path,
+            )
+            .await;
+            daemon.finish().await;
+
+            result.unwrap();
+            assert_eq!(out, expected, "--format {format}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_watch_reconnect_on_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let subscriptions = std::sync::atomic::AtomicUsize::new(0);
+        let daemon = MockDaemon::scripted(&socket_path, 2, move |_| {
+            if subscriptions.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                vec![state_change("idle"), "{not json".to_string()]
+            } else {
+                vec![state_change("listening")]
+            }
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(
+            &[
+                "handsfreectl",
+                "watch",
+                "--no-initial",
+                "--reconnect-on-error",
+            ],
+            &socket_path,
+        )
+        .await;
+        let received = daemon.finish().await;
+
+        result.unwrap();
+        assert_eq!(received, [r#"{"command":"subscribe"}"#; 2]);
+        assert_eq!(out, "State changed: idle\nState changed: listening\n");
+    }
+
+    #[tokio::test]
+    async fn test_watch_reconnect_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let first = MockDaemon::scripted(&socket_path, 1, |_| vec![state_change("idle")]).unwrap();
+
+        // Restart the daemon on the same path once the first one has hung up
+        let restart_path = socket_path.clone();
+        let restarted = tokio::spawn(async move {
+            let received = first.finish().await;
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            let second =
+                MockDaemon::scripted(&restart_path, 1, |_| vec![state_change("listening")])
+                    .unwrap();
+            (received, second.finish().await)
+        });
+
+        let (result, out, _) = run_args(
+            &[
+                "handsfreectl",
+                "--connect-retry-delay",
+                "50",
+                "watch",
+                "--no-initial",
+                "--reconnect",
+                "--count",
+                "2",
+            ],
+            &socket_path,
+        )
+        .await;
+        let (first_received, second_received) = restarted.await.unwrap();
+
+        result.unwrap();
+        assert_eq!(first_received, [r#"{"command":"subscribe"}"#]);
+        assert_eq!(second_received, [r#"{"command":"subscribe"}"#]);
+        assert_eq!(out, "State changed: idle\nState changed: listening\n");
+    }
+
+    #[tokio::test]
+    async fn test_watch_detect_daemon_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let hellos = std::sync::atomic::AtomicUsize::new(0);
+        let daemon = MockDaemon::scripted(&socket_path, 3, move |command| match command {
+            DaemonCommand::Hello { .. } => {
+                // The same instance for the first reconnect, a new one after
+                let hello = hellos.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let instance_id = if hello < 2 { "first" } else { "second" };
+                vec![
+                    json!({
+                        "response_type": "hello",
+                        "protocol_version": 1,
+                        "daemon_version": "mock",
+                        "instance_id": instance_id,
+                    })
+                    .to_string(),
+                ]
+            }
+            _ => vec![state_change("idle")],
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(
+            &[
+                "handsfreectl",
+                "--connect-retry-delay",
+                "50",
+                "watch",
+                "--no-initial",
+                "--reconnect",
+                "--detect-daemon-restart",
+                "--count",
+                "3",
+            ],
+            &socket_path,
+        )
+        .await;
+        let received = daemon.finish().await;
+
+        result.unwrap();
+        assert_eq!(
+            out,
+            "State changed: idle\nState changed: idle\ndaemon restarted\nState changed: idle\n"
+        );
+        let hello = format!(
+            r#"{{"command":"hello","client_version":"{}"}}"#,
+            env!("CARGO_PKG_VERSION")
+        );
+        let subscribe = r#"{"command":"subscribe"}"#;
+        assert_eq!(received, [hello.as_str(), subscribe].repeat(3));
+    }
+
+    #[tokio::test]
+    async fn test_watch_reconnect_gives_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| vec![state_change("idle")]).unwrap();
+
+        let (result, out, _) = run_args(
+            &[
+                "handsfreectl",
+                "--connect-retry-delay",
+                "50",
+                "watch",
+                "--no-initial",
+                "--reconnect",
+                "--reconnect-window",
+                "1",
+            ],
+            &socket_path,
+        )
+        .await;
+        daemon.finish().await;
+
+        assert_eq!(out, "State changed: idle\n");
+        assert!(
+            format!("{:#}", result.unwrap_err())
+                .starts_with("Failed to reconnect to daemon socket")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch_emit_connected() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let subscriptions = std::sync::atomic::AtomicUsize::new(0);
+        let daemon = MockDaemon::scripted(&socket_path, 2, move |_| {
+            if subscriptions.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                vec!["{not json".to_string()]
+            } else {
+                vec![state_change("idle")]
+            }
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(
+            &[
+                "handsfreectl",
+                "watch",
+                "--no-initial",
+                "--emit-connected",
+                "--reconnect-on-error",
+            ],
+            &socket_path,
+        )
+        .await;
+        daemon.finish().await;
+
+        result.unwrap();
+        assert_eq!(out, "connected\nconnected\nState changed: idle\n");
+    }
+
+    #[tokio::test]
+    async fn test_watch_summary_on_exit() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let subscriptions = std::sync::atomic::AtomicUsize::new(0);
+        let daemon = MockDaemon::scripted(&socket_path, 2, move |_| {
+            if subscriptions.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                vec![
+                    state_change("idle"),
+                    state_change("listening"),
+                    "{not json".to_string(),
+                ]
+            } else {
+                vec![
+                    state_change("idle"),
+                    r#"{"response_type":"ack"}"#.to_string(),
+                ]
+            }
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(
+            &[
+                "handsfreectl",
+                "watch",
+                "--no-initial",
+                "--reconnect-on-error",
+                "--summary-on-exit",
+            ],
+            &socket_path,
+        )
+        .await;
+        daemon.finish().await;
+
+        result.unwrap();
+        let summary = out.split_once("Summary: ").unwrap().1;
+        assert!(summary.starts_with("4 events over "), "{}", summary);
+        assert!(
+            summary.ends_with(", 1 reconnects\n  idle: 2\n  listening: 1\n"),
+            "{}",
+            summary
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch_summary_on_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| {
+            vec![state_change("idle"), "{not json".to_string()]
+        })
+        .unwrap();
+
+        let args = [
+            "handsfreectl",
+            "watch",
+            "--no-initial",
+            "--ndjson-strict",
+            "--summary-on-exit",
+        ];
+        let (result, out, _) = run_args(&args, &socket_path).await;
+        daemon.finish().await;
+
+        assert!(result.is_err());
+        let summary = out.split_once("Summary: ").unwrap().1;
+        assert!(summary.starts_with("1 events over "), "{}", summary);
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout() {
+        // A connection that never completes, as with a wedged listener
+        let err = within_connect_timeout(
+            Some(Duration::from_millis(50)),
+            std::future::pending::<Result<()>>(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Connection timed out after 50 ms");
+        assert_eq!(error_kind(&err), "timeout");
+        assert!(!is_daemon_down(&err));
+
+        // Other failures are passed through untouched
+        let err = within_connect_timeout(Some(Duration::from_secs(5)), async {
+            Err::<(), _>(io::Error::from(io::ErrorKind::ConnectionRefused).into())
+        })
+        .await
+        .unwrap_err();
+        assert!(is_daemon_down(&err));
+
+        within_connect_timeout(None, async { Ok(()) })
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_should_colorize() {
+        assert!(should_colorize(false, None, true));
+        assert!(!should_colorize(false, None, false));
+        // NO_COLOR counts only when set to something
+        assert!(should_colorize(false, Some(OsStr::new("")), true));
+        assert!(!should_colorize(false, Some(OsStr::new("1")), true));
+        // --plain wins even on a terminal
+        assert!(!should_colorize(true, None, true));
+        assert!(!should_colorize(true, Some(OsStr::new("")), true));
+    }
+
+    #[test]
+    fn test_json_log_format() {
+        // Shares what the logger writes with the test
+        #[derive(Clone, Default)]
+        struct Buffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+        impl Write for Buffer {
+            fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(data);
+                Ok(data.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = Buffer::default();
+        let logger = logger_builder("handsfreectl=warn", LogFormat::Json, true)
+            .target(env_logger::Target::Pipe(Box::new(buffer.clone())))
+            .build();
+        log::Log::log(
+            &logger,
+            &log::Record::builder()
+                .args(format_args!("Socket \"{}\" is stale", "x.sock"))
+                .level(log::Level::Warn)
+                .target("handsfreectl::daemon")
+                .build(),
+        );
+
+        let written = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let record: serde_json::Value = serde_json::from_str(written.trim_end()).unwrap();
+        assert_eq!(record["level"], "WARN");
+        assert_eq!(record["target"], "handsfreectl::daemon");
+        assert_eq!(record["message"], "Socket \"x.sock\" is stale");
+        assert!(record["timestamp"].as_str().unwrap().ends_with('Z'));
+        assert_eq!(written.lines().count(), 1);
+    }
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(
+            strip_ansi("\x1b[1;31mlistening\x1b[0m\nline two\tend"),
+            "listening\nline two\tend"
+        );
+        assert_eq!(
+            strip_ansi("\x1b]0;title\x07hi\x1b]8;;x\x1b\\there"),
+            "hithere"
+        );
+        assert_eq!(strip_ansi("a\x08b\rc\x1bMd\u{7f}"), "abcd");
+        assert_eq!(strip_ansi("plain text, ünïcode"), "plain text, ünïcode");
+    }
+
+    #[tokio::test]
+    async fn test_status_strip_ansi() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 2, |_| {
+            vec![
+                r#"{"response_type":"status","status":{"state":"\u001b[32midle\u001b[0m","last_error":"bad\u001b[2Jmic"}}"#
+                    .to_string(),
+            ]
+        }).unwrap();
+
+        for flag in ["--strip-ansi", "--plain"] {
+            let (result, out, _) = run_args(&["handsfreectl", "status", flag], &socket_path).await;
+            result.unwrap();
+            assert_eq!(out, "idle\nbadmic\n");
+        }
+        daemon.finish().await;
+    }
+
+    #[tokio::test]
+    async fn test_json_error_connection_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("missing.sock");
+
+        let (result, _, _) = run_args(&["handsfreectl", "start"], &socket_path).await;
+        let e = result.unwrap_err();
+
+        assert_eq!(error_kind(&e), "connection");
+        let report: serde_json::Value =
+            serde_json::from_str(&json_error(error_kind(&e), &format!("{:#}", e), None)).unwrap();
+        assert_eq!(report["error"]["kind"], "connection");
+        assert!(
+            report["error"]["message"]
+                .as_str()
+                .unwrap()
+                .starts_with("Connection Error: Is the daemon running?: Failed to connect")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_json_error_daemon_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| {
+            vec![r#"{"response_type":"error","message":"No microphone"}"#.to_string()]
+        })
+        .unwrap();
+
+        let (result, _, _) = run_args(&["handsfreectl", "stop"], &socket_path).await;
+        daemon.finish().await;
+        let e = result.unwrap_err();
+
+        assert_eq!(error_kind(&e), "daemon");
+        assert_eq!(
+            json_error(error_kind(&e), &format!("{:#}", e), None),
+            r#"{"error":{"kind":"daemon","message":"Daemon Error: No microphone"}}"#
+        );
+    }
+
+    #[test]
+    fn test_json_error_trace_id() {
+        assert_eq!(
+            json_error("timeout", "Read timeout", Some("abc-123")),
+            r#"{"error":{"kind":"timeout","message":"Read timeout","trace_id":"abc-123"}}"#
+        );
+    }
+
+    #[test]
+    fn test_error_kind_protocol_and_timeout() {
+        let parse_err = serde_json::from_str::<DaemonResponse>("{").unwrap_err();
+        let e = anyhow::Error::new(parse_err).context("Failed to parse JSON response");
+        assert_eq!(error_kind(&e), "protocol");
+
+        let e =
+            anyhow::Error::new(io::Error::from(io::ErrorKind::TimedOut)).context("Read timeout");
+        assert_eq!(error_kind(&e), "timeout");
+
+        assert_eq!(error_kind(&anyhow::anyhow!("something else")), "other");
+    }
+
+    #[tokio::test]
+    async fn test_watch_compact() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| {
+            [
+                "idle",
+                "listening",
+                "Processing",
+                "idle",
+                "error",
+                "reloading",
+            ]
+            .into_iter()
+            .map(state_change)
+            .collect()
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(
+            &["handsfreectl", "watch", "--no-initial", "--compact"],
+            &socket_path,
+        )
+        .await;
+        daemon.finish().await;
+
+        result.unwrap();
+        assert_eq!(out, "ILPIE?");
+    }
+
+    #[tokio::test]
+    async fn test_toggle_start_if_stopped_when_running() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |command| match command {
+            DaemonCommand::Status => vec![status_is("listening")],
+            _ => vec![ACK.to_string()],
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(
+            &["handsfreectl", "toggle", "--start-if-stopped"],
+            &socket_path,
+        )
+        .await;
+        result.unwrap();
+        let received = daemon.finish().await;
+
+        assert_eq!(received, [r#"{"command":"status"}"#]);
+        assert_eq!(out, "No change: daemon is listening\n");
+    }
+
+    #[tokio::test]
+    async fn test_toggle_start_if_stopped_when_idle() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |command| match command {
+            DaemonCommand::Status => vec![status_is("idle")],
+            _ => vec![ACK.to_string()],
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(
+            &["handsfreectl", "toggle", "--start-if-stopped"],
+            &socket_path,
+        )
+        .await;
+        result.unwrap();
+        let received = daemon.finish().await;
+
+        assert_eq!(
+            received,
+            [r#"{"command":"status"}"#, r#"{"command":"toggle"}"#]
+        );
+        assert_eq!(out, "OK\n");
+    }
+
+    #[tokio::test]
+    async fn test_toggle_start_if_stopped_when_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |command| match command {
+            DaemonCommand::Status => vec![status_is("error")],
+            _ => vec![ACK.to_string()],
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(
+            &["handsfreectl", "toggle", "--start-if-stopped"],
+            &socket_path,
+        )
+        .await;
+        result.unwrap();
+        let received = daemon.finish().await;
+
+        assert_eq!(received, [r#"{"command":"status"}"#]);
+        assert_eq!(out, "No change: daemon is error\n");
+    }
+
+    #[tokio::test]
+    async fn test_toggle_query() {
+        let cases: [(&str, &[&str], &str); 3] = [
+            (
+                "idle",
+                &[],
+                r#"{"command":"start","output_modes":["keyboard"]}"#,
+            ),
+            (
+                "idle",
+                &["--output", "clipboard"],
+                r#"{"command":"start","output_modes":["clipboard"]}"#,
+            ),
+            ("listening", &[], r#"{"command":"stop"}"#),
+        ];
+        for (state, extra_args, expected) in cases {
+            let dir = tempfile::tempdir().unwrap();
+            let socket_path = dir.path().join("test.sock");
+            let status = status_is(state);
+            let daemon = MockDaemon::scripted(&socket_path, 1, move |command| match command {
+                DaemonCommand::Status => vec![status.clone()],
+                _ => vec![ACK.to_string()],
+            })
+            .unwrap();
+
+            let mut args = vec!["handsfreectl", "toggle", "--query"];
+            args.extend(extra_args);
+            let (result, out, _) = run_args(&args, &socket_path).await;
+            result.unwrap();
+            let received = daemon.finish().await;
+
+            assert_eq!(received, [r#"{"command":"status"}"#, expected], "{state}");
+            assert_eq!(out, "OK\n");
+        }
+    }
+
+    #[test]
+    fn test_debounced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = debounce_stamp_path(&dir.path().join("daemon.sock")).unwrap();
+        assert_eq!(path, dir.path().join("daemon.sock.toggle"));
+        assert!(debounce_stamp_path(Path::new("@handsfree")).is_err());
+
+        let window = Duration::from_millis(500);
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut stamp = DebounceStamp::lock(&path).unwrap().unwrap();
+        // Another toggle doesn't wait for this one
+        assert!(DebounceStamp::lock(&path).unwrap().is_none());
+        assert_eq!(stamp.debounced(window, start), None);
+        stamp.record(start).unwrap();
+        // Within the window
+        let at = |ms| start + Duration::from_millis(ms);
+        assert_eq!(
+            stamp.debounced(window, at(200)),
+            Some(Duration::from_millis(200))
+        );
+        assert_eq!(
+            stamp.debounced(window, at(499)),
+            Some(Duration::from_millis(499))
+        );
+        // Outside it
+        assert_eq!(stamp.debounced(window, at(500)), None);
+        stamp.record(at(500)).unwrap();
+        assert!(stamp.debounced(window, at(900)).is_some());
+        // Clock went backwards
+        assert_eq!(stamp.debounced(window, start), None);
+        drop(stamp);
+        assert!(DebounceStamp::lock(&path).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_debounce_stamp_refuses_shared_places() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Not in a directory other users can write to, like /tmp
+        let dir = tempfile::tempdir().unwrap();
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o1777)).unwrap();
+        let err = debounce_stamp_path(&dir.path().join("daemon.sock")).unwrap_err();
+        assert!(err.to_string().contains("writable by other users"), "{err}");
+        assert!(debounce_stamp_path(&dir.path().join("missing").join("daemon.sock")).is_err());
+
+        // Nor through a symlink planted where the stamp goes
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("precious");
+        fs::write(&target, "keep me").unwrap();
+        let path = dir.path().join("daemon.sock.toggle");
+        std::os::unix::fs::symlink(&target, &path).unwrap();
+        assert!(DebounceStamp::lock(&path).is_err());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "keep me");
+
+        // A new stamp is private
+        fs::remove_file(&path).unwrap();
+        DebounceStamp::lock(&path).unwrap().unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[tokio::test]
+    async fn test_toggle_debounce() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let daemon = MockDaemon::scripted(&socket_path, 2, move |_| {
+            if calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                vec![r#"{"response_type":"error","message":"Busy"}"#.to_string()]
+            } else {
+                vec![ACK.to_string()]
+            }
+        })
+        .unwrap();
+
+        // A toggle the daemon refused doesn't start the window
+        let args = ["handsfreectl", "toggle", "--debounce", "60000"];
+        let (result, _, _) = run_args(&args, &socket_path).await;
+        assert!(result.is_err());
+        let (result, out, _) = run_args(&args, &socket_path).await;
+        result.unwrap();
+        assert_eq!(out, "OK\n");
+        let (result, out, _) = run_args(&args, &socket_path).await;
+        result.unwrap();
+        assert!(out.starts_with("Debounced: last toggle was "), "{out}");
+
+        // The third toggle never reached the daemon
+        assert_eq!(daemon.finish().await, [r#"{"command":"toggle"}"#; 2]);
+    }
+
+    #[tokio::test]
+    async fn test_toggle_query_error_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |command| match command {
+            DaemonCommand::Status => vec![status_is("error")],
+            _ => vec![ACK.to_string()],
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(&["handsfreectl", "toggle", "--query"], &socket_path).await;
+        let received = daemon.finish().await;
+
+        assert!(result.unwrap_err().to_string().contains("error state"));
+        assert_eq!(received, [r#"{"command":"status"}"#]);
+        assert_eq!(out, "");
+    }
+
+    #[tokio::test]
+    async fn test_state_alias_across_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let aliases = [
+            "--state-alias",
+            "running=listening",
+            "--state-alias",
+            "Stopped=idle",
+        ];
+
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| vec![status_is("running")]).unwrap();
+        let args = [&["handsfreectl", "status"][..], &aliases].concat();
+        let (result, out, _) = run_args(&args, &socket_path).await;
+        daemon.finish().await;
+        result.unwrap();
+        assert_eq!(out, "listening\n");
+
+        let socket_path = dir.path().join("watch.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| {
+            vec![state_change("stopped"), state_change("running")]
+        })
+        .unwrap();
+        let args = [
+            &["handsfreectl", "watch", "--no-initial", "--compact"][..],
+            &aliases,
+        ]
+        .concat();
+        let (result, out, _) = run_args(&args, &socket_path).await;
+        daemon.finish().await;
+        result.unwrap();
+        assert_eq!(out, "IL");
+
+        // Filtering matches the aliased state, not the reported one
+        let socket_path = dir.path().join("filter.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| {
+            vec![state_change("stopped"), state_change("running")]
+        })
+        .unwrap();
+        let args = [
+            &[
+                "handsfreectl",
+                "watch",
+                "--no-initial",
+                "--filter-state",
+                "idle",
+            ][..],
+            &aliases,
+        ]
+        .concat();
+        let (result, out, _) = run_args(&args, &socket_path).await;
+        daemon.finish().await;
+        result.unwrap();
+        assert_eq!(out, "State changed: idle\n");
+
+        // And so does the exit code
+        let socket_path = dir.path().join("exit.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| vec![status_is("stopped")]).unwrap();
+        let args = [
+            &["handsfreectl", "status", "--exit-code-only"][..],
+            &aliases,
+        ]
+        .concat();
+        let (result, out, _) = run_args(&args, &socket_path).await;
+        daemon.finish().await;
+        assert_eq!(out, "");
+        let status = Cli::try_parse_from(&args).unwrap().command;
+        assert_eq!(failure_code(&status, &result.unwrap_err()), EXIT_STATE_IDLE);
+    }
+
+    #[tokio::test]
+    async fn test_require_running_refuses_when_idle() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |command| match command {
+            DaemonCommand::Status => vec![status_is("idle")],
+            _ => vec![ACK.to_string()],
+        })
+        .unwrap();
+
+        let (result, out, _) =
+            run_args(&["handsfreectl", "stop", "--require-running"], &socket_path).await;
+        let received = daemon.finish().await;
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Daemon is not running (state: idle); command not sent"
+        );
+        assert_eq!(received, [r#"{"command":"status"}"#]);
+        assert_eq!(out, "");
+    }
+
+    #[tokio::test]
+    async fn test_require_running_ignores_state_alias() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |command| match command {
+            DaemonCommand::Status => vec![status_is("idle")],
+            _ => vec![ACK.to_string()],
+        })
+        .unwrap();
+
+        let args = [
+            "handsfreectl",
+            "--state-alias",
+            "idle=off",
+            "stop",
+            "--require-running",
+        ];
+        let (result, _, _) = run_args(&args, &socket_path).await;
+        let received = daemon.finish().await;
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Daemon is not running (state: off); command not sent"
+        );
+        assert_eq!(received, [r#"{"command":"status"}"#]);
+    }
+
+    #[tokio::test]
+    async fn test_require_running_proceeds_when_running() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |command| match command {
+            DaemonCommand::Status => vec![status_is("listening")],
+            _ => vec![ACK.to_string()],
+        })
+        .unwrap();
+
+        let (result, out, _) =
+            run_args(&["handsfreectl", "stop", "--require-running"], &socket_path).await;
+        result.unwrap();
+        let received = daemon.finish().await;
+
+        assert_eq!(
+            received,
+            [r#"{"command":"status"}"#, r#"{"command":"stop"}"#]
+        );
+        assert_eq!(out, "OK\n");
+    }
+
+    #[test]
+    fn test_read_text() {
+        let mut stdin = io::Cursor::new("unused");
+        assert_eq!(
+            read_text("Best regards", &mut stdin).unwrap(),
+            "Best regards"
+        );
+
+        let mut stdin = io::Cursor::new("Line one\nLine two\n");
+        assert_eq!(read_text("-", &mut stdin).unwrap(), "Line one\nLine two");
+
+        let mut stdin = io::Cursor::new("Windows\r\n");
+        assert_eq!(read_text("-", &mut stdin).unwrap(), "Windows");
+    }
+
+    #[tokio::test]
+    async fn test_inject() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| vec![ACK.to_string()]).unwrap();
+
+        let (result, out, _) =
+            run_args(&["handsfreectl", "inject", "Best regards"], &socket_path).await;
+        result.unwrap();
+        let received = daemon.finish().await;
+
+        assert_eq!(
+            received,
+            [r#"{"command":"injecttext","text":"Best regards"}"#]
+        );
+        assert_eq!(out, "OK\n");
+    }
+
+    #[tokio::test]
+    async fn test_send_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| vec![ACK.to_string()]).unwrap();
+
+        let (result, out, _) = run_args(
+            &[
+                "handsfreectl",
+                "send-text",
+                "Dear \"Sir\",\nThanks",
+                "--output",
+                "clipboard",
+            ],
+            &socket_path,
+        )
+        .await;
+        result.unwrap();
+        let received = daemon.finish().await;
+
+        assert_eq!(
+            received,
+            [r#"{"command":"sendtext","text":"Dear \"Sir\",\nThanks","output_mode":"clipboard"}"#]
+        );
+        assert_eq!(out, "OK\n");
+    }
+
+    #[tokio::test]
+    async fn test_start_progress() {
+        let replies = || {
+            vec![
+                r#"{"response_type":"progress","stage":"Loading model","percent":40}"#.to_string(),
+                r#"{"response_type":"progress","stage":"Warming up"}"#.to_string(),
+                ACK.to_string(),
+            ]
+        };
+        let cases: [(&[&str], &str); 3] = [
+            (&[], "Loading model... 40%\nWarming up...\nOK\n"),
+            (
+                &["--json"],
+                concat!(
+                    r#"{"event":"progress","percent":40,"stage":"Loading model"}"#,
+                    "\n",
+                    r#"{"event":"progress","percent":null,"stage":"Warming up"}"#,
+                    "\n",
+                    r#"{"ok":true}"#,
+                    "\n"
+                ),
+            ),
+            (&["--quiet"], ""),
+        ];
+        for (flags, expected) in cases {
+            let dir = tempfile::tempdir().unwrap();
+            let socket_path = dir.path().join("test.sock");
+            let daemon = MockDaemon::scripted(&socket_path, 1, move |_| replies()).unwrap();
+
+            let args: Vec<&str> = ["handsfreectl"]
+                .into_iter()
+                .chain(flags.iter().copied())
+                .chain(["start"])
+                .collect();
+            let (result, out, _) = run_args(&args, &socket_path).await;
+            result.unwrap();
+            daemon.finish().await;
+
+            assert_eq!(out, expected, "{flags:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 2, |command| match command {
+            DaemonCommand::Status => vec![status_is("idle")],
+            DaemonCommand::Stop => {
+                vec![r#"{"response_type":"error","message":"Not running"}"#.to_string()]
+            }
+            _ => vec![ACK.to_string()],
+        })
+        .unwrap();
+        let input = concat!(
+            r#"{"command":"status"}"#,
+            "\n\n",
+            r#"{"command":"stop"}"#,
+            "\n",
+            r#"{"command":"start","output_modes":["clipboard"]}"#,
+            "\n",
+        );
+        let cli = Cli::try_parse_from(["handsfreectl", "batch"]).unwrap();
+
+        // Stops at the error
+        let mut client = DaemonClient::connect(&socket_path).await.unwrap();
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let result = run_batch(
+            &cli,
+            &mut client,
+            &mut input.as_bytes(),
+            &mut out,
+            &mut err,
+            false,
+        )
+        .await;
+        assert_eq!(result.unwrap_err().to_string(), "Daemon Error: Not running");
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                r#"{"response_type":"status","status":{"state":"idle","last_error":null}}"#,
+                "\n",
+                r#"{"response_type":"error","message":"Not running"}"#,
+                "\n",
+            )
+        );
+        drop(client);
+
+        // Runs the rest, then still reports the error
+        let mut client = DaemonClient::connect(&socket_path).await.unwrap();
+        let mut out = Vec::new();
+        let result = run_batch(
+            &cli,
+            &mut client,
+            &mut input.as_bytes(),
+            &mut out,
+            &mut err,
+            true,
+        )
+        .await;
+        drop(client);
+        assert!(result.is_err());
+        assert!(
+            String::from_utf8(out)
+                .unwrap()
+                .ends_with("{\"response_type\":\"ack\"}\n")
+        );
+
+        let received = daemon.finish().await;
+        assert_eq!(
+            received,
+            [
+                r#"{"command":"status"}"#,
+                r#"{"command":"stop"}"#,
+                r#"{"command":"status"}"#,
+                r#"{"command":"stop"}"#,
+                r#"{"command":"start","output_modes":["clipboard"]}"#,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_invalid_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let _daemon = MockDaemon::spawn(&socket_path).unwrap();
+        let cli = Cli::try_parse_from(["handsfreectl", "batch"]).unwrap();
+
+        let mut client = DaemonClient::connect(&socket_path).await.unwrap();
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let result = run_batch(
+            &cli,
+            &mut client,
+            &mut "\n{\"command\":\"dance\"}\n".as_bytes(),
+            &mut out,
+            &mut err,
+            false,
+        )
+        .await;
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .starts_with("Invalid command on line 2")
+        );
+        assert!(out.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pause_resume() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 4, |command| match command {
+            DaemonCommand::Status => vec![status_is("paused")],
+            DaemonCommand::Subscribe => vec![state_change("paused"), state_change("listening")],
+            _ => vec![ACK.to_string()],
+        })
+        .unwrap();
+
+        for command in ["pause", "resume"] {
+            let (result, out, _) = run_args(&["handsfreectl", command], &socket_path).await;
+            result.unwrap();
+            assert_eq!(out, "OK\n");
+        }
+
+        let (result, out, _) = run_args(&["handsfreectl", "status"], &socket_path).await;
+        result.unwrap();
+        assert_eq!(out, "paused\n");
+
+        let (result, out, _) =
+            run_args(&["handsfreectl", "watch", "--no-initial"], &socket_path).await;
+        result.unwrap();
+        assert_eq!(out, "State changed: paused\nState changed: listening\n");
+
+        let received = daemon.finish().await;
+        assert_eq!(
+            received,
+            [
+                r#"{"command":"pause"}"#,
+                r#"{"command":"resume"}"#,
+                r#"{"command":"status"}"#,
+                r#"{"command":"subscribe"}"#,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_inject_when_not_running() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| {
+            vec![r#"{"response_type":"error","message":"Not running"}"#.to_string()]
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(&["handsfreectl", "inject", "hi"], &socket_path).await;
+        daemon.finish().await;
+
+        let e = result.unwrap_err();
+        assert_eq!(e.to_string(), "Daemon Error: Not running");
+        assert_eq!(error_kind(&e), "daemon");
+        assert_eq!(out, "");
+    }
+
+    #[tokio::test]
+    async fn test_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| vec![ACK.to_string()]).unwrap();
+
+        let (result, out, _) = run_args(&["handsfreectl", "restart"], &socket_path).await;
+        result.unwrap();
+        let received = daemon.finish().await;
+
+        assert_eq!(
+            received,
+            [r#"{"command":"restart","output_mode":"keyboard"}"#]
+        );
+        assert_eq!(out, "OK\n");
+    }
+
+    #[test]
+    fn test_write_completions() {
+        let mut out = Vec::new();
+        write_completions(Shell::Bash, &mut out).unwrap();
+        let script = String::from_utf8(out).unwrap();
+
+        assert!(script.contains("_handsfreectl()"));
+        for subcommand in ["start", "stop", "toggle", "status", "watch", "completions"] {
+            assert!(script.contains(subcommand), "missing {}", subcommand);
+        }
+    }
+
+    #[test]
+    fn test_resolve_socket_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let flag_path = dir.path().join("flag.sock");
+        let config_path = dir.path().join("config.sock");
+
+        let flag = flag_path.to_str().unwrap();
+        let cli = Cli::parse_from(["handsfreectl", "--socket", flag, "config", "path"]);
+        assert_eq!(
+            resolve_socket_path(&cli, Some(config_path.clone())).unwrap(),
+            (flag_path.clone(), SocketSource::Flag)
+        );
+
+        let cli = Cli::parse_from(["handsfreectl", "config", "path"]);
+        assert_eq!(
+            resolve_socket_path(&cli, Some(config_path.clone())).unwrap(),
+            (config_path.clone(), SocketSource::ConfigFile)
+        );
+
+        let mut out = Vec::new();
+        write_socket_path(&cli, &config_path, &SocketSource::ConfigFile, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{}\n(from the config file)\n", config_path.display())
+        );
+
+        let cli = Cli::parse_from(["handsfreectl", "--json", "config", "path"]);
+        let mut out = Vec::new();
+        write_socket_path(&cli, &flag_path, &SocketSource::Flag, &mut out).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(value["path"], flag);
+        assert_eq!(value["source"], "from --socket");
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 2, |_| {
+            vec![r#"{"response_type":"metrics","words_transcribed":48213,"sessions":371,"uptime_secs":86400,"last_session_words":42}"#.to_string()]
+        }).unwrap();
+
+        let (result, out, _) = run_args(&["handsfreectl", "metrics"], &socket_path).await;
+        result.unwrap();
+        assert_eq!(
+            out,
+            "words_transcribed   48213\n\
+             sessions            371\n\
+             uptime_secs         86400\n\
+             last_session_words  42\n"
+        );
+
+        let (result, out, _) = run_args(&["handsfreectl", "--json", "metrics"], &socket_path).await;
+        result.unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&out).unwrap(),
+            json!({
+                "words_transcribed": 48213,
+                "sessions": 371,
+                "uptime_secs": 86400,
+                "last_session_words": 42,
+            })
+        );
+
+        let received = daemon.finish().await;
+        assert_eq!(received, [r#"{"command":"metrics"}"#; 2]);
+    }
+
+    #[test]
+    fn test_metrics_summary() {
+        assert_eq!(
+            MetricsSummary::new(48213, 371, 86400),
+            MetricsSummary {
+                words_per_hour: 2008.88,
+                words_per_session: 129.95,
+            }
+        );
+        assert_eq!(
+            MetricsSummary::new(0, 0, 0),
+            MetricsSummary {
+                words_per_hour: 0.0,
+                words_per_session: 0.0,
+            }
+        );
+        // Counters from a daemon that has just started
+        assert_eq!(
+            MetricsSummary::new(120, 2, 0),
+            MetricsSummary {
+                words_per_hour: 0.0,
+                words_per_session: 60.0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_metrics_summary_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let _daemon = MockDaemon::spawn(&socket_path).unwrap();
+
+        let (result, out, _) =
+            run_args(&["handsfreectl", "metrics", "--summary"], &socket_path).await;
+        result.unwrap();
+        assert_eq!(
+            out,
+            "words_transcribed   0\n\
+             sessions            0\n\
+             uptime_secs         0\n\
+             last_session_words  0\n\
+             words_per_hour      0.0\n\
+             words_per_session   0.0\n"
+        );
+
+        let (result, out, _) = run_args(
+            &["handsfreectl", "--json", "metrics", "--summary"],
+            &socket_path,
+        )
+        .await;
+        result.unwrap();
+        let metrics: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(metrics["words_per_hour"], json!(0.0));
+        assert_eq!(metrics["words_per_session"], json!(0.0));
+    }
+
+    #[tokio::test]
+    async fn test_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let daemon = MockDaemon::scripted(&socket_path, 3, move |_| {
+            if calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 2 {
+                return vec![r#"{"response_type":"sessions","sessions":[]}"#.to_string()];
+            }
+            vec![r#"{"response_type":"sessions","sessions":[{"id":"a1","state":"listening","output_mode":"keyboard","started_at":"2025-01-01T12:00:00Z"},{"id":"editor-2","state":"processing","output_mode":"primary","started_at":"2025-01-01T12:05:00Z"}]}"#.to_string()]
+        }).unwrap();
+
+        let (result, out, _) = run_args(&["handsfreectl", "sessions"], &socket_path).await;
+        result.unwrap();
+        assert_eq!(
+            out,
+            "ID        STATE       OUTPUT    STARTED\n\
+             a1        listening   keyboard  2025-01-01T12:00:00Z\n\
+             editor-2  processing  primary   2025-01-01T12:05:00Z\n"
+        );
+
+        let (result, out, _) =
+            run_args(&["handsfreectl", "--json", "sessions"], &socket_path).await;
+        result.unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&out).unwrap()[1],
+            json!({
+                "id": "editor-2",
+                "state": "processing",
+                "output_mode": "primary",
+                "started_at": "2025-01-01T12:05:00Z",
+            })
+        );
+
+        let (result, out, _) = run_args(&["handsfreectl", "sessions"], &socket_path).await;
+        result.unwrap();
+        assert_eq!(out, "No active sessions\n");
+
+        let received = daemon.finish().await;
+        assert_eq!(received, [r#"{"command":"sessions"}"#; 3]);
+    }
+
+    #[tokio::test]
+    async fn test_sessions_strip_ansi() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| {
+            vec![r#"{"response_type":"sessions","sessions":[{"id":"a\u001b[2J1","state":"listening","output_mode":"\u001b[31mbraille","started_at":"2025\u0007"}]}"#.to_string()]
+        }).unwrap();
+
+        let (result, out, _) =
+            run_args(&["handsfreectl", "--strip-ansi", "sessions"], &socket_path).await;
+        daemon.finish().await;
+        result.unwrap();
+        assert_eq!(
+            out,
+            "ID  STATE      OUTPUT   STARTED\n\
+             a1  listening  braille  2025\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 2, |_| {
+            vec![
+                r#"{"response_type":"hello","protocol_version":1,"daemon_version":"0.3.1"}"#
+                    .to_string(),
+            ]
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(&["handsfreectl", "version"], &socket_path).await;
+        result.unwrap();
+        assert_eq!(
+            out,
+            format!(
+                "handsfreectl {}\nhandsfreed 0.3.1 (protocol 1)\n",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+
+        let (result, out, _) = run_args(&["handsfreectl", "--json", "version"], &socket_path).await;
+        result.unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&out).unwrap(),
+            json!({
+                "client_version": env!("CARGO_PKG_VERSION"),
+                "daemon_version": "0.3.1",
+                "protocol_version": 1,
+            })
+        );
+
+        let received = daemon.finish().await;
+        assert_eq!(
+            received[0],
+            format!(
+                r#"{{"command":"hello","client_version":"{}"}}"#,
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_version_with_old_daemon() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| {
+            vec![r#"{"response_type":"error","message":"Unknown command"}"#.to_string()]
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(&["handsfreectl", "version"], &socket_path).await;
+        result.unwrap();
+        daemon.finish().await;
+        assert_eq!(
+            out,
+            format!(
+                "handsfreectl {}\nhandsfreed version unavailable\n",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ping() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| vec![ACK.to_string()]).unwrap();
+
+        let (result, out, _) = run_args(&["handsfreectl", "ping"], &socket_path).await;
+        result.unwrap();
+        let received = daemon.finish().await;
+
+        assert_eq!(received, [r#"{"command":"ping"}"#]);
+        assert_eq!(out, "pong\n");
+    }
+
+    #[tokio::test]
+    async fn test_ping_latency() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let _daemon = MockDaemon::spawn(&socket_path).unwrap();
+
+        let (result, out, _) = run_args(&["handsfreectl", "ping", "--latency"], &socket_path).await;
+        result.unwrap();
+        let latency_ms = out.strip_suffix(" ms\n").unwrap();
+        assert!(latency_ms.parse::<f64>().unwrap() >= 0.0, "{out}");
+
+        let (result, out, _) = run_args(
+            &["handsfreectl", "--json", "ping", "--latency"],
+            &socket_path,
+        )
+        .await;
+        result.unwrap();
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert!(out["latency_ms"].as_f64().unwrap() >= 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_ping_exit_codes() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let ping = Commands::Ping { latency: false };
+
+        // Nothing listening
+        let (result, _, _) = run_args(&["handsfreectl", "ping"], &socket_path).await;
+        assert_eq!(failure_code(&ping, &result.unwrap_err()), EXIT_CONNECTION);
+
+        // Listening, but answering with an error
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| {
+            vec![r#"{"response_type":"error","message":"Model not loaded"}"#.to_string()]
+        })
+        .unwrap();
+        let (result, _, _) = run_args(&["handsfreectl", "ping"], &socket_path).await;
+        daemon.finish().await;
+        assert_eq!(failure_code(&ping, &result.unwrap_err()), EXIT_DAEMON);
+
+        // A timeout is the daemon misbehaving too
+        let err = anyhow::Error::new(io::Error::from(io::ErrorKind::TimedOut));
+        assert_eq!(failure_code(&ping, &err), EXIT_DAEMON);
+    }
+
+    #[test]
+    fn test_failure_code() {
+        let stop = Commands::Stop { wait: false };
+        let cases = [
+            (
+                anyhow::Error::new(io::Error::from(io::ErrorKind::ConnectionRefused))
+                    .context(ConnectError(PathBuf::from("/run/handsfree.sock"))),
+                EXIT_CONNECTION,
+            ),
+            (
+                anyhow::Error::new(io::Error::from(io::ErrorKind::NotFound))
+                    .context("Failed to read config file"),
+                EXIT_OTHER,
+            ),
+            (DaemonReportedError("Busy".to_string()).into(), EXIT_DAEMON),
+            (
+                serde_json::from_str::<DaemonResponse>("{")
+                    .unwrap_err()
+                    .into(),
+                EXIT_PROTOCOL,
+            ),
+            (
+                anyhow::Error::new(io::Error::from(io::ErrorKind::TimedOut))
+                    .context("Read timeout"),
+                EXIT_TIMEOUT,
+            ),
+            (
+                anyhow::Error::new(DaemonError::Timeout(Duration::from_secs(5)))
+                    .context("Communication Error"),
+                EXIT_TIMEOUT,
+            ),
+            (DaemonError::ConnectionClosed.into(), EXIT_CONNECTION),
+            (DaemonError::EmptyResponse.into(), EXIT_PROTOCOL),
+            (anyhow::anyhow!("Something else"), EXIT_OTHER),
+        ];
+        for (err, code) in cases {
+            assert_eq!(failure_code(&stop, &err), code, "{:#}", err);
+        }
+    }
+
+    #[test]
+    fn test_state_exit_code() {
+        let cases = [
+            ("listening", 0),
+            ("processing", 0),
+            ("running", 0),
+            ("idle", EXIT_STATE_IDLE),
+            ("error", EXIT_STATE_ERROR),
+        ];
+        for (state, code) in cases {
+            assert_eq!(
+                state_exit_code(&DaemonState::from(state.to_string())),
+                code,
+                "{state}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status_exit_code_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        // `stopped` is only known as idle through its alias
+        let states = ["listening", "stopped", "error"];
+        // One status per connection, in order
+        let next = std::sync::atomic::AtomicUsize::new(0);
+        let daemon = MockDaemon::scripted(&socket_path, states.len(), move |_| {
+            vec![status_is(
+                states[next.fetch_add(1, std::sync::atomic::Ordering::SeqCst)],
+            )]
+        })
+        .unwrap();
+
+        let args = [
+            "handsfreectl",
+            "--state-alias",
+            "stopped=idle",
+            "status",
+            "--exit-code-only",
+        ];
+        let status = Cli::try_parse_from(args).unwrap().command;
+        let mut codes = Vec::new();
+        for _ in states {
+            let (result, out, _) = run_args(&args, &socket_path).await;
+            assert_eq!(out, "");
+            codes.push(result.err().map(|e| failure_code(&status, &e)));
+        }
+        daemon.finish().await;
+        assert_eq!(codes, [None, Some(EXIT_STATE_IDLE), Some(EXIT_STATE_ERROR)]);
+
+        let (result, out, _) = run_args(&args, &socket_path).await;
+        assert_eq!(out, "");
+        assert!(is_daemon_down(&result.unwrap_err()));
+    }
+
+    #[tokio::test]
+    async fn test_quiet() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 3, |command| match command {
+            DaemonCommand::Status => vec![status_is("idle")],
+            DaemonCommand::Subscribe => vec![state_change("listening")],
+            _ => vec![ACK.to_string()],
+        })
+        .unwrap();
+
+        for args in [
+            &["handsfreectl", "-q", "start"][..],
+            &["handsfreectl", "--quiet", "status"],
+            &[
+                "handsfreectl",
+                "--quiet",
+                "watch",
+                "--no-initial",
+                "--summary-on-exit",
+            ],
+        ] {
+            let (result, out, _) = run_args(args, &socket_path).await;
+            result.unwrap();
+            assert_eq!(out, "", "{:?}", args);
+        }
+        daemon.finish().await;
+    }
+
+    #[tokio::test]
+    async fn test_quiet_keeps_json_and_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 2, |command| match command {
+            DaemonCommand::Stop => vec![ACK.to_string()],
+            _ => vec![r#"{"response_type":"error","message":"Not running"}"#.to_string()],
+        })
+        .unwrap();
+
+        let (result, out, _) =
+            run_args(&["handsfreectl", "-q", "--json", "stop"], &socket_path).await;
+        result.unwrap();
+        assert_eq!(out, "{\"ok\":true}\n");
+
+        let (result, out, _) = run_args(&["handsfreectl", "-q", "undo"], &socket_path).await;
+        assert_eq!(result.unwrap_err().to_string(), "Daemon Error: Not running");
+        assert_eq!(out, "");
+        daemon.finish().await;
+    }
+
+    #[tokio::test]
+    async fn test_undo() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| vec![ACK.to_string()]).unwrap();
+
+        let (result, out, _) = run_args(&["handsfreectl", "undo"], &socket_path).await;
+        result.unwrap();
+        let received = daemon.finish().await;
+
+        assert_eq!(received, [r#"{"command":"undo"}"#]);
+        assert_eq!(out, "OK\n");
+    }
+
+    #[tokio::test]
+    async fn test_undo_nothing_to_undo() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| {
+            vec![r#"{"response_type":"error","message":"Nothing to undo"}"#.to_string()]
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(&["handsfreectl", "undo"], &socket_path).await;
+        daemon.finish().await;
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Daemon Error: Nothing to undo"
+        );
+        assert_eq!(out, "");
+    }
+
+    #[tokio::test]
+    async fn test_status_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| {
+            vec![
+                r#"{"response_type":"status","status":{"state":"error","last_error":"Model failed"}}"#
+                    .to_string(),
+            ]
+        }).unwrap();
+
+        let (result, out, _) = run_args(&["handsfreectl", "status", "--json"], &socket_path).await;
+        daemon.finish().await;
+
+        result.unwrap();
+        assert_eq!(
+            out,
+            "{\"state\":\"error\",\"last_error\":\"Model failed\"}\n"
+        );
+
+        let missing = dir.path().join("missing.sock");
+        let (result, out, _) = run_args(&["handsfreectl", "status", "--json"], &missing).await;
+        result.unwrap();
+        assert_eq!(out, "{\"state\":\"Inactive\",\"last_error\":null}\n");
+    }
+
+    #[tokio::test]
+    async fn test_ack_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| vec![ACK.to_string()]).unwrap();
+
+        let (result, out, _) = run_args(&["handsfreectl", "--json", "stop"], &socket_path).await;
+        result.unwrap();
+        daemon.finish().await;
+
+        assert_eq!(out, "{\"ok\":true}\n");
+    }
+
+    #[tokio::test]
+    async fn test_watch_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| {
+            vec![
+                state_change("idle"),
+                state_change("listening"),
+                r#"{"response_type":"error","message":"Mic lost"}"#.to_string(),
+            ]
+        })
+        .unwrap();
+
+        let (result, out, _) = run_args(
+            &[
+                "handsfreectl",
+                "watch",
+                "--no-initial",
+                "--json",
+                "--pretty",
+                "--annotate-transitions",
+                "--emit-connected",
+            ],
+            &socket_path,
+        )
+        .await;
+        daemon.finish().await;
+        result.unwrap();
+
+        // Every line stands alone, even with --pretty, and is stamped with the
+        // time of the event
+        let events: Vec<serde_json::Value> = out
+            .lines()
+            .map(|line| {
+                let mut event: serde_json::Value = serde_json::from_str(line).unwrap();
+                let timestamp = event.as_object_mut().unwrap().remove("timestamp").unwrap();
+                assert!(chrono::DateTime::parse_from_rfc3339(timestamp.as_str().unwrap()).is_ok());
+                event
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [
+                json!({"event": "connected"}),
+                json!({"event": "state_change", "state": "idle", "last_error": null}),
+                json!({
+                    "event": "state_change",
+                    "state": "listening",
+                    "last_error": null,
+                    "previous_state": "idle",
+                    "duration_in_previous_secs": 0
+                }),
+                json!({"event": "error", "message": "Mic lost"}),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_no_echo_by_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("test.sock");
+        let daemon = MockDaemon::scripted(&socket_path, 1, |_| vec![ACK.to_string()]).unwrap();
+
+        let (result, out, err) = run_args(&["handsfreectl", "stop"], &socket_path).await;
+        result.unwrap();
+        daemon.finish().await;
+
+        assert!(err.is_empty());
+        assert_eq!(out, "OK\n");
+    }
+}
@@ -1,33 +1,360 @@
 use crate::cli::CliOutputMode;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+use std::ops::Deref;
+use std::path::PathBuf;
 
 /// Commands that can be sent to the daemon
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 #[serde(tag = "command", rename_all = "lowercase")]
 pub enum DaemonCommand {
-    /// Start transcription with the specified output mode
-    Start { output_mode: CliOutputMode },
+    /// Start transcription, sending the text to every one of `output_modes`
+    Start(StartCommand),
     /// Stop transcription
     Stop,
+    /// Stop listening for now, keeping the session and model loaded
+    Pause,
+    /// Continue a paused session
+    Resume,
     /// Get daemon status
     Status,
+    /// Liveness check, answered with a bare `Ack`
+    Ping,
+    /// Introduce the client, answered with `DaemonResponse::Hello`
+    Hello { client_version: String },
+    /// Get usage counters, answered with `DaemonResponse::Metrics`
+    Metrics,
+    /// List the active dictation sessions, answered with `DaemonResponse::Sessions`
+    Sessions,
     /// Tell daemon to shut down gracefully
     Shutdown,
     /// Toggle transcription state
     Toggle {
-        /// Optional output mode. If None, uses default/current.
+        /// Output modes to start with. If empty, uses default/current.
+        #[serde(
+            default,
+            alias = "output_mode",
+            deserialize_with = "one_or_many",
+            skip_serializing_if = "Vec::is_empty"
+        )]
+        output_modes: Vec<CliOutputMode>,
+        /// File to write the text to, set when `output_modes` includes `file`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        output_path: Option<PathBuf>,
+        /// Whether file output appends to `output_path` rather than truncating it
+        #[serde(default = "default_append", skip_serializing_if = "is_true")]
+        append: bool,
+        /// Output mode to switch to if the active one fails at runtime
         #[serde(skip_serializing_if = "Option::is_none")]
-        output_mode: Option<CliOutputMode>,
+        fallback_output: Option<CliOutputMode>,
     },
     /// Subscribe to state change notifications
     Subscribe,
+    /// End a subscription made with `Subscribe`. Sent best-effort when the
+    /// client stops reading events; no response is expected.
+    Unsubscribe,
+    /// Output `text` through the active output mode, as if it had been dictated
+    InjectText { text: String },
+    /// Output `text` through `output_mode`, independently of any session
+    SendText {
+        text: String,
+        output_mode: CliOutputMode,
+    },
+    /// Stop the current session, if any, and start a new one with `output_mode`.
+    /// An idle daemon just starts.
+    Restart {
+        output_mode: CliOutputMode,
+        /// File to write the text to, set when `output_mode` is `file`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        output_path: Option<PathBuf>,
+        /// Whether file output appends to `output_path` rather than truncating it
+        #[serde(default = "default_append", skip_serializing_if = "is_true")]
+        append: bool,
+    },
+    /// Retract the last emitted utterance. Best-effort: how (and whether) this
+    /// works depends on the daemon and output mode.
+    Undo,
+}
+
+/// The fields of [`DaemonCommand::Start`]. Build one with
+/// [`StartCommand::new`] and set only the fields that differ from the
+/// defaults, so adding a field doesn't touch every caller.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StartCommand {
+    #[serde(alias = "output_mode", deserialize_with = "one_or_many")]
+    pub output_modes: Vec<CliOutputMode>,
+    /// File to write the text to, set when `output_modes` includes `file`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_path: Option<PathBuf>,
+    /// Whether file output appends to `output_path` rather than truncating it
+    #[serde(default = "default_append", skip_serializing_if = "is_true")]
+    pub append: bool,
+    /// Input device id. At most one of `device` and `device_index` is set;
+    /// if neither is, the daemon uses its configured device.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// Input device index
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_index: Option<u32>,
+    /// Id of a previous session to resume instead of starting fresh
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resume_session: Option<String>,
+    /// Output mode to switch to if an output mode fails at runtime
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback_output: Option<CliOutputMode>,
+}
+
+impl StartCommand {
+    /// Starts transcribing to `output_modes`, appending to any output file,
+    /// on the daemon's configured device, in a fresh session and without a
+    /// fallback.
+    pub fn new(output_modes: Vec<CliOutputMode>) -> Self {
+        Self {
+            output_modes,
+            output_path: None,
+            append: true,
+            device: None,
+            device_index: None,
+            resume_session: None,
+            fallback_output: None,
+        }
+    }
+}
+
+/// Reads a list of output modes, or a single one as sent before multiple
+/// output modes were supported.
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<CliOutputMode>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(CliOutputMode),
+        Many(Vec<CliOutputMode>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(output_mode) => vec![output_mode],
+        OneOrMany::Many(output_modes) => output_modes,
+    })
+}
+
+/// File output appends unless told otherwise, so a forgotten flag never
+/// clobbers a file. Only `"append":false` is ever sent, and daemons ignore it
+/// for other output modes.
+fn default_append() -> bool {
+    true
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+impl DaemonCommand {
+    /// A `Start` command with the defaults of [`StartCommand::new`].
+    pub fn start(output_modes: Vec<CliOutputMode>) -> Self {
+        DaemonCommand::Start(StartCommand::new(output_modes))
+    }
+
+    /// Whether sending the command twice has the same effect as sending it once.
+    /// Only these commands are safe to resend after a dropped connection.
+    pub fn is_idempotent(&self) -> bool {
+        matches!(
+            self,
+            DaemonCommand::Status
+                | DaemonCommand::Ping
+                | DaemonCommand::Hello { .. }
+                | DaemonCommand::Metrics
+                | DaemonCommand::Sessions
+                | DaemonCommand::Stop
+                | DaemonCommand::Pause
+                | DaemonCommand::Resume
+        )
+    }
+}
+
+/// A command as written to the socket, with optional metadata alongside the
+/// command's own fields.
+#[derive(Serialize, Debug)]
+pub struct CommandEnvelope<'a> {
+    #[serde(flatten)]
+    pub command: &'a DaemonCommand,
+    /// Caller-supplied id for correlating daemon logs with other systems
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<&'a str>,
+    /// Request id, echoed in the response so it can be matched to the command
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+}
+
+/// A response as read from the socket. The daemon echoes back the
+/// metadata of the command it answers.
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct ResponseEnvelope {
+    #[serde(flatten)]
+    pub response: DaemonResponse,
+    #[serde(default)]
+    pub trace_id: Option<String>,
+    /// Id of the request this answers; unsolicited events have none
+    #[serde(default)]
+    pub id: Option<u64>,
+}
+
+/// State of the transcription daemon. On the wire this is a lowercase
+/// string; states this client doesn't recognise, e.g. from a newer daemon,
+/// are kept verbatim as `Unknown` instead of failing to parse.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(from = "String", into = "String")]
+pub enum DaemonState {
+    Idle,
+    Starting,
+    Listening,
+    Processing,
+    Running,
+    Paused,
+    Stopping,
+    Error,
+    Unknown(String),
+}
+
+impl DaemonState {
+    pub fn as_str(&self) -> &str {
+        match self {
+            DaemonState::Idle => "idle",
+            DaemonState::Starting => "starting",
+            DaemonState::Listening => "listening",
+            DaemonState::Processing => "processing",
+            DaemonState::Running => "running",
+            DaemonState::Paused => "paused",
+            DaemonState::Stopping => "stopping",
+            DaemonState::Error => "error",
+            DaemonState::Unknown(state) => state,
+        }
+    }
+
+    /// Whether a transcription session is active. A paused session still is,
+    /// and unknown states count as active, since the daemon is doing something
+    /// this client can't name.
+    pub fn is_active(&self) -> bool {
+        !matches!(self, DaemonState::Idle | DaemonState::Error)
+    }
+}
+
+impl From<String> for DaemonState {
+    fn from(state: String) -> Self {
+        match state.to_ascii_lowercase().as_str() {
+            "idle" => DaemonState::Idle,
+            "starting" => DaemonState::Starting,
+            "listening" => DaemonState::Listening,
+            "processing" => DaemonState::Processing,
+            "running" => DaemonState::Running,
+            "paused" => DaemonState::Paused,
+            "stopping" => DaemonState::Stopping,
+            "error" => DaemonState::Error,
+            _ => DaemonState::Unknown(state),
+        }
+    }
+}
+
+impl From<DaemonState> for String {
+    fn from(state: DaemonState) -> Self {
+        match state {
+            DaemonState::Unknown(state) => state,
+            known => known.as_str().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for DaemonState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A state as the daemon reported it. It derefs to the [`DaemonState`] it
+/// means, recognised regardless of case, but displays and serializes with
+/// the daemon's own spelling, e.g. `Idle` rather than `idle`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(from = "String", into = "String")]
+pub struct ReportedState {
+    state: DaemonState,
+    name: String,
+}
+
+impl ReportedState {
+    /// The state as the daemon spelled it.
+    pub fn as_str(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Deref for ReportedState {
+    type Target = DaemonState;
+
+    fn deref(&self) -> &DaemonState {
+        &self.state
+    }
+}
+
+impl PartialEq<DaemonState> for ReportedState {
+    fn eq(&self, other: &DaemonState) -> bool {
+        self.state == *other
+    }
+}
+
+impl From<String> for ReportedState {
+    fn from(name: String) -> Self {
+        Self {
+            state: name.clone().into(),
+            name,
+        }
+    }
+}
+
+impl From<DaemonState> for ReportedState {
+    fn from(state: DaemonState) -> Self {
+        Self {
+            name: state.as_str().to_string(),
+            state,
+        }
+    }
+}
+
+impl From<ReportedState> for String {
+    fn from(state: ReportedState) -> Self {
+        state.name
+    }
+}
+
+impl fmt::Display for ReportedState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
 }
 
 /// Status information returned by the daemon
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct DaemonStatus {
-    pub state: String,
+    pub state: ReportedState,
     pub last_error: Option<String>,
+    /// Seconds of inactivity after which the daemon stops on its own, if it does.
+    /// Older daemons don't report it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_secs: Option<u64>,
+    /// When the current session started (RFC 3339). Older daemons don't report it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub running_since: Option<String>,
+}
+
+/// One dictation session, for daemons that run several at once (e.g. one
+/// per window). Fields this client doesn't know are ignored.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct SessionInfo {
+    pub id: String,
+    pub state: ReportedState,
+    /// Where the text goes, e.g. `keyboard`. Kept as the daemon named it, so
+    /// modes this client doesn't know are still listed.
+    pub output_mode: String,
+    /// When the session started (RFC 3339)
+    pub started_at: String,
 }
 
 /// All possible responses from the daemon
@@ -43,6 +370,38 @@ pub enum DaemonResponse {
     Error { message: String },
     /// Notification broadcast when daemon state changes
     StateChange { status: DaemonStatus },
+    /// Answer to `DaemonCommand::Hello`
+    Hello {
+        /// Version of the socket protocol the daemon speaks
+        protocol_version: u32,
+        daemon_version: String,
+        /// Id of this run of the daemon, new each time it starts. Older
+        /// daemons don't send one.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        instance_id: Option<String>,
+    },
+    /// Answer to `DaemonCommand::Metrics`. Counters this client doesn't know
+    /// are ignored.
+    Metrics {
+        words_transcribed: u64,
+        sessions: u64,
+        uptime_secs: u64,
+        last_session_words: u64,
+    },
+    /// Answer to `DaemonCommand::Sessions`
+    Sessions {
+        #[serde(default)]
+        sessions: Vec<SessionInfo>,
+    },
+    /// Sent ahead of the answer to a slow command, such as `Start` while the
+    /// model loads. Any number may precede the final `Ack` or `Error`.
+    Progress {
+        /// What the daemon is doing, e.g. "Loading model"
+        stage: String,
+        /// How far along it is, if the daemon knows
+        #[serde(default)]
+        percent: Option<u8>,
+    },
 }
 
 #[cfg(test)]
@@ -51,33 +410,357 @@ mod tests {
 
     #[test]
     fn test_daemon_command_serialization() {
-        let start_cmd = DaemonCommand::Start {
-            output_mode: CliOutputMode::Clipboard,
-        };
+        let start_cmd = DaemonCommand::start(vec![CliOutputMode::Clipboard]);
         let json = serde_json::to_string(&start_cmd).unwrap();
-        assert_eq!(json, r#"{"command":"start","output_mode":"clipboard"}"#);
+        assert_eq!(json, r#"{"command":"start","output_modes":["clipboard"]}"#);
+
+        let start_device_cmd = DaemonCommand::Start(StartCommand {
+            device: Some("usb-mic".to_string()),
+            ..StartCommand::new(vec![CliOutputMode::Keyboard])
+        });
+        let json = serde_json::to_string(&start_device_cmd).unwrap();
+        assert_eq!(
+            json,
+            r#"{"command":"start","output_modes":["keyboard"],"device":"usb-mic"}"#
+        );
+
+        let start_index_cmd = DaemonCommand::Start(StartCommand {
+            device_index: Some(2),
+            ..StartCommand::new(vec![CliOutputMode::Keyboard])
+        });
+        let json = serde_json::to_string(&start_index_cmd).unwrap();
+        assert_eq!(
+            json,
+            r#"{"command":"start","output_modes":["keyboard"],"device_index":2}"#
+        );
+
+        let resume_cmd = DaemonCommand::Start(StartCommand {
+            resume_session: Some("abc123".to_string()),
+            ..StartCommand::new(vec![CliOutputMode::Keyboard])
+        });
+        let json = serde_json::to_string(&resume_cmd).unwrap();
+        assert_eq!(
+            json,
+            r#"{"command":"start","output_modes":["keyboard"],"resume_session":"abc123"}"#
+        );
+
+        let fallback_cmd = DaemonCommand::Start(StartCommand {
+            fallback_output: Some(CliOutputMode::Keyboard),
+            ..StartCommand::new(vec![CliOutputMode::Clipboard])
+        });
+        let json = serde_json::to_string(&fallback_cmd).unwrap();
+        assert_eq!(
+            json,
+            r#"{"command":"start","output_modes":["clipboard"],"fallback_output":"keyboard"}"#
+        );
+
+        let toggle_fallback_cmd = DaemonCommand::Toggle {
+            output_modes: vec![],
+            output_path: None,
+            append: true,
+            fallback_output: Some(CliOutputMode::Keyboard),
+        };
+        let json = serde_json::to_string(&toggle_fallback_cmd).unwrap();
+        assert_eq!(json, r#"{"command":"toggle","fallback_output":"keyboard"}"#);
 
         let stop_cmd = DaemonCommand::Stop;
         let json = serde_json::to_string(&stop_cmd).unwrap();
         assert_eq!(json, r#"{"command":"stop"}"#);
 
+        let pause_cmd = DaemonCommand::Pause;
+        let json = serde_json::to_string(&pause_cmd).unwrap();
+        assert_eq!(json, r#"{"command":"pause"}"#);
+        assert_eq!(
+            serde_json::from_str::<DaemonCommand>(&json).unwrap(),
+            pause_cmd
+        );
+
+        let resume_cmd = DaemonCommand::Resume;
+        let json = serde_json::to_string(&resume_cmd).unwrap();
+        assert_eq!(json, r#"{"command":"resume"}"#);
+        assert_eq!(
+            serde_json::from_str::<DaemonCommand>(&json).unwrap(),
+            resume_cmd
+        );
+
         let status_cmd = DaemonCommand::Status;
         let json = serde_json::to_string(&status_cmd).unwrap();
         assert_eq!(json, r#"{"command":"status"}"#);
 
+        let ping_cmd = DaemonCommand::Ping;
+        let json = serde_json::to_string(&ping_cmd).unwrap();
+        assert_eq!(json, r#"{"command":"ping"}"#);
+
+        let hello_cmd = DaemonCommand::Hello {
+            client_version: "0.2.0".to_string(),
+        };
+        let json = serde_json::to_string(&hello_cmd).unwrap();
+        assert_eq!(json, r#"{"command":"hello","client_version":"0.2.0"}"#);
+        assert_eq!(
+            serde_json::from_str::<DaemonCommand>(&json).unwrap(),
+            hello_cmd
+        );
+
+        let metrics_cmd = DaemonCommand::Metrics;
+        let json = serde_json::to_string(&metrics_cmd).unwrap();
+        assert_eq!(json, r#"{"command":"metrics"}"#);
+
+        let json = serde_json::to_string(&DaemonCommand::Sessions).unwrap();
+        assert_eq!(json, r#"{"command":"sessions"}"#);
+
         let shutdown_cmd = DaemonCommand::Shutdown;
         let json = serde_json::to_string(&shutdown_cmd).unwrap();
         assert_eq!(json, r#"{"command":"shutdown"}"#);
 
         let toggle_cmd = DaemonCommand::Toggle {
-            output_mode: Some(CliOutputMode::Keyboard),
+            output_modes: vec![CliOutputMode::Keyboard],
+            output_path: None,
+            append: true,
+            fallback_output: None,
         };
         let json = serde_json::to_string(&toggle_cmd).unwrap();
-        assert_eq!(json, r#"{"command":"toggle","output_mode":"keyboard"}"#);
+        assert_eq!(json, r#"{"command":"toggle","output_modes":["keyboard"]}"#);
+
+        let toggle_primary_cmd = DaemonCommand::Toggle {
+            output_modes: vec![CliOutputMode::PrimarySelection],
+            output_path: None,
+            append: true,
+            fallback_output: None,
+        };
+        let json = serde_json::to_string(&toggle_primary_cmd).unwrap();
+        assert_eq!(json, r#"{"command":"toggle","output_modes":["primary"]}"#);
+        assert_eq!(
+            serde_json::from_str::<DaemonCommand>(&json).unwrap(),
+            toggle_primary_cmd
+        );
 
         let subscribe_cmd = DaemonCommand::Subscribe;
         let json = serde_json::to_string(&subscribe_cmd).unwrap();
         assert_eq!(json, r#"{"command":"subscribe"}"#);
+
+        let json = serde_json::to_string(&DaemonCommand::Unsubscribe).unwrap();
+        assert_eq!(json, r#"{"command":"unsubscribe"}"#);
+        assert_eq!(
+            serde_json::from_str::<DaemonCommand>(&json).unwrap(),
+            DaemonCommand::Unsubscribe
+        );
+
+        let inject_cmd = DaemonCommand::InjectText {
+            text: "Best regards".to_string(),
+        };
+        let json = serde_json::to_string(&inject_cmd).unwrap();
+        assert_eq!(json, r#"{"command":"injecttext","text":"Best regards"}"#);
+
+        let send_cmd = DaemonCommand::SendText {
+            text: "Best regards".to_string(),
+            output_mode: CliOutputMode::Clipboard,
+        };
+        let json = serde_json::to_string(&send_cmd).unwrap();
+        assert_eq!(
+            json,
+            r#"{"command":"sendtext","text":"Best regards","output_mode":"clipboard"}"#
+        );
+
+        let restart_cmd = DaemonCommand::Restart {
+            output_mode: CliOutputMode::Clipboard,
+            output_path: None,
+            append: true,
+        };
+        let json = serde_json::to_string(&restart_cmd).unwrap();
+        assert_eq!(json, r#"{"command":"restart","output_mode":"clipboard"}"#);
+        assert_eq!(
+            serde_json::from_str::<DaemonCommand>(&json).unwrap(),
+            restart_cmd
+        );
+
+        let undo_cmd = DaemonCommand::Undo;
+        let json = serde_json::to_string(&undo_cmd).unwrap();
+        assert_eq!(json, r#"{"command":"undo"}"#);
+    }
+
+    #[test]
+    fn test_send_text_round_trip() {
+        let texts = [
+            "Dear Sir,\nThanks for the \"quick\" reply.\r\n",
+            "tab\there, backslash \\ and a trailing newline\n",
+            "Grüße, 你好 👋",
+        ];
+        for text in texts {
+            let command = DaemonCommand::SendText {
+                text: text.to_string(),
+                output_mode: CliOutputMode::PrimarySelection,
+            };
+            let json = serde_json::to_string(&command).unwrap();
+            // The line-based protocol needs the whole command on one line
+            assert!(!json.contains('\n'), "{json}");
+            assert_eq!(
+                serde_json::from_str::<DaemonCommand>(&json).unwrap(),
+                command
+            );
+        }
+    }
+
+    #[test]
+    fn test_start_output_modes() {
+        let start = DaemonCommand::start;
+
+        let both = start(vec![CliOutputMode::Keyboard, CliOutputMode::Clipboard]);
+        let json = serde_json::to_string(&both).unwrap();
+        assert_eq!(
+            json,
+            r#"{"command":"start","output_modes":["keyboard","clipboard"]}"#
+        );
+        assert_eq!(serde_json::from_str::<DaemonCommand>(&json).unwrap(), both);
+
+        let single = r#"{"command":"start","output_modes":["primary"]}"#;
+        assert_eq!(
+            serde_json::from_str::<DaemonCommand>(single).unwrap(),
+            start(vec![CliOutputMode::PrimarySelection])
+        );
+
+        // As sent by older clients
+        let legacy = r#"{"command":"start","output_mode":"clipboard"}"#;
+        assert_eq!(
+            serde_json::from_str::<DaemonCommand>(legacy).unwrap(),
+            start(vec![CliOutputMode::Clipboard])
+        );
+    }
+
+    #[test]
+    fn test_output_path_serialization() {
+        let start_file_cmd = DaemonCommand::Start(StartCommand {
+            output_path: Some(PathBuf::from("/tmp/notes.txt")),
+            ..StartCommand::new(vec![CliOutputMode::File])
+        });
+        let json = serde_json::to_string(&start_file_cmd).unwrap();
+        assert_eq!(
+            json,
+            r#"{"command":"start","output_modes":["file"],"output_path":"/tmp/notes.txt"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<DaemonCommand>(&json).unwrap(),
+            start_file_cmd
+        );
+
+        let toggle_file_cmd = DaemonCommand::Toggle {
+            output_modes: vec![CliOutputMode::File],
+            output_path: Some(PathBuf::from("notes.txt")),
+            append: true,
+            fallback_output: None,
+        };
+        let json = serde_json::to_string(&toggle_file_cmd).unwrap();
+        assert_eq!(
+            json,
+            r#"{"command":"toggle","output_modes":["file"],"output_path":"notes.txt"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<DaemonCommand>(&json).unwrap(),
+            toggle_file_cmd
+        );
+
+        let restart_file_cmd = DaemonCommand::Restart {
+            output_mode: CliOutputMode::File,
+            output_path: Some(PathBuf::from("/tmp/notes.txt")),
+            append: false,
+        };
+        let json = serde_json::to_string(&restart_file_cmd).unwrap();
+        assert_eq!(
+            json,
+            r#"{"command":"restart","output_mode":"file","output_path":"/tmp/notes.txt","append":false}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<DaemonCommand>(&json).unwrap(),
+            restart_file_cmd
+        );
+    }
+
+    #[test]
+    fn test_append_serialization() {
+        let start_overwrite_cmd = DaemonCommand::Start(StartCommand {
+            output_path: Some(PathBuf::from("notes.txt")),
+            append: false,
+            ..StartCommand::new(vec![CliOutputMode::File])
+        });
+        let json = serde_json::to_string(&start_overwrite_cmd).unwrap();
+        assert_eq!(
+            json,
+            r#"{"command":"start","output_modes":["file"],"output_path":"notes.txt","append":false}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<DaemonCommand>(&json).unwrap(),
+            start_overwrite_cmd
+        );
+
+        // Appending is the default, so it is left out and assumed when missing
+        let toggle: DaemonCommand = serde_json::from_str(
+            r#"{"command":"toggle","output_modes":["file"],"output_path":"notes.txt"}"#,
+        )
+        .unwrap();
+        match toggle {
+            DaemonCommand::Toggle { append, .. } => assert!(append),
+            _ => panic!("Expected Toggle command"),
+        }
+    }
+
+    #[test]
+    fn test_toggle_deserialization() {
+        // Older clients send a bare toggle; the daemon picks the output mode
+        let toggle: DaemonCommand = serde_json::from_str(r#"{"command":"toggle"}"#).unwrap();
+        assert_eq!(
+            toggle,
+            DaemonCommand::Toggle {
+                output_modes: vec![],
+                output_path: None,
+                append: true,
+                fallback_output: None,
+            }
+        );
+
+        let toggle: DaemonCommand =
+            serde_json::from_str(r#"{"command":"toggle","output_mode":"clipboard"}"#).unwrap();
+        assert_eq!(
+            toggle,
+            DaemonCommand::Toggle {
+                output_modes: vec![CliOutputMode::Clipboard],
+                output_path: None,
+                append: true,
+                fallback_output: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_is_idempotent() {
+        assert!(DaemonCommand::Status.is_idempotent());
+        assert!(DaemonCommand::Stop.is_idempotent());
+        assert!(!DaemonCommand::start(vec![CliOutputMode::Keyboard]).is_idempotent());
+        assert!(
+            !DaemonCommand::Toggle {
+                output_modes: vec![],
+                output_path: None,
+                append: true,
+                fallback_output: None,
+            }
+            .is_idempotent()
+        );
+        assert!(!DaemonCommand::Shutdown.is_idempotent());
+        assert!(!DaemonCommand::Subscribe.is_idempotent());
+        assert!(!DaemonCommand::Unsubscribe.is_idempotent());
+        assert!(!DaemonCommand::Undo.is_idempotent());
+        assert!(
+            !DaemonCommand::Restart {
+                output_mode: CliOutputMode::Keyboard,
+                output_path: None,
+                append: true,
+            }
+            .is_idempotent()
+        );
+        assert!(
+            !DaemonCommand::InjectText {
+                text: "x".to_string()
+            }
+            .is_idempotent()
+        );
     }
 
     #[test]
@@ -95,8 +778,10 @@ mod tests {
             resp_status,
             DaemonResponse::Status {
                 status: DaemonStatus {
-                    state: "error".to_string(),
-                    last_error: Some("Model failed".to_string())
+                    state: DaemonState::Error.into(),
+                    last_error: Some("Model failed".to_string()),
+                    idle_timeout_secs: None,
+                    running_since: None,
                 }
             }
         );
@@ -109,8 +794,26 @@ mod tests {
             resp_status_ok,
             DaemonResponse::Status {
                 status: DaemonStatus {
-                    state: "idle".to_string(),
-                    last_error: None
+                    state: DaemonState::Idle.into(),
+                    last_error: None,
+                    idle_timeout_secs: None,
+                    running_since: None,
+                }
+            }
+        );
+
+        // Test Status (with idle timeout and session start)
+        let json_status_running = r#"{"response_type":"status","status":{"state":"listening","last_error":null,"idle_timeout_secs":300,"running_since":"2025-01-01T12:00:00Z"}}"#;
+        let resp_status_running: DaemonResponse =
+            serde_json::from_str(json_status_running).unwrap();
+        assert_eq!(
+            resp_status_running,
+            DaemonResponse::Status {
+                status: DaemonStatus {
+                    state: DaemonState::Listening.into(),
+                    last_error: None,
+                    idle_timeout_secs: Some(300),
+                    running_since: Some("2025-01-01T12:00:00Z".to_string()),
                 }
             }
         );
@@ -133,8 +836,10 @@ mod tests {
             resp_notif,
             DaemonResponse::StateChange {
                 status: DaemonStatus {
-                    state: "listening".to_string(),
-                    last_error: None
+                    state: DaemonState::Listening.into(),
+                    last_error: None,
+                    idle_timeout_secs: None,
+                    running_since: None,
                 }
             }
         );
@@ -145,8 +850,10 @@ mod tests {
         // Test Status serialization
         let resp = DaemonResponse::Status {
             status: DaemonStatus {
-                state: "listening".to_string(),
+                state: DaemonState::Listening.into(),
                 last_error: None,
+                idle_timeout_secs: None,
+                running_since: None,
             },
         };
         let json = serde_json::to_string(&resp).unwrap();
@@ -154,5 +861,242 @@ mod tests {
             json,
             r#"{"response_type":"status","status":{"state":"listening","last_error":null}}"#
         );
+
+        let resp = DaemonResponse::Hello {
+            protocol_version: 1,
+            daemon_version: "0.3.1".to_string(),
+            instance_id: None,
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert_eq!(
+            json,
+            r#"{"response_type":"hello","protocol_version":1,"daemon_version":"0.3.1"}"#
+        );
+        assert_eq!(serde_json::from_str::<DaemonResponse>(&json).unwrap(), resp);
+
+        let resp = DaemonResponse::Hello {
+            protocol_version: 1,
+            daemon_version: "0.3.1".to_string(),
+            instance_id: Some("b7e1".to_string()),
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert_eq!(
+            json,
+            r#"{"response_type":"hello","protocol_version":1,"daemon_version":"0.3.1","instance_id":"b7e1"}"#
+        );
+        assert_eq!(serde_json::from_str::<DaemonResponse>(&json).unwrap(), resp);
+
+        let resp = DaemonResponse::Progress {
+            stage: "Loading model".to_string(),
+            percent: Some(40),
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert_eq!(
+            json,
+            r#"{"response_type":"progress","stage":"Loading model","percent":40}"#
+        );
+        assert_eq!(serde_json::from_str::<DaemonResponse>(&json).unwrap(), resp);
+        assert_eq!(
+            serde_json::from_str::<DaemonResponse>(
+                r#"{"response_type":"progress","stage":"Warming up"}"#
+            )
+            .unwrap(),
+            DaemonResponse::Progress {
+                stage: "Warming up".to_string(),
+                percent: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_command_envelope_trace_id() {
+        let envelope = CommandEnvelope {
+            command: &DaemonCommand::Status,
+            trace_id: Some("abc-123"),
+            id: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&envelope).unwrap(),
+            r#"{"command":"status","trace_id":"abc-123"}"#
+        );
+
+        let envelope = CommandEnvelope {
+            command: &DaemonCommand::Toggle {
+                output_modes: vec![],
+                output_path: None,
+                append: true,
+                fallback_output: None,
+            },
+            trace_id: None,
+            id: Some(42),
+        };
+        assert_eq!(
+            serde_json::to_string(&envelope).unwrap(),
+            r#"{"command":"toggle","id":42}"#
+        );
+    }
+
+    #[test]
+    fn test_response_envelope_deserialization() {
+        let envelope: ResponseEnvelope =
+            serde_json::from_str(r#"{"response_type":"ack","trace_id":"abc-123"}"#).unwrap();
+        assert_eq!(envelope.response, DaemonResponse::Ack);
+        assert_eq!(envelope.trace_id.as_deref(), Some("abc-123"));
+        assert_eq!(envelope.id, None);
+
+        let envelope: ResponseEnvelope =
+            serde_json::from_str(r#"{"response_type":"ack","id":42}"#).unwrap();
+        assert_eq!(envelope.id, Some(42));
+
+        let envelope: ResponseEnvelope = serde_json::from_str(
+            r#"{"response_type":"status","status":{"state":"idle","last_error":null}}"#,
+        )
+        .unwrap();
+        assert_eq!(envelope.trace_id, None);
+    }
+
+    #[test]
+    fn test_daemon_state_wire_format() {
+        let status: DaemonStatus =
+            serde_json::from_str(r#"{"state":"listening","last_error":null}"#).unwrap();
+        assert_eq!(status.state, DaemonState::Listening);
+        assert_eq!(
+            serde_json::to_string(&status).unwrap(),
+            r#"{"state":"listening","last_error":null}"#
+        );
+
+        // A state from a newer daemon survives the round trip unchanged
+        let status: DaemonStatus =
+            serde_json::from_str(r#"{"state":"warming_up","last_error":null}"#).unwrap();
+        assert_eq!(status.state, DaemonState::Unknown("warming_up".to_string()));
+        assert!(status.state.is_active());
+        assert_eq!(
+            serde_json::to_string(&status).unwrap(),
+            r#"{"state":"warming_up","last_error":null}"#
+        );
+
+        let status: DaemonStatus =
+            serde_json::from_str(r#"{"state":"paused","last_error":null}"#).unwrap();
+        assert_eq!(status.state, DaemonState::Paused);
+        assert_eq!(status.state.to_string(), "paused");
+        assert!(status.state.is_active());
+        assert_eq!(
+            serde_json::to_string(&status).unwrap(),
+            r#"{"state":"paused","last_error":null}"#
+        );
+
+        assert_eq!(DaemonState::from("Idle".to_string()), DaemonState::Idle);
+        assert!(!DaemonState::Idle.is_active());
+        assert!(!DaemonState::Error.is_active());
+
+        // Known states keep the daemon's spelling for display
+        let status: DaemonStatus =
+            serde_json::from_str(r#"{"state":"Idle","last_error":null}"#).unwrap();
+        assert_eq!(status.state, DaemonState::Idle);
+        assert!(!status.state.is_active());
+        assert_eq!(status.state.to_string(), "Idle");
+        assert_eq!(
+            serde_json::to_string(&status).unwrap(),
+            r#"{"state":"Idle","last_error":null}"#
+        );
+    }
+
+    #[test]
+    fn test_reported_state_from_daemon_state() {
+        let state = ReportedState::from(DaemonState::Listening);
+        assert_eq!(state, DaemonState::Listening);
+        assert_eq!(state.as_str(), "listening");
+        assert!(!DaemonState::Error.is_active());
+    }
+
+    #[test]
+    fn test_metrics_deserialization() {
+        let expected = DaemonResponse::Metrics {
+            words_transcribed: 48213,
+            sessions: 371,
+            uptime_secs: 86400,
+            last_session_words: 42,
+        };
+        let line = r#"{"response_type":"metrics","words_transcribed":48213,"sessions":371,"uptime_secs":86400,"last_session_words":42}"#;
+        assert_eq!(
+            serde_json::from_str::<DaemonResponse>(line).unwrap(),
+            expected
+        );
+
+        // A newer daemon may report more than this client knows about
+        let line = r#"{"response_type":"metrics","words_transcribed":48213,"sessions":371,"uptime_secs":86400,"last_session_words":42,"gpu_secs":1200,"models":{"base":3}}"#;
+        assert_eq!(
+            serde_json::from_str::<DaemonResponse>(line).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_sessions_deserialization() {
+        let sessions_of = |line: &str| match serde_json::from_str::<DaemonResponse>(line) {
+            Ok(DaemonResponse::Sessions { sessions }) => sessions,
+            other => panic!("Expected sessions, got {:?}", other),
+        };
+
+        assert!(sessions_of(r#"{"response_type":"sessions","sessions":[]}"#).is_empty());
+        // Nothing to list may also leave the field out
+        assert!(sessions_of(r#"{"response_type":"sessions"}"#).is_empty());
+
+        let editor = SessionInfo {
+            id: "a1".to_string(),
+            state: DaemonState::Listening.into(),
+            output_mode: "keyboard".to_string(),
+            started_at: "2025-01-01T12:00:00Z".to_string(),
+        };
+        assert_eq!(
+            sessions_of(
+                r#"{"response_type":"sessions","sessions":[{"id":"a1","state":"listening","output_mode":"keyboard","started_at":"2025-01-01T12:00:00Z"}]}"#
+            ),
+            vec![editor.clone()]
+        );
+
+        // Extra fields, from the session or the response, are ignored
+        let sessions = sessions_of(
+            r#"{"response_type":"sessions","sessions":[{"id":"a1","state":"listening","output_mode":"keyboard","started_at":"2025-01-01T12:00:00Z","window":"Editor"},{"id":"b2","state":"processing","output_mode":"primary","started_at":"2025-01-01T12:05:00Z"}],"max_sessions":4}"#,
+        );
+        assert_eq!(
+            sessions,
+            [
+                editor,
+                SessionInfo {
+                    id: "b2".to_string(),
+                    state: DaemonState::Processing.into(),
+                    output_mode: "primary".to_string(),
+                    started_at: "2025-01-01T12:05:00Z".to_string(),
+                },
+            ]
+        );
+
+        // An output mode from a newer daemon doesn't break the listing
+        let sessions = sessions_of(
+            r#"{"response_type":"sessions","sessions":[{"id":"c3","state":"listening","output_mode":"braille","started_at":"2025-01-01T12:10:00Z"}]}"#,
+        );
+        assert_eq!(sessions[0].output_mode, "braille");
+    }
+
+    #[test]
+    fn test_state_change_deserialization() {
+        // As broadcast to subscribers when a session fails
+        let line = r#"{"response_type":"state_change","status":{"state":"error","last_error":"Audio device disconnected"}}"#;
+        let envelope: ResponseEnvelope = serde_json::from_str(line).unwrap();
+        assert_eq!(
+            envelope.response,
+            DaemonResponse::StateChange {
+                status: DaemonStatus {
+                    state: DaemonState::Error.into(),
+                    last_error: Some("Audio device disconnected".to_string()),
+                    idle_timeout_secs: None,
+                    running_since: None,
+                }
+            }
+        );
+
+        let subscribe: DaemonCommand = serde_json::from_str(r#"{"command":"subscribe"}"#).unwrap();
+        assert_eq!(subscribe, DaemonCommand::Subscribe);
     }
 }
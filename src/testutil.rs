@@ -0,0 +1,363 @@
+//! In-process mock of the `handsfreed` socket protocol, for testing the client
+//! without a real daemon.
+
+use crate::capture::{CaptureEntry, Direction};
+use crate::protocol::{DaemonCommand, DaemonResponse, DaemonState, DaemonStatus};
+use log::{debug, warn};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::task::JoinHandle;
+
+/// A mock daemon listening on a Unix socket.
+/// It keeps a single `idle`/`listening`/`paused` state that start, stop,
+/// toggle, pause and resume act on, only accepts injected text while listening, and answers
+/// subscriptions with the current status and then hangs up. Request ids
+/// are echoed back like a current daemon does.
+/// Every command line received is kept, see [`MockDaemon::received`].
+/// The listener is stopped and the socket removed when dropped.
+pub struct MockDaemon {
+    socket_path: PathBuf,
+    handle: JoinHandle<()>,
+    received: Received,
+}
+
+type Received = Arc<Mutex<Vec<String>>>;
+
+impl MockDaemon {
+    /// Binds the mock daemon to `socket_path` and starts serving clients.
+    pub fn spawn(socket_path: &Path) -> io::Result<Self> {
+        let listener = UnixListener::bind(socket_path)?;
+        let state = Arc::new(Mutex::new(DaemonState::Idle));
+        let received = Received::default();
+
+        let handle = tokio::spawn({
+            let received = Arc::clone(&received);
+            async move {
+                while let Ok((socket, _)) = listener.accept().await {
+                    tokio::spawn(serve(socket, Arc::clone(&state), Arc::clone(&received)));
+                }
+            }
+        });
+
+        Ok(Self {
+            socket_path: socket_path.to_path_buf(),
+            handle,
+            received,
+        })
+    }
+
+    /// Binds a mock daemon that answers each command with the lines
+    /// `handler` returns for it, written as they are, instead of acting on
+    /// it. It serves `connections` clients one after the other, hanging up
+    /// once a subscribe has been answered, and then stops listening.
+    pub fn scripted<F>(socket_path: &Path, connections: usize, handler: F) -> io::Result<Self>
+    where
+        F: Fn(&DaemonCommand) -> Vec<String> + Send + Sync + 'static,
+    {
+        let listener = UnixListener::bind(socket_path)?;
+        let received = Received::default();
+
+        let handle = tokio::spawn({
+            let received = Arc::clone(&received);
+            async move {
+                for _ in 0..connections {
+                    let Ok((socket, _)) = listener.accept().await else {
+                        break;
+                    };
+                    serve_scripted(socket, &handler, &received).await;
+                }
+            }
+        });
+
+        Ok(Self {
+            socket_path: socket_path.to_path_buf(),
+            handle,
+            received,
+        })
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    /// The command lines received so far, in order and without their
+    /// request ids, since those depend on what else ran in the process.
+    pub fn received(&self) -> Vec<String> {
+        self.received.lock().unwrap().clone()
+    }
+
+    /// Waits for a [`scripted`](Self::scripted) daemon to have served all
+    /// of its clients, then stops it and returns what it received.
+    pub async fn finish(mut self) -> Vec<String> {
+        let _ = (&mut self.handle).await;
+        self.received()
+    }
+}
+
+impl Drop for MockDaemon {
+    fn drop(&mut self) {
+        self.handle.abort();
+        let _ = fs::remove_file(&self.socket_path);
+    }
+}
+
+fn status_of(state: &Mutex<DaemonState>) -> DaemonStatus {
+    DaemonStatus {
+        state: state.lock().unwrap().clone().into(),
+        last_error: None,
+        idle_timeout_secs: None,
+        running_since: None,
+    }
+}
+
+async fn serve(socket: UnixStream, state: Arc<Mutex<DaemonState>>, received: Received) {
+    let (reader, mut writer) = socket.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        debug!("Mock daemon received: {}", line);
+        received.lock().unwrap().push(without_request_id(&line));
+        let (response, hang_up) = match serde_json::from_str::<DaemonCommand>(&line) {
+            Ok(DaemonCommand::Status) => (
+                DaemonResponse::Status {
+                    status: status_of(&state),
+                },
+                false,
+            ),
+            Ok(DaemonCommand::Start(_)) | Ok(DaemonCommand::Restart { .. }) => {
+                *state.lock().unwrap() = DaemonState::Listening;
+                (DaemonResponse::Ack, false)
+            }
+            Ok(DaemonCommand::Stop) => {
+                *state.lock().unwrap() = DaemonState::Idle;
+                (DaemonResponse::Ack, false)
+            }
+            Ok(DaemonCommand::Pause) => {
+                let mut state = state.lock().unwrap();
+                if *state == DaemonState::Listening {
+                    *state = DaemonState::Paused;
+                }
+                (DaemonResponse::Ack, false)
+            }
+            Ok(DaemonCommand::Resume) => {
+                let mut state = state.lock().unwrap();
+                if *state == DaemonState::Paused {
+                    *state = DaemonState::Listening;
+                }
+                (DaemonResponse::Ack, false)
+            }
+            Ok(DaemonCommand::Toggle { .. }) => {
+                let mut state = state.lock().unwrap();
+                *state = if *state == DaemonState::Idle {
+                    DaemonState::Listening
+                } else {
+                    DaemonState::Idle
+                };
+                (DaemonResponse::Ack, false)
+            }
+            Ok(DaemonCommand::Ping) => (DaemonResponse::Ack, false),
+            Ok(DaemonCommand::Metrics) => (
+                DaemonResponse::Metrics {
+                    words_transcribed: 0,
+                    sessions: 0,
+                    uptime_secs: 0,
+                    last_session_words: 0,
+                },
+                false,
+            ),
+            Ok(DaemonCommand::Sessions) => (DaemonResponse::Sessions { sessions: vec![] }, false),
+            Ok(DaemonCommand::Hello { .. }) => (
+                DaemonResponse::Hello {
+                    protocol_version: 1,
+                    daemon_version: "mock".to_string(),
+                    instance_id: None,
+                },
+                false,
+            ),
+            Ok(DaemonCommand::Shutdown) => (DaemonResponse::Ack, true),
+            Ok(DaemonCommand::InjectText { .. }) if *state.lock().unwrap() == DaemonState::Idle => {
+                (
+                    DaemonResponse::Error {
+                        message: "Not running".to_string(),
+                    },
+                    false,
+                )
+            }
+            Ok(DaemonCommand::InjectText { .. })
+            | Ok(DaemonCommand::SendText { .. })
+            | Ok(DaemonCommand::Undo) => (DaemonResponse::Ack, false),
+            Ok(DaemonCommand::Subscribe) => (
+                DaemonResponse::StateChange {
+                    status: status_of(&state),
+                },
+                true,
+            ),
+            Ok(DaemonCommand::Unsubscribe) => break,
+            Err(e) => (
+                DaemonResponse::Error {
+                    message: format!("Invalid command: {}", e),
+                },
+                false,
+            ),
+        };
+
+        let Ok(mut response_json) = serde_json::to_value(&response) else {
+            break;
+        };
+        if let Some(id) = request_id(&line) {
+            response_json["id"] = id.into();
+        }
+        if writer
+            .write_all(format!("{}\n", response_json).as_bytes())
+            .await
+            .is_err()
+            || hang_up
+        {
+            break;
+        }
+    }
+}
+
+async fn serve_scripted<F>(socket: UnixStream, handler: &F, received: &Mutex<Vec<String>>)
+where
+    F: Fn(&DaemonCommand) -> Vec<String>,
+{
+    let (reader, mut writer) = socket.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        debug!("Mock daemon received: {}", line);
+        received.lock().unwrap().push(without_request_id(&line));
+        let command = serde_json::from_str::<DaemonCommand>(&line);
+        let replies = match &command {
+            Ok(command) => handler(command),
+            Err(e) => vec![
+                serde_json::json!({
+                    "response_type": "error",
+                    "message": format!("Invalid command: {}", e),
+                })
+                .to_string(),
+            ],
+        };
+        for reply in replies {
+            if writer
+                .write_all(format!("{}\n", reply).as_bytes())
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+        if matches!(command, Ok(DaemonCommand::Subscribe)) {
+            return;
+        }
+    }
+}
+
+/// A command line without its request id, which is always the last field.
+fn without_request_id(line: &str) -> String {
+    match line.rfind(r#","id":"#) {
+        Some(start) => format!("{}}}", &line[..start]),
+        None => line.to_string(),
+    }
+}
+
+/// The request id of a command line, if it has one.
+fn request_id(line: &str) -> Option<u64> {
+    serde_json::from_str::<serde_json::Value>(line)
+        .ok()?
+        .get("id")?
+        .as_u64()
+}
+
+/// A daemon that plays back a session recorded with `--capture`: the n-th
+/// client connection gets the responses recorded for the n-th connection,
+/// each written once the command before it has been received. Recorded
+/// request ids are replaced with those of the commands actually received.
+pub struct ReplayDaemon {
+    socket_path: PathBuf,
+    handle: JoinHandle<()>,
+}
+
+impl ReplayDaemon {
+    pub fn spawn(socket_path: &Path, entries: Vec<CaptureEntry>) -> io::Result<Self> {
+        let listener = UnixListener::bind(socket_path)?;
+
+        let mut connections: Vec<Vec<CaptureEntry>> = Vec::new();
+        for entry in entries {
+            match (entry.direction, connections.last_mut()) {
+                (Direction::Connect, _) => connections.push(Vec::new()),
+                (_, Some(connection)) => connection.push(entry),
+                (_, None) => warn!("Ignoring capture entry before the first connect"),
+            }
+        }
+
+        let handle = tokio::spawn(async move {
+            for connection in connections {
+                let Ok((socket, _)) = listener.accept().await else {
+                    break;
+                };
+                tokio::spawn(replay(socket, connection));
+            }
+        });
+
+        Ok(Self {
+            socket_path: socket_path.to_path_buf(),
+            handle,
+        })
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+}
+
+impl Drop for ReplayDaemon {
+    fn drop(&mut self) {
+        self.handle.abort();
+        let _ = fs::remove_file(&self.socket_path);
+    }
+}
+
+async fn replay(socket: UnixStream, entries: Vec<CaptureEntry>) {
+    let (reader, mut writer) = socket.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut live_id = None;
+
+    for entry in entries {
+        match entry.direction {
+            Direction::Sent => match lines.next_line().await {
+                Ok(Some(line)) => {
+                    if line != entry.line {
+                        debug!("Replay expected {} but received {}", entry.line, line);
+                    }
+                    live_id = request_id(&line);
+                }
+                _ => break,
+            },
+            Direction::Received => {
+                let line = match (live_id, serde_json::from_str(&entry.line)) {
+                    (Some(id), Ok(serde_json::Value::Object(mut response)))
+                        if response.contains_key("id") =>
+                    {
+                        response.insert("id".to_string(), id.into());
+                        serde_json::Value::Object(response).to_string()
+                    }
+                    _ => entry.line,
+                };
+                if writer
+                    .write_all(format!("{}\n", line).as_bytes())
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            Direction::Connect => {}
+        }
+    }
+}
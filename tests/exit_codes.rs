@@ -0,0 +1,313 @@
+//! Runs the built binary against scripted sockets and checks the exit code
+//! for each kind of failure, and for an interrupted watch, as well as
+//! output the unit tests can't capture.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+
+/// Runs handsfreectl with `args` against `socket_path`, ignoring any user
+/// config file, and returns its exit code.
+fn exit_code(socket_path: &Path, args: &[&str]) -> i32 {
+    let config_home = socket_path.parent().unwrap();
+    Command::new(env!("CARGO_BIN_EXE_handsfreectl"))
+        .env("XDG_CONFIG_HOME", config_home)
+        .arg("--socket")
+        .arg(socket_path)
+        .args(args)
+        .output()
+        .unwrap()
+        .status
+        .code()
+        .unwrap()
+}
+
+/// Accepts one client and answers its first command with `reply`, or
+/// with nothing if `reply` is `None`, keeping the connection open (and
+/// ignoring further commands) until the client hangs up.
+fn serve_once(socket_path: &Path, reply: Option<&'static str>) -> thread::JoinHandle<()> {
+    let listener = UnixListener::bind(socket_path).unwrap();
+    thread::spawn(move || {
+        let (socket, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(socket.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if let Some(reply) = reply {
+            writeln!(&socket, "{}", reply).unwrap();
+        }
+        // Wait for the client to hang up
+        while reader.read_line(&mut line).is_ok_and(|read| read > 0) {}
+    })
+}
+
+#[test]
+fn test_success() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("daemon.sock");
+    let daemon = serve_once(&socket_path, Some(r#"{"response_type":"ack"}"#));
+
+    assert_eq!(exit_code(&socket_path, &["stop"]), 0);
+    daemon.join().unwrap();
+}
+
+#[test]
+fn test_inactive_status_succeeds() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("daemon.sock");
+
+    assert_eq!(exit_code(&socket_path, &["status"]), 0);
+}
+
+#[test]
+fn test_connection_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("daemon.sock");
+
+    assert_eq!(exit_code(&socket_path, &["stop"]), 6);
+}
+
+#[test]
+fn test_local_file_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("daemon.sock");
+    let missing = dir.path().join("missing.toml");
+
+    let args = ["--config-file", missing.to_str().unwrap(), "stop"];
+    assert_eq!(exit_code(&socket_path, &args), 1);
+}
+
+#[test]
+fn test_daemon_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("daemon.sock");
+    let daemon = serve_once(
+        &socket_path,
+        Some(r#"{"response_type":"error","message":"Busy"}"#),
+    );
+
+    assert_eq!(exit_code(&socket_path, &["start"]), 3);
+    daemon.join().unwrap();
+}
+
+#[test]
+fn test_protocol_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("daemon.sock");
+    let daemon = serve_once(&socket_path, Some("not json"));
+
+    assert_eq!(exit_code(&socket_path, &["start"]), 4);
+    daemon.join().unwrap();
+}
+
+#[test]
+fn test_timeout() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("daemon.sock");
+    let daemon = serve_once(&socket_path, None);
+
+    assert_eq!(exit_code(&socket_path, &["--timeout", "1", "start"]), 5);
+    daemon.join().unwrap();
+}
+
+#[test]
+fn test_wait_for_timeout() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("daemon.sock");
+    let daemon = serve_once(
+        &socket_path,
+        Some(r#"{"response_type":"status","status":{"state":"idle","last_error":null}}"#),
+    );
+
+    assert_eq!(
+        exit_code(&socket_path, &["wait-for", "listening", "--within", "1"]),
+        5
+    );
+    daemon.join().unwrap();
+}
+
+#[test]
+fn test_watch_interrupted() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("daemon.sock");
+    let (subscribed_tx, subscribed_rx) = mpsc::channel();
+    let listener = UnixListener::bind(&socket_path).unwrap();
+    let daemon = thread::spawn(move || {
+        let (socket, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(socket.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        subscribed_tx.send(()).unwrap();
+        // Keep the stream open until the client goes away
+        let _ = reader.read_line(&mut line);
+    });
+
+    let mut watch = Command::new(env!("CARGO_BIN_EXE_handsfreectl"))
+        .env("XDG_CONFIG_HOME", dir.path())
+        .arg("--socket")
+        .arg(&socket_path)
+        .args(["watch", "--no-initial", "--emit-connected"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    subscribed_rx.recv().unwrap();
+    // Ctrl-C is handled from the moment watch reports the connection
+    let mut stdout = BufReader::new(watch.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(line, "connected\n");
+    let kill = Command::new("kill")
+        .arg("-INT")
+        .arg(watch.id().to_string())
+        .status()
+        .unwrap();
+    assert!(kill.success());
+
+    assert_eq!(watch.wait().unwrap().code(), Some(0));
+    daemon.join().unwrap();
+}
+
+#[test]
+fn test_raw() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("daemon.sock");
+    let daemon = serve_once(&socket_path, Some(r#"{"response_type":"ack"}"#));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_handsfreectl"))
+        .env("XDG_CONFIG_HOME", dir.path())
+        .env_remove("RUST_LOG")
+        .arg("--socket")
+        .arg(&socket_path)
+        .args(["--raw", "stop"])
+        .output()
+        .unwrap();
+    daemon.join().unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(r#">> {"command":"stop","id":"#), "{stderr}");
+    assert!(stderr.contains(r#"<< {"response_type":"ack"}"#), "{stderr}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "OK\n");
+}
+
+#[test]
+fn test_invalid_output_env() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_handsfreectl"))
+        .env("XDG_CONFIG_HOME", dir.path())
+        .env("HANDSFREE_OUTPUT", "speaker")
+        .arg("--socket")
+        .arg(dir.path().join("daemon.sock"))
+        .arg("start")
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("HANDSFREE_OUTPUT"));
+}
+
+#[test]
+fn test_usage_error_with_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("daemon.sock");
+    let config_file = dir.path().join("handsfreectl.toml");
+    std::fs::write(&config_file, "output = \"clipboard\"\n").unwrap();
+
+    let config = config_file.to_str().unwrap();
+    let args = [
+        "--config-file",
+        config,
+        "start",
+        "--output-fallback",
+        "clipboard",
+    ];
+    assert_eq!(exit_code(&socket_path, &args), 2);
+}
+
+#[test]
+fn test_env_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let runtime_dir = dir.path().join("runtime");
+    let env_file = dir.path().join("instance.env");
+    std::fs::write(
+        &env_file,
+        format!(
+            "# Second instance\nXDG_RUNTIME_DIR={}\n",
+            runtime_dir.display()
+        ),
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_handsfreectl"))
+        .env("XDG_CONFIG_HOME", dir.path())
+        .env("XDG_RUNTIME_DIR", "/nonexistent")
+        .arg("--env-file")
+        .arg(&env_file)
+        .args(["config", "path"])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let expected = runtime_dir.join("handsfree").join("daemon.sock");
+    assert!(stdout.contains(&expected.display().to_string()), "{stdout}");
+
+    std::fs::write(&env_file, "not an assignment\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_handsfreectl"))
+        .env("XDG_CONFIG_HOME", dir.path())
+        .arg("--env-file")
+        .arg(&env_file)
+        .args(["config", "path"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Line 1: expected KEY=VALUE"));
+}
+
+#[test]
+fn test_deadline() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("daemon.sock");
+    // Never answers, but the read timeout alone would wait much longer
+    let daemon = serve_once(&socket_path, None);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_handsfreectl"))
+        .env("XDG_CONFIG_HOME", dir.path())
+        .arg("--socket")
+        .arg(&socket_path)
+        .args(["--timeout", "30", "--deadline", "200", "stop"])
+        .output()
+        .unwrap();
+    daemon.join().unwrap();
+
+    assert_eq!(output.status.code(), Some(5));
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("Operation deadline exceeded after 200 ms")
+    );
+}
+
+#[test]
+fn test_parse_only() {
+    let dir = tempfile::tempdir().unwrap();
+    // None of the socket's directory, the config file or the env file would
+    // get far
+    let config_dir = dir.path().join("handsfree");
+    std::fs::create_dir(&config_dir).unwrap();
+    std::fs::write(config_dir.join("handsfreectl.toml"), "not toml").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_handsfreectl"))
+        .env("XDG_CONFIG_HOME", dir.path())
+        .arg("--socket")
+        .arg(dir.path().join("missing").join("daemon.sock"))
+        .arg("--env-file")
+        .arg(dir.path().join("missing.env"))
+        .args(["--parse-only", "start", "--output", "clipboard"])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Start { output: [Clipboard]"));
+}